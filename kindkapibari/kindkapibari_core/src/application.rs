@@ -0,0 +1,103 @@
+use crate::scopes::KKBScope;
+use serde::{Deserialize, Serialize};
+use std::ops::{Deref, DerefMut};
+use url::Url;
+use utoipa::Component;
+
+pub const MAX_REDIRECT_URIS: usize = 10;
+pub const MAX_APPLICATION_NAME: usize = 64;
+pub const MAX_APPLICATION_DESCRIPTION: usize = 500;
+
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RedirectUris {
+    int: Vec<String>,
+}
+
+impl Deref for RedirectUris {
+    type Target = Vec<String>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.int
+    }
+}
+
+impl DerefMut for RedirectUris {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.int
+    }
+}
+
+impl From<Vec<String>> for RedirectUris {
+    fn from(v: Vec<String>) -> Self {
+        Self { int: v }
+    }
+}
+
+impl From<RedirectUris> for Vec<String> {
+    fn from(r: RedirectUris) -> Self {
+        r.int
+    }
+}
+
+// Absolute and without a fragment (RFC 6749 3.1.2). Custom schemes are fine, that's how native
+// apps get the user back (RFC 8252).
+#[must_use]
+pub fn is_valid_redirect_uri(uri: &str) -> bool {
+    Url::parse(uri).map_or(false, |url| {
+        url.fragment().is_none() && !url.cannot_be_a_base()
+    })
+}
+
+// an application as its creator sees it, the secret hash never leaves the server
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct Application {
+    pub id: u64,
+    pub name: String,
+    pub description: Option<String>,
+    pub homepage: String,
+    pub logo: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<KKBScope>,
+    pub confidential: bool,
+    // users that currently have it authorized
+    pub authorized_users: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct ApplicationRequest {
+    pub name: String,
+    pub description: Option<String>,
+    pub homepage: String,
+    pub logo: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<KKBScope>,
+    // only confidential applications get a secret and client credentials, this can't change later
+    pub confidential: bool,
+}
+
+// anything left out stays the same
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct ApplicationUpdate {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub homepage: Option<String>,
+    pub logo: Option<String>,
+    pub redirect_uris: Option<Vec<String>>,
+    pub scopes: Option<Vec<KKBScope>>,
+}
+
+// the only time the secret is ever shown, it can't be looked up again
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct ClientSecret {
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct NewApplication {
+    pub application: Application,
+    pub secret: Option<ClientSecret>,
+}
+
+crate::impl_sea_orm!(RedirectUris);
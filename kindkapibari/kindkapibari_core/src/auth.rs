@@ -1,3 +1,4 @@
+use crate::scopes::KKBScope;
 use async_trait::async_trait;
 use axum::{headers::Cookie, TypedHeader};
 use axum_core::{
@@ -5,10 +6,11 @@ use axum_core::{
     extract::{FromRequest, RequestParts},
     response::{IntoResponse, Response},
 };
-use http::StatusCode;
-use http_body::Empty;
+use chrono::{DateTime, Utc};
+use http::{header::WWW_AUTHENTICATE, StatusCode};
+use http_body::{Empty, Full};
 use serde::de::DeserializeOwned;
-use std::borrow::Cow;
+use std::{borrow::Cow, marker::PhantomData};
 
 pub enum Located {
     Query(Cow<'static, str>),
@@ -20,32 +22,91 @@ pub enum Located {
 pub trait FromAuth: Sized {
     const LOCATION: Located;
 
-    async fn from_auth(provided: String) -> Option<Self>;
+    async fn from_auth(provided: String) -> Result<Self, AuthenticationRejection>;
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum AuthenticationRejection {
     BadQuery,
     BadHeader,
     BadCookie,
     BadAuthorization,
+    Expired,
+    Revoked,
+    // `None` is forever
+    Banned(Option<DateTime<Utc>>),
+    // empty when the endpoint is only for our own login tokens
+    InsufficientScope(Vec<KKBScope>),
 }
 
 impl IntoResponse for AuthenticationRejection {
     fn into_response(self) -> Response {
-        let statuscode = match self {
+        let (statuscode, challenge, reason) = match self {
             AuthenticationRejection::BadQuery
             | AuthenticationRejection::BadHeader
-            | AuthenticationRejection::BadCookie => StatusCode::UNPROCESSABLE_ENTITY,
-            AuthenticationRejection::BadAuthorization => StatusCode::UNAUTHORIZED,
+            | AuthenticationRejection::BadCookie => {
+                return Response::builder()
+                    .status(StatusCode::UNPROCESSABLE_ENTITY)
+                    .body(boxed(Empty::new()))
+                    .unwrap()
+            }
+            AuthenticationRejection::BadAuthorization => (
+                StatusCode::UNAUTHORIZED,
+                invalid_token("invalid"),
+                "invalid".to_string(),
+            ),
+            AuthenticationRejection::Expired => (
+                StatusCode::UNAUTHORIZED,
+                invalid_token("expired"),
+                "expired".to_string(),
+            ),
+            AuthenticationRejection::Revoked => (
+                StatusCode::UNAUTHORIZED,
+                invalid_token("revoked"),
+                "revoked".to_string(),
+            ),
+            // the token is fine, the user just isn't allowed in
+            AuthenticationRejection::Banned(until) => {
+                let reason = match until {
+                    Some(until) => format!("banned until {}", until.to_rfc3339()),
+                    None => "banned".to_string(),
+                };
+                (StatusCode::FORBIDDEN, invalid_token(&reason), reason)
+            }
+            AuthenticationRejection::InsufficientScope(needed) => {
+                let (challenge, reason) = if needed.is_empty() {
+                    (
+                        "Bearer error=\"insufficient_scope\", error_description=\"first party only\""
+                            .to_string(),
+                        "first party only".to_string(),
+                    )
+                } else {
+                    let scope = needed
+                        .iter()
+                        .map(KKBScope::scope_name)
+                        .collect::<Vec<String>>()
+                        .join(" ");
+                    (
+                        format!("Bearer error=\"insufficient_scope\", scope=\"{scope}\""),
+                        format!("needs {scope}"),
+                    )
+                };
+                (StatusCode::FORBIDDEN, challenge, reason)
+            }
         };
 
         Response::builder()
             .status(statuscode)
-            .body(boxed(Empty::new()))
+            .header(WWW_AUTHENTICATE, challenge)
+            .body(boxed(Full::from(reason)))
             .unwrap()
     }
 }
 
+fn invalid_token(description: &str) -> String {
+    format!("Bearer error=\"invalid_token\", error_description=\"{description}\"")
+}
+
 pub struct Authentication<T>(pub T)
 where
     T: DeserializeOwned + FromAuth;
@@ -64,9 +125,7 @@ where
                 let query = req.uri().query().unwrap_or_default();
                 let value = serde_urlencoded::from_str::<String>(query)
                     .map_err(|_| AuthenticationRejection::BadQuery)?;
-                T::from_auth(value)
-                    .await
-                    .ok_or(AuthenticationRejection::BadAuthorization)?
+                T::from_auth(value).await?
             }
             Located::Header(header_key) => {
                 let header_value = req
@@ -76,9 +135,7 @@ where
                     .to_str()
                     .map_err(|_| AuthenticationRejection::BadHeader)?
                     .to_string();
-                T::from_auth(header_value)
-                    .await
-                    .ok_or(AuthenticationRejection::BadAuthorization)?
+                T::from_auth(header_value).await?
             }
             Located::Cookie(cookie_key) => {
                 let cookie_value = Option::<TypedHeader<Cookie>>::from_request(req)
@@ -86,12 +143,97 @@ where
                     .map_err(|_| AuthenticationRejection::BadHeader)?
                     .and_then(|cookie| cookie.get(&cookie_key).map(ToString::to_string))
                     .ok_or(AuthenticationRejection::BadCookie)?;
-                T::from_auth(cookie_value.to_string())
-                    .await
-                    .ok_or(AuthenticationRejection::BadAuthorization)?
+                T::from_auth(cookie_value.to_string()).await?
             }
         };
 
         Ok(Authentication(data))
     }
 }
+
+// Implemented by auth types that know what their token was allowed to do.
+pub trait Scoped {
+    // `None` for our own login tokens, which can do anything
+    fn granted_scopes(&self) -> Option<&[KKBScope]>;
+}
+
+pub trait ScopeList {
+    // `None` lets in first party tokens only, nothing an app was granted is enough
+    const SCOPES: Option<&'static [KKBScope]>;
+}
+
+// `Authentication`, plus every scope in `S`. Use the markers in `require`, for example
+// `RequireScopes<UserAuthMdl, require::UserdataRead>`.
+pub struct RequireScopes<T, S>(pub T, pub PhantomData<S>)
+where
+    T: DeserializeOwned + FromAuth + Scoped,
+    S: ScopeList;
+
+#[async_trait]
+impl<T, S, B> FromRequest<B> for RequireScopes<T, S>
+where
+    T: DeserializeOwned + FromAuth + Scoped + Send,
+    S: ScopeList + Send,
+    B: Send,
+{
+    type Rejection = AuthenticationRejection;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Authentication(auth) = Authentication::<T>::from_request(req).await?;
+
+        if let Some(granted) = auth.granted_scopes() {
+            let allowed = match S::SCOPES {
+                Some(needed) => needed
+                    .iter()
+                    .all(|scope| granted.iter().any(|have| have.grants(scope))),
+                None => false,
+            };
+            if !allowed {
+                return Err(AuthenticationRejection::InsufficientScope(
+                    S::SCOPES.unwrap_or_default().to_vec(),
+                ));
+            }
+        }
+
+        Ok(RequireScopes(auth, PhantomData))
+    }
+}
+
+macro_rules! scope_lists {
+    { $( $name:ident => [ $( $scope:ident ),* ] ),* } => {
+        $(
+            pub struct $name;
+
+            impl ScopeList for $name {
+                const SCOPES: Option<&'static [KKBScope]> = Some(&[ $( KKBScope::$scope ),* ]);
+            }
+        )*
+    };
+}
+
+pub mod require {
+    use super::ScopeList;
+    use crate::scopes::KKBScope;
+
+    pub struct FirstParty;
+
+    impl ScopeList for FirstParty {
+        const SCOPES: Option<&'static [KKBScope]> = None;
+    }
+
+    scope_lists! {
+        PublicRead => [PublicRead],
+        BadgesRead => [BadgesRead],
+        EmailRead => [EmailRead],
+        ConnectionsRead => [ConnectionsRead],
+        PreferencesRead => [PreferencesRead],
+        UserdataRead => [UserdataRead],
+        ApplicationsRead => [ApplicationsRead],
+        RecordsRead => [RecordsRead],
+        OfflineRead => [OfflineRead],
+        RemindersWrite => [RemindersWrite],
+        SobersWrite => [SobersWrite],
+        PreferencesWrite => [PreferencesWrite],
+        UserdataWrite => [UserdataWrite]
+    }
+}
@@ -11,6 +11,7 @@ pub enum Badge {
     Contributor,
     Supporter,
     PengChanApproved,
+    SoberMilestone(u64), // days
 }
 
 #[derive(Clone, Ord, Debug, Hash, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
@@ -33,5 +34,11 @@ impl DerefMut for Badges {
     }
 }
 
+impl From<Vec<Badge>> for Badges {
+    fn from(v: Vec<Badge>) -> Self {
+        Self { int: v }
+    }
+}
+
 #[cfg(feature = "server")]
 crate::impl_sea_orm!(Badges, Badge);
@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// every cache entry starts with this, anything else is from before envelopes and gets evicted
+pub const CACHE_MAGIC: &[u8; 4] = b"KKBc";
+
+#[derive(Clone, Debug, Error)]
+pub enum CacheError {
+    #[error("Failed to encode {0}: {1}")]
+    Encode(&'static str, String),
+    #[error("Not a cache envelope")]
+    Unenveloped,
+    #[error("Expected a {expected}, found a {found}")]
+    WrongType {
+        expected: &'static str,
+        found: String,
+    },
+    #[error("{tag} version {found} is incompatible with version {expected}")]
+    WrongVersion {
+        tag: &'static str,
+        expected: u32,
+        found: u32,
+    },
+    #[error("Failed to decode {0}: {1}")]
+    Decode(&'static str, String),
+}
+
+impl CacheError {
+    // the entry can never be read by this build, so it should be evicted
+    #[must_use]
+    pub fn is_incompatible(&self) -> bool {
+        !matches!(self, CacheError::Encode(..))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    tag: String,
+    version: u32,
+}
+
+// Layout: CACHE_MAGIC | postcard(Header) | payload. The header never changes shape, so we can
+// always tell what an entry is and which version wrote it before touching the payload.
+// Implemented by `impl_redis!`.
+pub trait Cached: Sized {
+    const TAG: &'static str;
+    // bump this when the payload changes shape, old entries are then evicted on read
+    const VERSION: u32;
+    // older versions can still be decoded (the payload carries its own upgrades)
+    const UPGRADABLE: bool = false;
+
+    fn encode_payload(&self) -> Result<Vec<u8>, CacheError>;
+    fn decode_payload(payload: &[u8], version: u32) -> Result<Self, CacheError>;
+
+    fn to_cache(&self) -> Result<Vec<u8>, CacheError> {
+        let header = Header {
+            tag: Self::TAG.to_string(),
+            version: Self::VERSION,
+        };
+        let mut bytes = CACHE_MAGIC.to_vec();
+        bytes.extend(
+            postcard::to_allocvec(&header)
+                .map_err(|why| CacheError::Encode(Self::TAG, why.to_string()))?,
+        );
+        bytes.extend(self.encode_payload()?);
+        Ok(bytes)
+    }
+
+    fn from_cache(bytes: &[u8]) -> Result<Self, CacheError> {
+        let rest = bytes
+            .strip_prefix(CACHE_MAGIC)
+            .ok_or(CacheError::Unenveloped)?;
+        let (header, payload) = postcard::take_from_bytes::<Header>(rest)
+            .map_err(|why| CacheError::Decode(Self::TAG, why.to_string()))?;
+
+        if header.tag != Self::TAG {
+            return Err(CacheError::WrongType {
+                expected: Self::TAG,
+                found: header.tag,
+            });
+        }
+        let compatible =
+            header.version == Self::VERSION || (Self::UPGRADABLE && header.version < Self::VERSION);
+        if !compatible {
+            return Err(CacheError::WrongVersion {
+                tag: Self::TAG,
+                expected: Self::VERSION,
+                found: header.version,
+            });
+        }
+
+        Self::decode_payload(payload, header.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preferences::{Preferences, SkinTone, PREFERENCES_VERSION};
+    use serde_json::json;
+
+    // optional fields last, where postcard is touchiest
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    struct Note {
+        text: String,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        reply_to: Option<u64>,
+    }
+
+    crate::impl_redis!(Note);
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Other(u64);
+
+    crate::impl_redis!(Other);
+
+    // `Note` as a later build would write it
+    #[derive(Debug, Serialize)]
+    struct NewerNote(Note);
+
+    impl Cached for NewerNote {
+        const TAG: &'static str = Note::TAG;
+        const VERSION: u32 = Note::VERSION + 1;
+
+        fn encode_payload(&self) -> Result<Vec<u8>, CacheError> {
+            self.0.encode_payload()
+        }
+
+        fn decode_payload(_: &[u8], _: u32) -> Result<Self, CacheError> {
+            unreachable!()
+        }
+    }
+
+    fn note() -> Note {
+        Note {
+            text: "drink water".to_string(),
+            tags: vec![],
+            reply_to: None,
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        for note in [
+            note(),
+            Note {
+                tags: vec!["health".to_string()],
+                reply_to: Some(3),
+                ..note()
+            },
+        ] {
+            assert_eq!(Note::from_cache(&note.to_cache().unwrap()).unwrap(), note);
+        }
+    }
+
+    #[test]
+    fn wrong_type() {
+        let cached = Other(1).to_cache().unwrap();
+        let why = Note::from_cache(&cached).unwrap_err();
+        assert!(
+            matches!(&why, CacheError::WrongType { expected, .. } if *expected == Note::TAG),
+            "{why}"
+        );
+        assert!(why.is_incompatible());
+    }
+
+    #[test]
+    fn newer_version() {
+        let cached = NewerNote(note()).to_cache().unwrap();
+        let why = Note::from_cache(&cached).unwrap_err();
+        assert!(
+            matches!(
+                why,
+                CacheError::WrongVersion {
+                    expected: 0,
+                    found: 1,
+                    ..
+                }
+            ),
+            "{why}"
+        );
+        assert!(why.is_incompatible());
+    }
+
+    #[test]
+    fn from_before_envelopes() {
+        let cached = postcard::to_allocvec(&note()).unwrap();
+        let why = Note::from_cache(&cached).unwrap_err();
+        assert!(matches!(why, CacheError::Unenveloped), "{why}");
+        assert!(why.is_incompatible());
+    }
+
+    #[test]
+    fn versioned_entries_are_upgraded() {
+        // a version 0 entry, written before skin tones
+        let mut cached = CACHE_MAGIC.to_vec();
+        cached.extend(
+            postcard::to_allocvec(&Header {
+                tag: Preferences::TAG.to_string(),
+                version: 0,
+            })
+            .unwrap(),
+        );
+        cached.extend(
+            serde_json::to_vec(&json!({
+                "version": 0,
+                "appearance": { "font_size": "Small", "background": "Day", "light": true },
+            }))
+            .unwrap(),
+        );
+
+        let preferences = Preferences::from_cache(&cached).unwrap();
+        assert_eq!(preferences.appearance.skin_tone, SkinTone::Default);
+        assert_eq!(Preferences::VERSION, PREFERENCES_VERSION);
+        assert_eq!(
+            Preferences::from_cache(&preferences.to_cache().unwrap()).unwrap(),
+            preferences
+        );
+    }
+}
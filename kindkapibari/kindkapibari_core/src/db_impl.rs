@@ -1,41 +1,110 @@
+// Stores the type in a `cache::Cached` envelope. `impl_redis!(Foo => 2)` sets the cache version,
+// bump it whenever `Foo` changes shape. Without one it's 0. `impl_redis!(versioned Foo)` is for
+// `Versioned` types, the cache version follows the schema version and older entries are upgraded
+// instead of evicted.
 #[macro_export]
 macro_rules! impl_redis {
-    ($($to_impl:ty),+) => {
+    (versioned $($to_impl:ty),+) => {
         $(
-            impl redis::ToRedisArgs for $to_impl {
-                fn write_redis_args<W>(&self, out: &mut W)
-                where
-                    W: ?Sized + redis::RedisWrite,
-                {
-                    let data = postcard::to_allocvec(&self).unwrap_or_default();
-                    out.write_arg(&data);
+            impl $crate::cache::Cached for $to_impl {
+                const TAG: &'static str = concat!(module_path!(), "::", stringify!($to_impl));
+                const VERSION: u32 = <$to_impl as $crate::versioned::Versioned>::VERSION;
+                const UPGRADABLE: bool = true;
+
+                fn encode_payload(&self) -> Result<Vec<u8>, $crate::cache::CacheError> {
+                    let versioned = <$to_impl as $crate::versioned::Versioned>::to_versioned(self)
+                        .map_err(|why| $crate::cache::CacheError::Encode(Self::TAG, why.to_string()))?;
+                    serde_json::to_vec(&versioned).map_err(|why| $crate::cache::CacheError::Encode(Self::TAG, why.to_string()))
+                }
+
+                fn decode_payload(payload: &[u8], _: u32) -> Result<Self, $crate::cache::CacheError> {
+                    serde_json::from_slice(payload)
+                        .map_err(|why| why.to_string())
+                        .and_then(|value| <$to_impl as $crate::versioned::Versioned>::from_versioned(value).map_err(|why| why.to_string()))
+                        .map_err(|why| $crate::cache::CacheError::Decode(Self::TAG, why))
                 }
             }
 
-            impl redis::FromRedisValue for $to_impl {
-                fn from_redis_value(v: &redis::Value) -> redis::RedisResult<Self> {
-                    match v {
-                        redis::Value::Data(d) => {
-                            Ok(postcard::from_bytes(d).map_err(|_| redis::RedisError::from( (redis::ErrorKind::ResponseError, "data deserialize") ))?)
-                        }
-                        _ => {
-                            return Err(redis::RedisError::from( (redis::ErrorKind::TypeError, "data deserialize") ))
-                        }
-                    }
+            $crate::impl_redis_value!($to_impl);
+        )+
+    };
+    ($($to_impl:ty $(=> $version:literal)?),+) => {
+        $(
+            impl $crate::cache::Cached for $to_impl {
+                const TAG: &'static str = concat!(module_path!(), "::", stringify!($to_impl));
+                const VERSION: u32 = $crate::cache_version!($($version)?);
+
+                fn encode_payload(&self) -> Result<Vec<u8>, $crate::cache::CacheError> {
+                    postcard::to_allocvec(self).map_err(|why| $crate::cache::CacheError::Encode(Self::TAG, why.to_string()))
+                }
+
+                fn decode_payload(payload: &[u8], _: u32) -> Result<Self, $crate::cache::CacheError> {
+                    postcard::from_bytes(payload).map_err(|why| $crate::cache::CacheError::Decode(Self::TAG, why.to_string()))
                 }
             }
+
+            $crate::impl_redis_value!($to_impl);
         )+
     };
 }
 
+#[doc(hidden)]
+#[macro_export]
+macro_rules! cache_version {
+    () => {
+        0
+    };
+    ($version:literal) => {
+        $version
+    };
+}
+
+// Only reading goes through redis-rs. There's no `ToRedisArgs`, it can't fail, so a value that
+// didn't encode would silently be left out of the command. Encode it with `Cached::to_cache` and
+// pass the bytes instead, `insert_into_cache` does.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! impl_redis_value {
+    ($to_impl:ty) => {
+        impl redis::FromRedisValue for $to_impl {
+            fn from_redis_value(v: &redis::Value) -> redis::RedisResult<Self> {
+                match v {
+                    redis::Value::Data(d) => <$to_impl as $crate::cache::Cached>::from_cache(d)
+                        .map_err(|why| {
+                            redis::RedisError::from((
+                                redis::ErrorKind::ResponseError,
+                                "data deserialize",
+                                why.to_string(),
+                            ))
+                        }),
+                    _ => Err(redis::RedisError::from((
+                        redis::ErrorKind::TypeError,
+                        "data deserialize",
+                    ))),
+                }
+            }
+        }
+    };
+}
+
+// Stored as JSON, `impl_sea_orm!(versioned Foo)` wraps it in a `Versioned` envelope that's
+// upgraded on read.
 #[macro_export]
 macro_rules! impl_sea_orm {
-    ($($to_impl:ty),+) => {
+    (versioned $($to_impl:ty),+) => {
         $(
+            $crate::impl_sea_orm!(
+                @json $to_impl,
+                |v: &$to_impl| <$to_impl as $crate::versioned::Versioned>::to_versioned(v).map_err(|why| why.to_string()),
+                |value| <$to_impl as $crate::versioned::Versioned>::from_versioned(value).map_err(|why| why.to_string())
+            );
+        )+
+    };
+    (@json $to_impl:ty, $to_json:expr, $from_json:expr) => {
 
 impl From<$to_impl> for sea_orm::Value {
     fn from(v: $to_impl) -> Self {
-        let json = match serde_json::to_value(v).unwrap() {
+        let json = match ($to_json)(&v).unwrap() {
             serde_json::Value::Null => sea_orm::query::JsonValue::Null,
             serde_json::Value::Bool(b) => sea_orm::query::JsonValue::Bool(b),
             serde_json::Value::Number(n) => sea_orm::query::JsonValue::Number(n),
@@ -61,8 +130,8 @@ impl sea_orm::TryGetable for $to_impl {
     ) -> Result<Self, sea_orm::TryGetError> {
         let value = serde_json::to_value(sea_orm::entity::prelude::Json::try_get(res, pre, col)?)
             .map_err(|why| sea_orm::TryGetError::DbErr(sea_orm::DbErr::Json(why.to_string())))?;
-        serde_json::from_value(value)
-            .map_err(|why| sea_orm::TryGetError::DbErr(sea_orm::DbErr::Json(why.to_string())))
+        ($from_json)(value)
+            .map_err(|why| sea_orm::TryGetError::DbErr(sea_orm::DbErr::Json(why)))
     }
 }
 
@@ -77,7 +146,7 @@ impl sea_orm::sea_query::ValueType for $to_impl {
             sea_orm::query::JsonValue::Array(a) => serde_json::Value::Array(a),
             sea_orm::query::JsonValue::Object(o) => serde_json::Value::Object(o),
         };
-        serde_json::from_value(serde_value)
+        ($from_json)(serde_value)
             .map_err(|_| sea_orm::sea_query::value::ValueTypeErr)
     }
 
@@ -90,6 +159,14 @@ impl sea_orm::sea_query::ValueType for $to_impl {
     }
 }
 
+    };
+    ($($to_impl:ty),+) => {
+        $(
+            $crate::impl_sea_orm!(
+                @json $to_impl,
+                |v: &$to_impl| serde_json::to_value(v).map_err(|why| why.to_string()),
+                |value| serde_json::from_value::<$to_impl>(value).map_err(|why| why.to_string())
+            );
         )+
     };
 }
@@ -0,0 +1,141 @@
+use crate::{error::KKBCoreError, milestones::MilestoneEvent};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{Debug, Display, Formatter},
+    str::FromStr,
+};
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::Component))]
+pub enum ReminderKind {
+    OneTime,
+    Recurring,
+}
+
+impl Display for ReminderKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReminderKind::OneTime => write!(f, "onetime"),
+            ReminderKind::Recurring => write!(f, "recurring"),
+        }
+    }
+}
+
+impl FromStr for ReminderKind {
+    type Err = KKBCoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "onetime" => Ok(ReminderKind::OneTime),
+            "recurring" => Ok(ReminderKind::Recurring),
+            other => Err(KKBCoreError::Parse(other.to_string())),
+        }
+    }
+}
+
+// one firing of a reminder. (kind, reminder, fire_at) is unique.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::Component))]
+pub struct ReminderEvent {
+    pub kind: ReminderKind,
+    pub reminder: u64,
+    pub owner: u64,
+    pub name: String,
+    pub fire_at: DateTime<Utc>,
+}
+
+impl ReminderEvent {
+    #[must_use]
+    pub fn key(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            self.kind,
+            self.reminder,
+            self.fire_at.timestamp()
+        )
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::Component))]
+pub enum DeliveryState {
+    Delivered,
+    Acknowledged,
+    Snoozed { until: DateTime<Utc> },
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::Component))]
+pub struct ReminderHistoryEntry {
+    pub id: u64,
+    pub kind: ReminderKind,
+    pub reminder: u64,
+    pub name: String,
+    pub fire_at: DateTime<Utc>,
+    pub state: DeliveryState,
+    // delivered, but never acknowledged or snoozed
+    pub missed: bool,
+}
+
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::Component))]
+pub struct ReminderHistory {
+    pub history: Vec<ReminderHistoryEntry>,
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::Component))]
+pub struct SnoozeRequest {
+    pub minutes: u32,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Notification {
+    Reminder(ReminderEvent),
+    Milestone(MilestoneEvent),
+}
+
+#[async_trait]
+pub trait ReminderSink: Debug + Send + Sync {
+    async fn deliver(&self, event: &ReminderEvent) -> Result<(), KKBCoreError>;
+
+    async fn celebrate(&self, _event: &MilestoneEvent) -> Result<(), KKBCoreError> {
+        Ok(())
+    }
+}
+
+// Keeps everything in process, useful for tests and single-node setups.
+#[derive(Debug)]
+pub struct InMemorySink {
+    sender: flume::Sender<Notification>,
+}
+
+impl InMemorySink {
+    #[must_use]
+    pub fn new() -> (Self, flume::Receiver<Notification>) {
+        let (sender, receiver) = flume::unbounded();
+        (Self { sender }, receiver)
+    }
+}
+
+#[async_trait]
+impl ReminderSink for InMemorySink {
+    async fn deliver(&self, event: &ReminderEvent) -> Result<(), KKBCoreError> {
+        self.sender
+            .send_async(Notification::Reminder(event.clone()))
+            .await
+            .map_err(|why| KKBCoreError::Delivery(why.to_string()))
+    }
+
+    async fn celebrate(&self, event: &MilestoneEvent) -> Result<(), KKBCoreError> {
+        self.sender
+            .send_async(Notification::Milestone(event.clone()))
+            .await
+            .map_err(|why| KKBCoreError::Delivery(why.to_string()))
+    }
+}
+
+crate::impl_redis!(ReminderEvent, DeliveryState, Notification);
+crate::impl_sea_orm!(ReminderKind, DeliveryState);
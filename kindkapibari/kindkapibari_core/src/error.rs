@@ -4,4 +4,12 @@ use thiserror::Error;
 pub enum KKBCoreError {
     #[error("Error creating the template: {0}")]
     TemplateInit(String),
+    #[error("Error rendering the template: {0}")]
+    TemplateRender(String),
+    #[error("Failed to parse: {0}")]
+    Parse(String),
+    #[error("Failed to deliver: {0}")]
+    Delivery(String),
+    #[error("Schema version {0} is newer than the newest we know ({1})")]
+    SchemaVersion(u64, u32),
 }
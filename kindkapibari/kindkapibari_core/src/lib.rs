@@ -6,16 +6,24 @@
 #[macro_use]
 extern crate serde;
 
+#[cfg(feature = "server")]
+pub mod application;
+#[cfg(feature = "server")]
+pub mod cache;
 #[cfg(feature = "server")]
 pub mod dbarray;
 #[cfg(feature = "server")]
 pub mod dbvec;
+#[cfg(feature = "server")]
+pub mod delivery;
 pub mod error;
 pub mod gender;
 pub mod language;
 pub mod license;
 pub mod manifest;
 pub mod motd;
+#[cfg(feature = "server")]
+pub mod oauth;
 pub mod output;
 #[cfg(feature = "server")]
 pub mod permissions;
@@ -24,9 +32,12 @@ pub mod pronouns;
 #[cfg(feature = "server")]
 pub mod reseedingrng;
 pub mod responses;
+pub mod selector;
 #[cfg(feature = "server")]
 pub mod secret;
 #[cfg(feature = "server")]
+pub mod session;
+#[cfg(feature = "server")]
 pub mod snowflake;
 #[cfg(feature = "server")]
 pub mod state;
@@ -37,9 +48,11 @@ pub mod text;
 pub mod throttle;
 pub mod user_data;
 pub mod version;
+pub mod versioned;
 #[cfg(feature = "server")]
 #[macro_use]
 pub mod db_impl;
+pub mod recurrence;
 pub mod reminder;
 pub mod sober;
 #[cfg(feature = "server")]
@@ -53,6 +66,7 @@ pub mod roles;
 #[macro_use]
 pub mod route;
 pub mod map;
+pub mod milestones;
 pub mod scopes;
 
 pub use kindkapibari_proc::AttrString;
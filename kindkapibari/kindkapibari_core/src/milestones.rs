@@ -0,0 +1,186 @@
+use crate::{badges::Badge, sober::Sober};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::ops::{Deref, DerefMut};
+
+pub const BUILTIN_MILESTONES: [Milestone; 5] = [
+    Milestone::Day,
+    Milestone::Week,
+    Milestone::Month,
+    Milestone::Quarter,
+    Milestone::Year,
+];
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::Component))]
+pub enum Milestone {
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+    Custom { name: String, seconds: i64 },
+}
+
+impl Milestone {
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        match self {
+            Milestone::Day => Duration::days(1),
+            Milestone::Week => Duration::weeks(1),
+            Milestone::Month => Duration::days(30),
+            Milestone::Quarter => Duration::days(90),
+            Milestone::Year => Duration::days(365),
+            Milestone::Custom { seconds, .. } => Duration::seconds(*seconds),
+        }
+    }
+
+    // only the big ones get a badge, the rest just get a celebration
+    #[must_use]
+    #[allow(clippy::cast_sign_loss)]
+    pub fn badge(&self) -> Option<Badge> {
+        match self {
+            Milestone::Month | Milestone::Quarter | Milestone::Year => {
+                Some(Badge::SoberMilestone(self.duration().num_days() as u64))
+            }
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn verify(&self) -> bool {
+        match self {
+            Milestone::Custom { name, seconds } => {
+                name.len() <= 160 && *seconds > 0 && *seconds <= Duration::days(36500).num_seconds()
+            }
+            _ => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Milestones {
+    int: Vec<Milestone>,
+}
+
+impl Deref for Milestones {
+    type Target = Vec<Milestone>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.int
+    }
+}
+
+impl DerefMut for Milestones {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.int
+    }
+}
+
+impl From<Vec<Milestone>> for Milestones {
+    fn from(v: Vec<Milestone>) -> Self {
+        Self { int: v }
+    }
+}
+
+impl From<Milestones> for Vec<Milestone> {
+    fn from(m: Milestones) -> Self {
+        m.int
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::Component))]
+pub struct MilestoneEvent {
+    pub sober: u64,
+    pub owner: u64,
+    pub name: String,
+    pub milestone: Milestone,
+    pub streak_start: DateTime<Utc>,
+    pub reached: DateTime<Utc>,
+}
+
+// Every milestone (builtin + the sober's custom ones) the current streak has crossed that isn't in
+// `already_reached`, in the order they were crossed.
+#[must_use]
+pub fn newly_crossed(
+    sober: &Sober,
+    already_reached: &[Milestone],
+    now: DateTime<Utc>,
+) -> Vec<Milestone> {
+    let streak = now - sober.start_time;
+    let mut crossed = BUILTIN_MILESTONES
+        .iter()
+        .chain(sober.milestones.iter())
+        .filter(|milestone| milestone.verify())
+        .filter(|milestone| milestone.duration() <= streak)
+        .filter(|milestone| !already_reached.contains(milestone))
+        .cloned()
+        .collect::<Vec<Milestone>>();
+    crossed.sort_by_key(Milestone::duration);
+    crossed.dedup();
+    crossed
+}
+
+#[cfg(feature = "server")]
+crate::impl_sea_orm!(Milestone, Milestones, MilestoneEvent);
+#[cfg(feature = "server")]
+crate::impl_redis!(Milestone, MilestoneEvent);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sober(days: i64, milestones: Vec<Milestone>) -> (Sober, DateTime<Utc>) {
+        let now = Utc::now();
+        let sober = Sober {
+            start_time: now - Duration::days(days),
+            milestones,
+            ..Sober::default()
+        };
+        (sober, now)
+    }
+
+    #[test]
+    fn crosses_several_at_once_in_order() {
+        let (sober, now) = sober(31, vec![]);
+        assert_eq!(
+            newly_crossed(&sober, &[], now),
+            vec![Milestone::Day, Milestone::Week, Milestone::Month]
+        );
+    }
+
+    #[test]
+    fn reached_ones_are_left_out() {
+        let (sober, now) = sober(8, vec![]);
+        assert_eq!(
+            newly_crossed(&sober, &[Milestone::Day], now),
+            vec![Milestone::Week]
+        );
+        assert!(newly_crossed(&sober, &[Milestone::Day, Milestone::Week], now).is_empty());
+    }
+
+    #[test]
+    fn nothing_new_right_after_a_reset() {
+        let (sober, now) = sober(0, vec![]);
+        assert!(newly_crossed(&sober, &[], now).is_empty());
+    }
+
+    #[test]
+    fn custom_milestones_count_too() {
+        let three_days = Milestone::Custom {
+            name: "three days".to_string(),
+            seconds: Duration::days(3).num_seconds(),
+        };
+        let broken = Milestone::Custom {
+            name: "never".to_string(),
+            seconds: 0,
+        };
+        let (sober, now) = sober(4, vec![three_days.clone(), three_days.clone(), broken]);
+        assert_eq!(
+            newly_crossed(&sober, &[], now),
+            vec![Milestone::Day, three_days]
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{
+    scopes::{KKBScope, KKBScopes},
+    secret::base64_url,
+};
+use rand::{thread_rng, RngCore};
+use url::Url;
+use utoipa::Component;
+
+// PKCE is required for everyone, and only the SHA-256 kind
+pub const CODE_CHALLENGE_METHOD: &str = "S256";
+// how long an app has to swap an authorization code for tokens
+pub const AUTHORIZATION_CODE_SECONDS: usize = 60;
+
+// what an app sends the user to us with, from RFC 6749 4.1.1 plus PKCE
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct AuthorizeRequest {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: Option<String>,
+    // space delimited, the application's registered scopes if empty
+    #[serde(default)]
+    pub scope: String,
+    pub state: Option<String>,
+    pub code_challenge: String,
+    pub code_challenge_method: String,
+    // OpenID Connect, handed back untouched in the id_token
+    pub nonce: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct ScopeConsent {
+    pub scope: KKBScope,
+    pub name: String,
+    pub description: String,
+}
+
+impl From<KKBScope> for ScopeConsent {
+    fn from(scope: KKBScope) -> Self {
+        Self {
+            scope,
+            name: scope.scope_name(),
+            description: scope.description().to_string(),
+        }
+    }
+}
+
+// everything the consent page needs to show
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct ConsentPrompt {
+    pub application: u64,
+    pub name: String,
+    pub description: Option<String>,
+    pub homepage: String,
+    pub logo: String,
+    pub redirect_uri: String,
+    pub scopes: Vec<ScopeConsent>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct ConsentDecision {
+    pub allow: bool,
+}
+
+// where to send the user next, back to the app either way
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct AuthorizeRedirect {
+    pub redirect: String,
+}
+
+// An app the user let in and what it got. `id` is the authorization, an app authorized twice
+// shows up twice.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct AuthorizedApplication {
+    pub id: u64,
+    pub application: u64,
+    pub name: String,
+    pub description: Option<String>,
+    pub homepage: String,
+    pub logo: String,
+    pub scopes: Vec<ScopeConsent>,
+    pub created: u64,
+    pub last_used: u64,
+}
+
+// what the app gets to keep, it can only ever be less than what it has
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct ScopeDowngrade {
+    pub scopes: Vec<KKBScope>,
+}
+
+// RFC 6749 5.1
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub scope: String,
+    // only when the app asked for `openid`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+// Kept in redis between the user saying yes and the app redeeming the code. The code itself is
+// the key.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthorizationCode {
+    pub user: u64,
+    pub application: u64,
+    pub redirect_uri: String,
+    pub scopes: KKBScopes,
+    pub code_challenge: String,
+    pub openid: Option<OpenIdRequest>,
+}
+
+// the app asked for `openid`, so redeeming the code gets it an id_token too
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenIdRequest {
+    pub profile: bool,
+    pub nonce: Option<String>,
+}
+
+fn random_token() -> String {
+    let mut bytes = [0_u8; 32];
+    thread_rng().fill_bytes(&mut bytes);
+    base64_url(&bytes)
+}
+
+#[must_use]
+pub fn new_authorization_code() -> String {
+    random_token()
+}
+
+// shown to the application's creator once, we only keep the argon2 hash
+#[must_use]
+pub fn new_client_secret() -> String {
+    random_token()
+}
+
+// BASE64URL(SHA256(verifier)), so always 43 characters of the url safe alphabet
+#[must_use]
+pub fn is_valid_code_challenge(challenge: &str) -> bool {
+    challenge.len() == 43
+        && challenge
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+}
+
+// tacks the parameters onto the app's redirect URI, keeping any query it already has
+pub fn redirect_with(
+    redirect_uri: &str,
+    params: &[(&str, &str)],
+) -> Result<String, url::ParseError> {
+    let mut url = Url::parse(redirect_uri)?;
+    url.query_pairs_mut().extend_pairs(params);
+    Ok(url.to_string())
+}
+
+// 1: + openid
+crate::impl_redis!(AuthorizationCode => 1);
@@ -1,8 +1,12 @@
-pub const V1: u32 = 0;
+use crate::versioned::{Upgrade, Versioned};
+use serde_json::{json, Value};
+
+// 0: font_size, background, light, and a `version` of its own
+// 1: + appearance.skin_tone, the envelope keeps the version now
+pub const PREFERENCES_VERSION: u32 = 1;
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Preferences {
-    pub version: u32,
     // pub coconutpak_settings: CoconutPakIslandSettings,
     pub appearance: Appearance,
 }
@@ -13,6 +17,25 @@ pub struct Preferences {
 //     pub added_islands: HashMap<String, Option<String>>,
 // }
 
+impl Versioned for Preferences {
+    const VERSION: u32 = PREFERENCES_VERSION;
+    const UPGRADES: &'static [Upgrade] = &[preferences_v0_to_v1];
+}
+
+const _: () = assert!(Preferences::UPGRADES.len() == Preferences::VERSION as usize);
+
+fn preferences_v0_to_v1(mut data: Value) -> Value {
+    if let Some(fields) = data.as_object_mut() {
+        fields.remove("version");
+    }
+    if let Some(appearance) = data.get_mut("appearance").and_then(Value::as_object_mut) {
+        appearance
+            .entry("skin_tone")
+            .or_insert_with(|| json!(SkinTone::Default));
+    }
+    data
+}
+
 #[derive(Clone, Debug, Hash, PartialOrd, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FontSize {
     ExtraSmall,
@@ -29,14 +52,57 @@ pub enum BackGroundColour {
     Night,
 }
 
+// fitzpatrick scale, used to tone emojis in text
+#[derive(Copy, Clone, Debug, Hash, PartialOrd, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SkinTone {
+    Default,
+    Light,
+    MediumLight,
+    Medium,
+    MediumDark,
+    Dark,
+}
+
+impl Default for SkinTone {
+    fn default() -> Self {
+        SkinTone::Default
+    }
+}
+
 #[derive(Clone, Debug, Hash, PartialOrd, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Appearance {
     pub font_size: FontSize,
     pub background: BackGroundColour,
     pub light: bool,
+    #[serde(default)]
+    pub skin_tone: SkinTone,
 }
 
 #[cfg(feature = "server")]
-crate::impl_redis!(Preferences, Appearance, BackGroundColour, FontSize);
+crate::impl_redis!(Appearance, BackGroundColour, FontSize, SkinTone);
+#[cfg(feature = "server")]
+crate::impl_sea_orm!(Appearance, BackGroundColour, FontSize, SkinTone);
+#[cfg(feature = "server")]
+crate::impl_redis!(versioned Preferences);
 #[cfg(feature = "server")]
-crate::impl_sea_orm!(Preferences, Appearance, BackGroundColour, FontSize);
+crate::impl_sea_orm!(versioned Preferences);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrades_rows_from_before_the_envelope() {
+        let row = json!({
+            "version": 0,
+            "appearance": { "font_size": "Medium", "background": "Night", "light": false },
+        });
+        let preferences = Preferences::from_versioned(row).unwrap();
+        assert_eq!(preferences.appearance.skin_tone, SkinTone::Default);
+
+        let stored = preferences.to_versioned().unwrap();
+        assert_eq!(stored["v"], json!(PREFERENCES_VERSION));
+        assert!(stored["d"].get("version").is_none());
+        assert_eq!(Preferences::from_versioned(stored).unwrap(), preferences);
+    }
+}
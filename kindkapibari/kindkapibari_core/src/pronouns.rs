@@ -1,7 +1,26 @@
-// TODO: Localized Pronouns! This is English centric!
-// EX: some languages don't have gender neutral pronouns or neopronouns. We should support that!
+// Pronouns are per language now. English is the default (and what we use for languages we don't
+// have a table for yet), everything else lives in `LANGUAGES`.
 // mfw ill have to translate this later AATAGAGOAIWGUAWHUIGHIAHGWAGUAWU
 
+use crate::user_data::Locale;
+use std::{
+    collections::BTreeMap,
+    ops::{Deref, DerefMut},
+};
+
+const MAX_FORM_LEN: usize = 30;
+const MAX_CASES: usize = 8;
+const MAX_LOCALIZED: usize = 16;
+const MAX_ROTATION: usize = 16;
+
+pub const ENGLISH_CASES: [&str; 5] = [
+    "nominative",
+    "accusative",
+    "pronominal",
+    "predicative",
+    "reflexive",
+];
+
 pub const PRONOUNS_CONST_BUILTIN: [PronounProfileStr<'static>; 9] = [
     PronounProfileStr::HE_HIM,
     PronounProfileStr::SHE_HER,
@@ -14,6 +33,19 @@ pub const PRONOUNS_CONST_BUILTIN: [PronounProfileStr<'static>; 9] = [
     PronounProfileStr::AE_AERS,
 ];
 
+// what any/all rotates through when the user hasn't picked their own list
+pub const PRONOUNS_BUILTIN: [Pronouns; 9] = [
+    Pronouns::HeHim,
+    Pronouns::SheHer,
+    Pronouns::TheyThem,
+    Pronouns::PerPers,
+    Pronouns::ItIts,
+    Pronouns::FaeFaer,
+    Pronouns::XeXyrs,
+    Pronouns::ZeZie,
+    Pronouns::AeAers,
+];
+
 #[cfg_attr(feature = "server", derive(utoipa::Component))]
 #[derive(Clone, Debug, Hash, PartialOrd, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "t", content = "c")]
@@ -33,38 +65,114 @@ pub enum Pronouns {
 }
 
 impl Pronouns {
-    pub fn decode<S: AsRef<str>>(nouns: S) -> Option<Pronouns> {
-        match nouns.as_ref().to_lowercase().as_ref() {
-            "he/him" => Some(Pronouns::HeHim),
-            "she/her" => Some(Pronouns::SheHer),
-            "they/them" => Some(Pronouns::TheyThem),
-            "per/pers" => Some(Pronouns::PerPers),
-            "it/its" => Some(Pronouns::ItIts),
-            "fae/faer" | "fae/faers" => Some(Pronouns::FaeFaer),
-            "xe" | "xe/xyrs" | "xe/xers" | "xe/xem" => Some(Pronouns::XeXyrs),
-            "ze" | "ze/zie" | "ze/zers" | "ze/zirs" => Some(Pronouns::ZeZie),
-            "ae/aers" => Some(Pronouns::AeAers),
-            "any/all" => Some(Pronouns::AnyAll),
-            _ => None,
-            // "he/him" => Some(PronounProfile::new("he", "him", "his", "his", "himself")),
-            // "she/her" => Some(PronounProfile::new("she", "her", "hers", "her", "herself")),
-            // "they/them" => Some(PronounProfile::new(
-            //     "they", "them", "theirs", "their", "themself",
-            // )),
-            // "per/pers" => Some(PronounProfile::new("per", "per", "per", "pers", "perself")),
-            // "it/its" => Some(PronounProfile::new("it", "it", "its", "its", "itself")),
-            // "fae/faer" | "fae/faers" => Some(PronounProfile::new(
-            //     "fae", "faer", "faer", "faers", "faerself",
-            // )),
-            // "xe" | "xe/xyrs" | "xe/xers" | "xe/xem" => {
-            //     Some(PronounProfile::new("xe", "xem", "xyr", "xyrs", "xemself"))
-            // }
-            // "ze" | "ze/zie" | "ze/zers" | "ze/zirs" => {
-            //     Some(PronounProfile::new("ze", "zir", "zir", "zirs", "zirself"))
-            // }
-            // "ae/aers" => Some(PronounProfile::new("ae", "aer", "aer", "aers", "aerself")),
-            // _ => None,
+    #[must_use]
+    pub fn profile(&self) -> PronounProfileStr<'_> {
+        match self {
+            Pronouns::HeHim => PronounProfileStr::HE_HIM,
+            Pronouns::SheHer => PronounProfileStr::SHE_HER,
+            Pronouns::PerPers => PronounProfileStr::PER_PERS,
+            Pronouns::ItIts => PronounProfileStr::IT_ITS,
+            Pronouns::FaeFaer => PronounProfileStr::FAE_FAER,
+            Pronouns::XeXyrs => PronounProfileStr::XE_XYRS,
+            Pronouns::ZeZie => PronounProfileStr::ZE_ZIE,
+            Pronouns::AeAers => PronounProfileStr::AE_AERS,
+            Pronouns::Custom(profile) => PronounProfileStr::from(profile),
+            Pronouns::TheyThem | Pronouns::AnyAll => PronounProfileStr::THEY_THEM,
+        }
+    }
+
+    // Any/all picks one set from `allowed` (or every builtin if that's empty). The pick only
+    // depends on the user and the session, so one conversation stays consistent. Everything else
+    // is returned as is.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn for_session(&self, user_id: u64, session: u64, allowed: &[Pronouns]) -> Pronouns {
+        if *self != Pronouns::AnyAll {
+            return self.clone();
+        }
+        let allowed = allowed
+            .iter()
+            .filter(|pronouns| **pronouns != Pronouns::AnyAll)
+            .collect::<Vec<&Pronouns>>();
+        let pool = if allowed.is_empty() {
+            PRONOUNS_BUILTIN.iter().collect()
+        } else {
+            allowed
+        };
+        let pick = splitmix64(user_id ^ session.rotate_left(32)) % pool.len() as u64;
+        pool[pick as usize].clone()
+    }
+
+    // The forms for the locale's language, keyed by grammatical case. `None` means the language
+    // has nothing that fits and the person should be referred to by name.
+    #[must_use]
+    pub fn forms(&self, locale: &Locale) -> Option<PronounForms> {
+        let table = match LanguagePronouns::find(locale) {
+            Some(table) => table,
+            None => return Some(PronounForms::from(&self.profile())),
+        };
+        if let Pronouns::Custom(profile) = self {
+            if let Some(forms) = profile.localized(table.language) {
+                return Some(forms.clone());
+            }
         }
+        table.forms_for(self).or_else(|| match &table.fallback {
+            Fallback::Use(other) => table.forms_for(other),
+            Fallback::Name => None,
+        })
+    }
+}
+
+// a tiny, stable mixer. std's hasher is allowed to change between releases, this isn't.
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// the any/all allow-list, a newtype so it can be its own column
+#[derive(Clone, Debug, Default, Hash, PartialOrd, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PronounRotation {
+    int: Vec<Pronouns>,
+}
+
+impl PronounRotation {
+    #[must_use]
+    pub fn verify(&self) -> bool {
+        self.int.len() <= MAX_ROTATION
+            && self.int.iter().all(|pronouns| match pronouns {
+                Pronouns::AnyAll => false,
+                Pronouns::Custom(profile) => profile.verify(),
+                _ => true,
+            })
+    }
+}
+
+impl Deref for PronounRotation {
+    type Target = Vec<Pronouns>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.int
+    }
+}
+
+impl DerefMut for PronounRotation {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.int
+    }
+}
+
+impl From<Vec<Pronouns>> for PronounRotation {
+    fn from(v: Vec<Pronouns>) -> Self {
+        Self { int: v }
+    }
+}
+
+impl From<PronounRotation> for Vec<Pronouns> {
+    fn from(r: PronounRotation) -> Self {
+        r.int
     }
 }
 
@@ -76,6 +184,10 @@ impl Default for Pronouns {
 
 impl From<PronounProfile> for Pronouns {
     fn from(pp: PronounProfile) -> Self {
+        // localized forms make it custom, even if the english ones are builtin
+        if !pp.localized.is_empty() {
+            return Pronouns::Custom(pp);
+        }
         let pps = PronounProfileStr::from(&pp);
         match pps {
             PronounProfileStr::HE_HIM => Pronouns::HeHim,
@@ -100,6 +212,9 @@ pub struct PronounProfile {
     pub(crate) pronominal: String,
     pub(crate) predicative: String,
     pub(crate) reflexive: String,
+    // forms for other languages, keyed by primary language (e.g. "de")
+    #[serde(default)]
+    pub(crate) localized: BTreeMap<String, PronounForms>,
 }
 
 impl PronounProfile {
@@ -116,6 +231,7 @@ impl PronounProfile {
             pronominal: pronominal.as_ref().to_string(),
             predicative: predicative.as_ref().to_string(),
             reflexive: reflexive.as_ref().to_string(),
+            localized: BTreeMap::new(),
         }
     }
 
@@ -125,7 +241,18 @@ impl PronounProfile {
             || self.accusative.len() > 30
             || self.pronominal.len() > 30
             || self.predicative.len() > 30
-            || self.reflexive.len() > 30)
+            || self.reflexive.len() > 30
+            || self.localized.len() > MAX_LOCALIZED
+            || !self.localized.values().all(PronounForms::verify))
+    }
+
+    #[must_use]
+    pub fn localized(&self, language: &str) -> Option<&PronounForms> {
+        self.localized.get(language)
+    }
+
+    pub fn set_localized(&mut self, language: String, forms: PronounForms) {
+        self.localized.insert(language, forms);
     }
 
     pub fn set_nominative(&mut self, nominative: String) {
@@ -175,6 +302,7 @@ impl Default for PronounProfile {
             pronominal: "their".to_string(),
             predicative: "their".to_string(),
             reflexive: "themself".to_string(),
+            localized: BTreeMap::new(),
         }
     }
 }
@@ -290,11 +418,267 @@ impl<'a> From<PronounProfileStr<'a>> for PronounProfile {
             pronominal: pps.pronominal.to_string(),
             predicative: pps.predicative.to_string(),
             reflexive: pps.reflexive.to_string(),
+            localized: BTreeMap::new(),
+        }
+    }
+}
+
+// grammatical case -> form, e.g. "dative" -> "ihm"
+#[derive(Clone, Debug, Default, Hash, PartialOrd, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::Component))]
+#[serde(transparent)]
+pub struct PronounForms {
+    forms: BTreeMap<String, String>,
+}
+
+impl PronounForms {
+    #[must_use]
+    pub fn new(cases: &[&str], forms: &[&str]) -> Self {
+        Self {
+            forms: cases
+                .iter()
+                .zip(forms.iter())
+                .map(|(case, form)| ((*case).to_string(), (*form).to_string()))
+                .collect(),
+        }
+    }
+
+    // every case is just the name, for languages that have nothing that fits
+    #[must_use]
+    pub fn name_only(locale: &Locale, name: &str) -> Self {
+        let cases = LanguagePronouns::find(locale).map_or(&ENGLISH_CASES[..], |table| table.cases);
+        Self {
+            forms: cases
+                .iter()
+                .map(|case| ((*case).to_string(), name.to_string()))
+                .collect(),
         }
     }
+
+    #[must_use]
+    pub fn get(&self, case: &str) -> Option<&str> {
+        self.forms.get(case).map(String::as_str)
+    }
+
+    #[must_use]
+    pub fn verify(&self) -> bool {
+        self.forms.len() <= MAX_CASES
+            && self
+                .forms
+                .iter()
+                .all(|(case, form)| case.len() <= MAX_FORM_LEN && form.len() <= MAX_FORM_LEN)
+    }
+}
+
+impl Deref for PronounForms {
+    type Target = BTreeMap<String, String>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.forms
+    }
+}
+
+impl DerefMut for PronounForms {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.forms
+    }
+}
+
+impl<'a> From<&PronounProfileStr<'a>> for PronounForms {
+    fn from(pps: &PronounProfileStr<'a>) -> Self {
+        Self::new(
+            &ENGLISH_CASES,
+            &[
+                pps.nominative,
+                pps.accusative,
+                pps.pronominal,
+                pps.predicative,
+                pps.reflexive,
+            ],
+        )
+    }
+}
+
+// what to do when a language has no forms for someone's pronouns
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fallback {
+    // use a set the language does have instead
+    Use(Pronouns),
+    // nothing fits (e.g. no neutral pronouns), use their name
+    Name,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LanguagePronouns {
+    pub language: &'static str,
+    pub cases: &'static [&'static str],
+    pub fallback: Fallback,
+    // forms are in the same order as `cases`
+    pub forms: &'static [(Pronouns, &'static [&'static str])],
+}
+
+impl LanguagePronouns {
+    #[must_use]
+    pub fn find(locale: &Locale) -> Option<&'static LanguagePronouns> {
+        LANGUAGES
+            .iter()
+            .find(|table| table.language == locale.primary_language())
+    }
+
+    #[must_use]
+    pub fn forms_for(&self, pronouns: &Pronouns) -> Option<PronounForms> {
+        self.forms
+            .iter()
+            .find(|(p, _)| p == pronouns)
+            .map(|(_, forms)| PronounForms::new(self.cases, forms))
+    }
 }
 
+pub const LANGUAGES: [LanguagePronouns; 2] = [
+    // no established neutral pronoun, a lot of enbies just go by their name
+    LanguagePronouns {
+        language: "de",
+        cases: &["nominative", "accusative", "dative", "genitive"],
+        fallback: Fallback::Name,
+        forms: &[
+            (Pronouns::HeHim, &["er", "ihn", "ihm", "sein"]),
+            (Pronouns::SheHer, &["sie", "sie", "ihr", "ihr"]),
+            (Pronouns::ItIts, &["es", "es", "ihm", "sein"]),
+        ],
+    },
+    LanguagePronouns {
+        language: "es",
+        cases: &["nominative", "accusative", "dative", "possessive"],
+        fallback: Fallback::Use(Pronouns::TheyThem),
+        forms: &[
+            (Pronouns::HeHim, &["él", "lo", "le", "su"]),
+            (Pronouns::SheHer, &["ella", "la", "le", "su"]),
+            (Pronouns::TheyThem, &["elle", "le", "le", "su"]),
+        ],
+    },
+];
+
 #[cfg(feature = "server")]
-crate::impl_sea_orm!(Pronouns, PronounProfile);
+crate::impl_sea_orm!(Pronouns, PronounProfile, PronounForms, PronounRotation);
 #[cfg(feature = "server")]
-crate::impl_redis!(Pronouns, PronounProfile);
+crate::impl_redis!(Pronouns, PronounProfile, PronounForms, PronounRotation);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn locale(tag: &str) -> Locale {
+        Locale::from_str(tag).unwrap()
+    }
+
+    #[test]
+    fn german_has_its_own_cases() {
+        let forms = Pronouns::HeHim.forms(&locale("de-DE")).unwrap();
+        assert_eq!(forms.get("nominative"), Some("er"));
+        assert_eq!(forms.get("accusative"), Some("ihn"));
+        assert_eq!(forms.get("dative"), Some("ihm"));
+        assert_eq!(forms.get("genitive"), Some("sein"));
+        assert_eq!(forms.get("reflexive"), None);
+    }
+
+    #[test]
+    fn german_falls_back_to_the_name() {
+        let de = locale("de");
+        assert_eq!(Pronouns::TheyThem.forms(&de), None);
+        assert_eq!(Pronouns::XeXyrs.forms(&de), None);
+        assert_eq!(
+            PronounForms::name_only(&de, "kapi").get("dative"),
+            Some("kapi")
+        );
+    }
+
+    #[test]
+    fn spanish_falls_back_to_elle() {
+        let es = locale("es-MX");
+        let elle = Pronouns::TheyThem.forms(&es).unwrap();
+        assert_eq!(elle.get("nominative"), Some("elle"));
+        assert_eq!(Pronouns::FaeFaer.forms(&es), Some(elle));
+    }
+
+    #[test]
+    fn unknown_languages_are_english() {
+        let forms = Pronouns::FaeFaer.forms(&locale("fr-CA")).unwrap();
+        assert_eq!(forms, PronounForms::from(&PronounProfileStr::FAE_FAER));
+        assert_eq!(forms.get("reflexive"), Some("faerself"));
+    }
+
+    #[test]
+    fn a_session_keeps_its_pronouns() {
+        let allowed = [Pronouns::SheHer, Pronouns::XeXyrs, Pronouns::TheyThem];
+        for session in 0..32 {
+            let picked = Pronouns::AnyAll.for_session(7, session, &allowed);
+            assert_eq!(Pronouns::AnyAll.for_session(7, session, &allowed), picked);
+            assert!(allowed.contains(&picked));
+        }
+    }
+
+    #[test]
+    fn sessions_rotate_through_the_allow_list() {
+        let allowed = [Pronouns::SheHer, Pronouns::XeXyrs, Pronouns::TheyThem];
+        let picked = (0..64)
+            .map(|session| Pronouns::AnyAll.for_session(7, session, &allowed))
+            .collect::<Vec<Pronouns>>();
+        assert!(allowed.iter().all(|pronouns| picked.contains(pronouns)));
+    }
+
+    #[test]
+    fn any_all_is_never_picked() {
+        let allowed = [Pronouns::AnyAll, Pronouns::FaeFaer];
+        assert!(
+            (0..32)
+                .all(|session| Pronouns::AnyAll.for_session(7, session, &allowed)
+                    == Pronouns::FaeFaer)
+        );
+        // only any/all is the same as nothing
+        let picked = (0..64)
+            .map(|session| Pronouns::AnyAll.for_session(7, session, &[Pronouns::AnyAll]))
+            .collect::<Vec<Pronouns>>();
+        assert!(picked
+            .iter()
+            .all(|pronouns| PRONOUNS_BUILTIN.contains(pronouns)));
+    }
+
+    #[test]
+    fn empty_allow_list_uses_the_builtins() {
+        let picked = (0..256)
+            .map(|session| Pronouns::AnyAll.for_session(7, session, &[]))
+            .collect::<Vec<Pronouns>>();
+        assert!(picked
+            .iter()
+            .all(|pronouns| PRONOUNS_BUILTIN.contains(pronouns)));
+        assert!(PRONOUNS_BUILTIN
+            .iter()
+            .all(|pronouns| picked.contains(pronouns)));
+    }
+
+    #[test]
+    fn only_any_all_rotates() {
+        assert_eq!(
+            Pronouns::HeHim.for_session(7, 1, &[Pronouns::SheHer]),
+            Pronouns::HeHim
+        );
+    }
+
+    #[test]
+    fn custom_pronouns_use_their_own_translation() {
+        let mut profile = PronounProfile::from(PronounProfileStr::THEY_THEM);
+        profile.set_localized(
+            "de".to_string(),
+            PronounForms::new(&["nominative", "accusative"], &["dey", "dem"]),
+        );
+        let custom = Pronouns::from(profile);
+        let forms = custom.forms(&locale("de")).unwrap();
+        assert_eq!(forms.get("nominative"), Some("dey"));
+        // no translation for spanish, so it falls back like they/them would
+        assert_eq!(
+            custom.forms(&locale("es")),
+            Pronouns::TheyThem.forms(&locale("es"))
+        );
+    }
+}
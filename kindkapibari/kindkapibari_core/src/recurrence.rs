@@ -0,0 +1,747 @@
+use chrono::{
+    DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc,
+    Weekday,
+};
+use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+
+// how many periods in a row can produce nothing before we give up
+// (e.g. BYMONTHDAY=30 on a YEARLY rule anchored in february)
+const MAX_EMPTY_PERIODS: u32 = 1024;
+// no DST transition moves the clock by more than this
+const DST_SLACK_HOURS: i64 = 3;
+
+const DAYS: [Day; 7] = [
+    Day::Monday,
+    Day::Tuesday,
+    Day::Wednesday,
+    Day::Thursday,
+    Day::Friday,
+    Day::Saturday,
+    Day::Sunday,
+];
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::Component))]
+pub enum Day {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Day {
+    #[must_use]
+    pub fn num_days_from_monday(self) -> u32 {
+        Weekday::from(self).num_days_from_monday()
+    }
+}
+
+impl From<Weekday> for Day {
+    fn from(weekday: Weekday) -> Self {
+        DAYS[weekday.num_days_from_monday() as usize]
+    }
+}
+
+impl From<Day> for Weekday {
+    fn from(day: Day) -> Self {
+        match day {
+            Day::Monday => Weekday::Mon,
+            Day::Tuesday => Weekday::Tue,
+            Day::Wednesday => Weekday::Wed,
+            Day::Thursday => Weekday::Thu,
+            Day::Friday => Weekday::Fri,
+            Day::Saturday => Weekday::Sat,
+            Day::Sunday => Weekday::Sun,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::Component))]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum RecurrenceError {
+    #[error("Interval must be at least 1")]
+    ZeroInterval,
+    #[error("Invalid month day {0}, must be within -31..=31 and not 0")]
+    InvalidMonthDay(i8),
+    #[error("Until {0} is before the start {1}")]
+    UntilBeforeStart(NaiveDateTime, NaiveDateTime),
+}
+
+// RFC 5545 RRULE, minus the parts we don't need (BYSETPOS, BYWEEKNO, BYHOUR...).
+// All times are wall clock times, the owner's timezone is applied on top of this.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Serialize, Deserialize)]
+pub struct RecurrenceRule {
+    pub start: NaiveDateTime,
+    pub frequency: Frequency,
+    pub interval: u32,
+    #[serde(default)]
+    pub by_day: Vec<Day>,
+    #[serde(default)]
+    pub by_month_day: Vec<i8>,
+    pub count: Option<u32>,
+    pub until: Option<NaiveDateTime>,
+}
+
+impl RecurrenceRule {
+    #[must_use]
+    pub fn new(start: NaiveDateTime, frequency: Frequency) -> Self {
+        Self {
+            start,
+            frequency,
+            interval: 1,
+            by_day: vec![],
+            by_month_day: vec![],
+            count: None,
+            until: None,
+        }
+    }
+
+    // the old alarm-clock style 7 day bitmask is just a weekly rule
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn from_legacy_days(days: [bool; 7], time: NaiveTime) -> Self {
+        // 1970-01-05 is a monday
+        let start = NaiveDate::from_ymd_opt(1970, 1, 5).unwrap().and_time(time); // Panics: this is a valid date
+        let by_day = DAYS
+            .iter()
+            .zip(days)
+            .filter_map(|(day, on)| on.then_some(*day))
+            .collect::<Vec<Day>>();
+        Self {
+            by_day,
+            ..Self::new(start, Frequency::Weekly)
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), RecurrenceError> {
+        if self.interval == 0 {
+            return Err(RecurrenceError::ZeroInterval);
+        }
+        if let Some(bad) = self
+            .by_month_day
+            .iter()
+            .find(|day| **day == 0 || !(-31..=31).contains(*day))
+        {
+            return Err(RecurrenceError::InvalidMonthDay(*bad));
+        }
+        if let Some(until) = self.until {
+            if until < self.start {
+                return Err(RecurrenceError::UntilBeforeStart(until, self.start));
+            }
+        }
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn occurrences(&self) -> Occurrences<'_> {
+        Occurrences::new(self, 0)
+    }
+
+    // the next `n` occurrences strictly after `after`
+    pub fn next_occurrences(
+        &self,
+        after: NaiveDateTime,
+        n: usize,
+    ) -> impl Iterator<Item = NaiveDateTime> + '_ {
+        // COUNT needs to see every occurrence from the start, otherwise we can skip ahead
+        let period = if self.count.is_none() {
+            self.period_of(after).saturating_sub(1)
+        } else {
+            0
+        };
+        Occurrences::new(self, period)
+            .skip_while(move |occurrence| *occurrence <= after)
+            .take(n)
+    }
+
+    #[must_use]
+    pub fn next_occurrence(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        self.next_occurrences(after, 1).next()
+    }
+
+    // wall clock times are interpreted in `tz`, so 08:00 stays 08:00 across DST
+    pub fn next_fire<T: TimeZone>(&self, after: DateTime<Utc>, tz: &T) -> Option<DateTime<Utc>> {
+        // look back a bit so an occurrence inside a DST fold isn't skipped
+        let local_after = after.with_timezone(tz).naive_local() - Duration::hours(DST_SLACK_HOURS);
+        self.next_occurrences(local_after, usize::MAX)
+            .filter_map(|occurrence| resolve_local(tz, occurrence))
+            .find(|fire| *fire > after)
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    fn period_of(&self, at: NaiveDateTime) -> u32 {
+        if at <= self.start {
+            return 0;
+        }
+        let start = self.start.date();
+        let at = at.date();
+        let elapsed = match self.frequency {
+            Frequency::Daily => (at - start).num_days(),
+            Frequency::Weekly => (week_start(at) - week_start(start)).num_weeks(),
+            Frequency::Monthly => {
+                i64::from(at.year() - start.year()) * 12 + i64::from(at.month())
+                    - i64::from(start.month())
+            }
+            Frequency::Yearly => i64::from(at.year() - start.year()),
+        };
+        (elapsed.max(0) as u64 / u64::from(self.interval.max(1))).min(u64::from(u32::MAX)) as u32
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    fn candidates(&self, period: u32) -> Vec<NaiveDate> {
+        let start = self.start.date();
+        let step = i64::from(period) * i64::from(self.interval.max(1));
+        let mut dates = match self.frequency {
+            Frequency::Daily => {
+                let day = start + Duration::days(step);
+                if self.by_day.is_empty() || self.by_day.contains(&day.weekday().into()) {
+                    vec![day]
+                } else {
+                    vec![]
+                }
+            }
+            Frequency::Weekly => {
+                let week = week_start(start) + Duration::weeks(step);
+                if self.by_day.is_empty() {
+                    vec![week + Duration::days(i64::from(start.weekday().num_days_from_monday()))]
+                } else {
+                    self.by_day
+                        .iter()
+                        .map(|day| week + Duration::days(i64::from(day.num_days_from_monday())))
+                        .collect()
+                }
+            }
+            Frequency::Monthly => {
+                let months = i64::from(start.year()) * 12 + i64::from(start.month0()) + step;
+                match i32::try_from(months.div_euclid(12)) {
+                    Ok(year) => self.month_candidates(year, months.rem_euclid(12) as u32 + 1),
+                    Err(_) => vec![],
+                }
+            }
+            Frequency::Yearly => match i32::try_from(i64::from(start.year()) + step) {
+                Ok(year) => self.month_candidates(year, start.month()),
+                Err(_) => vec![],
+            },
+        };
+
+        if !self.by_month_day.is_empty() && self.frequency == Frequency::Daily {
+            dates.retain(|date| self.matches_month_day(*date));
+        }
+        dates.sort_unstable();
+        dates.dedup();
+        dates
+    }
+
+    fn month_candidates(&self, year: i32, month: u32) -> Vec<NaiveDate> {
+        let days_in_month = days_in_month(year, month);
+        let by_month_day = self
+            .by_month_day
+            .iter()
+            .filter_map(|day| {
+                let day = i32::from(*day);
+                let resolved = if day < 0 {
+                    i32::try_from(days_in_month).ok()? + day + 1
+                } else {
+                    day
+                };
+                NaiveDate::from_ymd_opt(year, month, u32::try_from(resolved).ok()?)
+            })
+            .collect::<Vec<NaiveDate>>();
+
+        // the rule's days, not what's left of them, a month without a 31st has none
+        match (self.by_month_day.is_empty(), self.by_day.is_empty()) {
+            (true, true) => NaiveDate::from_ymd_opt(year, month, self.start.day())
+                .into_iter()
+                .collect(),
+            (false, true) => by_month_day,
+            (true, false) => (1..=days_in_month)
+                .filter_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+                .filter(|date| self.by_day.contains(&date.weekday().into()))
+                .collect(),
+            (false, false) => by_month_day
+                .into_iter()
+                .filter(|date| self.by_day.contains(&date.weekday().into()))
+                .collect(),
+        }
+    }
+
+    fn matches_month_day(&self, date: NaiveDate) -> bool {
+        let days_in_month = i64::from(days_in_month(date.year(), date.month()));
+        let day = i64::from(date.day());
+        self.by_month_day.iter().any(|by| {
+            let by = i64::from(*by);
+            by == day || (by < 0 && days_in_month + by + 1 == day)
+        })
+    }
+}
+
+pub struct Occurrences<'a> {
+    rule: &'a RecurrenceRule,
+    period: u32,
+    buffer: Vec<NaiveDateTime>,
+    emitted: u32,
+    done: bool,
+}
+
+impl<'a> Occurrences<'a> {
+    fn new(rule: &'a RecurrenceRule, period: u32) -> Self {
+        Self {
+            rule,
+            period,
+            buffer: vec![],
+            emitted: 0,
+            done: rule.validate().is_err(),
+        }
+    }
+
+    fn fill(&mut self) {
+        let mut empty = 0;
+        while self.buffer.is_empty() {
+            if empty >= MAX_EMPTY_PERIODS {
+                self.done = true;
+                return;
+            }
+            let time = self.rule.start.time();
+            let mut dates = self
+                .rule
+                .candidates(self.period)
+                .into_iter()
+                .map(|date| date.and_time(time))
+                .filter(|occurrence| *occurrence >= self.rule.start)
+                .collect::<Vec<NaiveDateTime>>();
+            // pop() from the back
+            dates.reverse();
+            self.buffer = dates;
+
+            if let Some(next) = self.period.checked_add(1) {
+                self.period = next;
+            } else {
+                self.done = self.buffer.is_empty();
+                return;
+            }
+            empty += 1;
+        }
+    }
+}
+
+impl Iterator for Occurrences<'_> {
+    type Item = NaiveDateTime;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(count) = self.rule.count {
+            if self.emitted >= count {
+                self.done = true;
+                return None;
+            }
+        }
+
+        self.fill();
+        let occurrence = self.buffer.pop()?;
+        if let Some(until) = self.rule.until {
+            if occurrence > until {
+                self.done = true;
+                return None;
+            }
+        }
+        self.emitted += 1;
+        Some(occurrence)
+    }
+}
+
+// Clocks going back: fire on the first of the two.
+// Clocks going forward: push it forward until the time exists (02:30 -> 03:30)
+pub fn resolve_local<T: TimeZone>(tz: &T, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+    (0..=DST_SLACK_HOURS).find_map(|hours| {
+        match tz.from_local_datetime(&(local + Duration::hours(hours))) {
+            LocalResult::Single(fire) | LocalResult::Ambiguous(fire, _) => {
+                Some(fire.with_timezone(&Utc))
+            }
+            LocalResult::None => None,
+        }
+    })
+}
+
+fn week_start(date: NaiveDate) -> NaiveDate {
+    date - Duration::days(i64::from(date.weekday().num_days_from_monday()))
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    NaiveDate::from_ymd_opt(next_year, next_month, 1)
+        .and_then(|first| first.pred_opt())
+        .map_or(31, |last| last.day())
+}
+
+#[cfg(feature = "server")]
+impl utoipa::Component for RecurrenceRule {
+    fn component() -> utoipa::openapi::Component {
+        use utoipa::openapi::{
+            ArrayBuilder, ComponentFormat, ComponentType, ObjectBuilder, PropertyBuilder, Ref,
+        };
+        ObjectBuilder::new()
+            .property(
+                "start",
+                PropertyBuilder::new()
+                    .component_type(ComponentType::String)
+                    .format(Some(ComponentFormat::DateTime)),
+            )
+            .required("start")
+            .property("frequency", Ref::from_component_name("Frequency"))
+            .required("frequency")
+            .property(
+                "interval",
+                PropertyBuilder::new()
+                    .component_type(ComponentType::Integer)
+                    .format(Some(ComponentFormat::Int32)),
+            )
+            .required("interval")
+            .property(
+                "by_day",
+                ArrayBuilder::new()
+                    .items(Ref::from_component_name("Day"))
+                    .max_items(Some(7))
+                    .build(),
+            )
+            .property(
+                "by_month_day",
+                ArrayBuilder::new()
+                    .items(
+                        PropertyBuilder::new()
+                            .component_type(ComponentType::Integer)
+                            .format(Some(ComponentFormat::Int32)),
+                    )
+                    .build(),
+            )
+            .property(
+                "count",
+                PropertyBuilder::new()
+                    .component_type(ComponentType::Integer)
+                    .format(Some(ComponentFormat::Int32)),
+            )
+            .property(
+                "until",
+                PropertyBuilder::new()
+                    .component_type(ComponentType::String)
+                    .format(Some(ComponentFormat::DateTime)),
+            )
+            .into()
+    }
+}
+
+#[cfg(feature = "server")]
+crate::impl_sea_orm!(RecurrenceRule);
+#[cfg(feature = "server")]
+crate::impl_redis!(RecurrenceRule);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::Europe::Berlin;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn dates(occurrences: impl Iterator<Item = NaiveDateTime>) -> Vec<NaiveDate> {
+        occurrences.map(|occurrence| occurrence.date()).collect()
+    }
+
+    fn ymd(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn weekly_by_day() {
+        // 2022-06-06 is a monday
+        let rule = RecurrenceRule {
+            by_day: vec![Day::Friday, Day::Monday, Day::Wednesday],
+            ..RecurrenceRule::new(at(2022, 6, 6, 9, 0), Frequency::Weekly)
+        };
+        assert_eq!(
+            dates(rule.occurrences().take(6)),
+            vec![
+                ymd(2022, 6, 6),
+                ymd(2022, 6, 8),
+                ymd(2022, 6, 10),
+                ymd(2022, 6, 13),
+                ymd(2022, 6, 15),
+                ymd(2022, 6, 17),
+            ]
+        );
+    }
+
+    #[test]
+    fn weekly_by_day_before_start_is_skipped() {
+        // starts on a wednesday, that week's monday already went by
+        let rule = RecurrenceRule {
+            by_day: vec![Day::Monday, Day::Wednesday],
+            interval: 2,
+            ..RecurrenceRule::new(at(2022, 6, 8, 9, 0), Frequency::Weekly)
+        };
+        assert_eq!(
+            dates(rule.occurrences().take(3)),
+            vec![ymd(2022, 6, 8), ymd(2022, 6, 20), ymd(2022, 6, 22)]
+        );
+    }
+
+    #[test]
+    fn daily_by_day() {
+        // weekdays only, 2022-06-10 is a friday
+        let rule = RecurrenceRule {
+            by_day: vec![
+                Day::Monday,
+                Day::Tuesday,
+                Day::Wednesday,
+                Day::Thursday,
+                Day::Friday,
+            ],
+            ..RecurrenceRule::new(at(2022, 6, 10, 7, 30), Frequency::Daily)
+        };
+        assert_eq!(
+            dates(rule.occurrences().take(3)),
+            vec![ymd(2022, 6, 10), ymd(2022, 6, 13), ymd(2022, 6, 14)]
+        );
+    }
+
+    #[test]
+    fn monthly_by_month_day_skips_short_months() {
+        let rule = RecurrenceRule {
+            by_month_day: vec![31],
+            ..RecurrenceRule::new(at(2022, 1, 1, 12, 0), Frequency::Monthly)
+        };
+        assert_eq!(
+            dates(rule.occurrences().take(4)),
+            vec![
+                ymd(2022, 1, 31),
+                ymd(2022, 3, 31),
+                ymd(2022, 5, 31),
+                ymd(2022, 7, 31),
+            ]
+        );
+    }
+
+    #[test]
+    fn monthly_negative_month_day_is_month_end() {
+        let rule = RecurrenceRule {
+            by_month_day: vec![-1],
+            ..RecurrenceRule::new(at(2022, 1, 1, 12, 0), Frequency::Monthly)
+        };
+        assert_eq!(
+            dates(rule.occurrences().take(4)),
+            vec![
+                ymd(2022, 1, 31),
+                ymd(2022, 2, 28),
+                ymd(2022, 3, 31),
+                ymd(2022, 4, 30),
+            ]
+        );
+    }
+
+    #[test]
+    fn monthly_by_day_and_month_day() {
+        // friday the 13th
+        let rule = RecurrenceRule {
+            by_day: vec![Day::Friday],
+            by_month_day: vec![13],
+            ..RecurrenceRule::new(at(2022, 1, 1, 0, 0), Frequency::Monthly)
+        };
+        assert_eq!(
+            dates(rule.occurrences().take(3)),
+            vec![ymd(2022, 5, 13), ymd(2023, 1, 13), ymd(2023, 10, 13)]
+        );
+    }
+
+    #[test]
+    fn daily_by_month_day() {
+        let rule = RecurrenceRule {
+            by_month_day: vec![1, -1],
+            ..RecurrenceRule::new(at(2024, 2, 2, 8, 0), Frequency::Daily)
+        };
+        assert_eq!(
+            dates(rule.occurrences().take(3)),
+            vec![ymd(2024, 2, 29), ymd(2024, 3, 1), ymd(2024, 3, 31)]
+        );
+    }
+
+    #[test]
+    fn count_limits_occurrences() {
+        let rule = RecurrenceRule {
+            count: Some(3),
+            ..RecurrenceRule::new(at(2022, 6, 6, 9, 0), Frequency::Daily)
+        };
+        assert_eq!(rule.occurrences().count(), 3);
+        // counted from the start, not from `after`
+        assert_eq!(
+            dates(rule.next_occurrences(at(2022, 6, 7, 9, 0), 10)),
+            vec![ymd(2022, 6, 8)]
+        );
+        assert_eq!(rule.next_occurrence(at(2022, 6, 8, 9, 0)), None);
+    }
+
+    #[test]
+    fn until_is_inclusive() {
+        let rule = RecurrenceRule {
+            until: Some(at(2022, 6, 8, 9, 0)),
+            ..RecurrenceRule::new(at(2022, 6, 6, 9, 0), Frequency::Daily)
+        };
+        assert_eq!(
+            dates(rule.occurrences()),
+            vec![ymd(2022, 6, 6), ymd(2022, 6, 7), ymd(2022, 6, 8)]
+        );
+
+        let rule = RecurrenceRule {
+            until: Some(at(2022, 6, 8, 8, 59)),
+            ..rule
+        };
+        assert_eq!(rule.occurrences().count(), 2);
+    }
+
+    #[test]
+    fn yearly_leap_day() {
+        let rule = RecurrenceRule::new(at(2020, 2, 29, 10, 0), Frequency::Yearly);
+        assert_eq!(
+            dates(rule.occurrences().take(3)),
+            vec![ymd(2020, 2, 29), ymd(2024, 2, 29), ymd(2028, 2, 29)]
+        );
+        assert_eq!(
+            rule.next_occurrence(at(2021, 1, 1, 0, 0)),
+            Some(at(2024, 2, 29, 10, 0))
+        );
+    }
+
+    #[test]
+    fn monthly_leap_day() {
+        let rule = RecurrenceRule {
+            by_month_day: vec![29],
+            ..RecurrenceRule::new(at(2023, 1, 1, 10, 0), Frequency::Monthly)
+        };
+        assert_eq!(
+            dates(rule.next_occurrences(at(2023, 1, 30, 0, 0), 2)),
+            vec![ymd(2023, 3, 29), ymd(2023, 4, 29)]
+        );
+        assert_eq!(
+            dates(rule.next_occurrences(at(2024, 1, 30, 0, 0), 1)),
+            vec![ymd(2024, 2, 29)]
+        );
+    }
+
+    #[test]
+    fn impossible_rule_ends() {
+        // there's no february 30th, ever
+        let rule = RecurrenceRule {
+            by_month_day: vec![30],
+            ..RecurrenceRule::new(at(2022, 2, 1, 0, 0), Frequency::Yearly)
+        };
+        assert_eq!(rule.occurrences().next(), None);
+    }
+
+    #[test]
+    fn next_occurrences_skips_ahead() {
+        let rule = RecurrenceRule {
+            interval: 3,
+            ..RecurrenceRule::new(at(2022, 1, 1, 9, 0), Frequency::Daily)
+        };
+        assert_eq!(
+            dates(rule.next_occurrences(at(2022, 1, 7, 9, 0), 2)),
+            vec![ymd(2022, 1, 10), ymd(2022, 1, 13)]
+        );
+    }
+
+    #[test]
+    fn invalid_rules() {
+        let start = at(2022, 1, 1, 0, 0);
+        let rule = RecurrenceRule {
+            interval: 0,
+            ..RecurrenceRule::new(start, Frequency::Daily)
+        };
+        assert_eq!(rule.validate(), Err(RecurrenceError::ZeroInterval));
+        assert_eq!(rule.occurrences().next(), None);
+
+        for bad in [0, 32, -32] {
+            let rule = RecurrenceRule {
+                by_month_day: vec![bad],
+                ..RecurrenceRule::new(start, Frequency::Monthly)
+            };
+            assert_eq!(rule.validate(), Err(RecurrenceError::InvalidMonthDay(bad)));
+        }
+
+        let until = at(2021, 1, 1, 0, 0);
+        let rule = RecurrenceRule {
+            until: Some(until),
+            ..RecurrenceRule::new(start, Frequency::Daily)
+        };
+        assert_eq!(
+            rule.validate(),
+            Err(RecurrenceError::UntilBeforeStart(until, start))
+        );
+    }
+
+    #[test]
+    fn legacy_days() {
+        let rule = RecurrenceRule::from_legacy_days(
+            [true, false, false, false, false, false, true],
+            NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+        );
+        assert_eq!(rule.by_day, vec![Day::Monday, Day::Sunday]);
+        assert_eq!(
+            dates(rule.next_occurrences(at(2022, 6, 8, 0, 0), 3)),
+            vec![ymd(2022, 6, 12), ymd(2022, 6, 13), ymd(2022, 6, 19)]
+        );
+    }
+
+    #[test]
+    fn next_fire_across_dst() {
+        // 2022-03-27 02:30 doesn't exist in berlin, clocks jump to 03:00
+        let rule = RecurrenceRule::new(at(2022, 3, 26, 2, 30), Frequency::Daily);
+        let after = Utc.from_utc_datetime(&at(2022, 3, 26, 12, 0));
+        assert_eq!(
+            rule.next_fire(after, &Berlin),
+            Some(Utc.from_utc_datetime(&at(2022, 3, 27, 1, 30)))
+        );
+
+        // 2022-10-30 02:30 happens twice, the first one is 00:30 UTC
+        let rule = RecurrenceRule::new(at(2022, 10, 29, 2, 30), Frequency::Daily);
+        let after = Utc.from_utc_datetime(&at(2022, 10, 29, 12, 0));
+        assert_eq!(
+            rule.next_fire(after, &Berlin),
+            Some(Utc.from_utc_datetime(&at(2022, 10, 30, 0, 30)))
+        );
+
+        // 08:00 stays 08:00 local, summer and winter
+        let rule = RecurrenceRule::new(at(2022, 1, 1, 8, 0), Frequency::Daily);
+        let after = Utc.from_utc_datetime(&at(2022, 7, 1, 12, 0));
+        assert_eq!(
+            rule.next_fire(after, &Berlin),
+            Some(Utc.from_utc_datetime(&at(2022, 7, 2, 6, 0)))
+        );
+        let after = Utc.from_utc_datetime(&at(2022, 12, 1, 12, 0));
+        assert_eq!(
+            rule.next_fire(after, &Berlin),
+            Some(Utc.from_utc_datetime(&at(2022, 12, 2, 7, 0)))
+        );
+    }
+}
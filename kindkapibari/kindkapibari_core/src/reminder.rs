@@ -1,3 +1,4 @@
+use crate::{recurrence::RecurrenceRule, user_data::Timezone};
 use chrono::{naive::NaiveTime, DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::hash::Hash;
@@ -36,7 +37,36 @@ pub struct RecurringReminder {
     pub id: u64,
     pub name: String,
     pub time: NaiveTime,
+    // legacy form, only used if `recurrence` is `None`
     pub days: [bool; 7],
+    #[serde(default)]
+    pub recurrence: Option<RecurrenceRule>,
+    // if this is None, the owner's timezone is used
+    #[serde(default)]
+    pub timezone: Option<Timezone>,
+    // filled in by the server, ignored on write
+    #[serde(default)]
+    pub next_fire: Option<DateTime<Utc>>,
+}
+
+impl RecurringReminder {
+    #[must_use]
+    pub fn rule(&self) -> RecurrenceRule {
+        match &self.recurrence {
+            Some(rule) => rule.clone(),
+            None => RecurrenceRule::from_legacy_days(self.days, self.time),
+        }
+    }
+
+    #[must_use]
+    pub fn next_fire(
+        &self,
+        after: DateTime<Utc>,
+        owner_timezone: Timezone,
+    ) -> Option<DateTime<Utc>> {
+        let timezone = self.timezone.unwrap_or(owner_timezone);
+        self.rule().next_fire(after, &*timezone)
+    }
 }
 
 impl Default for RecurringReminder {
@@ -46,6 +76,9 @@ impl Default for RecurringReminder {
             name: "".to_string(),
             time: Utc::now().time(),
             days: u8_bitflag_to_days(0),
+            recurrence: None,
+            timezone: None,
+            next_fire: None,
         }
     }
 }
@@ -55,6 +88,7 @@ impl utoipa::Component for RecurringReminder {
     fn component() -> utoipa::openapi::Component {
         use utoipa::openapi::{
             ArrayBuilder, ComponentFormat, ComponentType, ObjectBuilder, Property, PropertyBuilder,
+            Ref,
         };
         ObjectBuilder::new()
             .property(
@@ -77,6 +111,14 @@ impl utoipa::Component for RecurringReminder {
                     .build(),
             )
             .required("days")
+            .property("recurrence", Ref::from_component_name("RecurrenceRule"))
+            .property("timezone", Property::new(ComponentType::String))
+            .property(
+                "next_fire",
+                PropertyBuilder::new()
+                    .component_type(ComponentType::String)
+                    .format(Some(ComponentFormat::DateTime)),
+            )
             .into()
     }
 }
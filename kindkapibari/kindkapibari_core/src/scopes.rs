@@ -1,8 +1,21 @@
+use crate::ParseEnumError;
 use kindkapibari_proc::AttrString;
 use serde::{Deserialize, Serialize};
 use std::ops::{Deref, DerefMut};
 
+// OpenID Connect's own scopes. They aren't ours, `openid` asks for an id_token alongside the
+// tokens and `profile` for who the user is in it.
+pub const OPENID_SCOPE: &str = "openid";
+pub const PROFILE_SCOPE: &str = "profile";
+
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenIdScopes {
+    pub openid: bool,
+    pub profile: bool,
+}
+
 #[derive(Copy, Clone, Hash, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize, AttrString)]
+#[cfg_attr(feature = "server", derive(utoipa::Component))]
 pub enum KKBScope {
     PublicRead,
     BadgesRead,
@@ -14,14 +27,164 @@ pub enum KKBScope {
     RecordsRead,
     OfflineRead,
     // Danger Zone
+    RemindersWrite,
+    SobersWrite,
+    PreferencesWrite,
+    UserdataWrite,
 }
 
-#[derive(Clone, Debug, Hash, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
+impl KKBScope {
+    pub const ALL: [KKBScope; 13] = [
+        KKBScope::PublicRead,
+        KKBScope::BadgesRead,
+        KKBScope::EmailRead,
+        KKBScope::ConnectionsRead,
+        KKBScope::PreferencesRead,
+        KKBScope::UserdataRead,
+        KKBScope::ApplicationsRead,
+        KKBScope::RecordsRead,
+        KKBScope::OfflineRead,
+        KKBScope::RemindersWrite,
+        KKBScope::SobersWrite,
+        KKBScope::PreferencesWrite,
+        KKBScope::UserdataWrite,
+    ];
+
+    // what the user is shown when an app asks for this
+    #[must_use]
+    pub fn description(&self) -> &'static str {
+        match self {
+            KKBScope::PublicRead => "See your username, profile picture and when you joined",
+            KKBScope::BadgesRead => "See your badges",
+            KKBScope::EmailRead => "See your email address",
+            KKBScope::ConnectionsRead => "See which accounts you've connected",
+            KKBScope::PreferencesRead => "See your preferences",
+            KKBScope::UserdataRead => "See your gender, pronouns, birthday, language and timezone",
+            KKBScope::ApplicationsRead => "See the applications you've made",
+            KKBScope::RecordsRead => "See your reminders and sobriety trackers",
+            KKBScope::OfflineRead => "Keep access while you aren't using it",
+            KKBScope::RemindersWrite => "Add, change, snooze and delete your reminders",
+            KKBScope::SobersWrite => "Add, change, reset and delete your sobriety trackers",
+            KKBScope::PreferencesWrite => "Change your preferences",
+            KKBScope::UserdataWrite => {
+                "Change your gender, pronouns, birthday, language and timezone"
+            }
+        }
+    }
+
+    // anything that can change something can see it too
+    #[must_use]
+    pub fn implies(&self) -> &'static [KKBScope] {
+        match self {
+            KKBScope::RemindersWrite | KKBScope::SobersWrite => &[KKBScope::RecordsRead],
+            KKBScope::PreferencesWrite => &[KKBScope::PreferencesRead],
+            KKBScope::UserdataWrite => &[KKBScope::UserdataRead],
+            _ => &[],
+        }
+    }
+
+    // having `self` is enough for `other`
+    #[must_use]
+    pub fn grants(&self, other: &KKBScope) -> bool {
+        self == other || self.implies().contains(other)
+    }
+
+    // the name used in OAuth scope strings, `records-read`
+    #[must_use]
+    pub fn scope_name(&self) -> String {
+        self.to_kebab_case()
+    }
+
+    #[must_use]
+    pub fn from_scope_name(name: &str) -> Option<Self> {
+        KKBScope::ALL
+            .into_iter()
+            .find(|scope| scope.scope_name() == name)
+    }
+}
+
+#[derive(Clone, Debug, Default, Hash, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct KKBScopes {
     int: Vec<KKBScope>,
 }
 
+impl KKBScopes {
+    // A space delimited OAuth scope string, `records-read reminders-write`. Duplicates are dropped
+    // and anything we don't know fails the whole thing.
+    pub fn parse(scopes: &str) -> Result<Self, ParseEnumError> {
+        let mut int = Vec::new();
+        for name in scopes.split_whitespace() {
+            let scope = KKBScope::from_scope_name(name)
+                .ok_or_else(|| ParseEnumError::FailToParse(name.to_string()))?;
+            if !int.contains(&scope) {
+                int.push(scope);
+            }
+        }
+        Ok(Self { int })
+    }
+
+    // Like `parse`, but `openid` and `profile` are let through and handed back on their own.
+    // `profile` shows the username and picture, so it's the same as asking for `public-read` too.
+    pub fn parse_with_openid(scopes: &str) -> Result<(Self, OpenIdScopes), ParseEnumError> {
+        let mut openid = OpenIdScopes::default();
+        let ours = scopes
+            .split_whitespace()
+            .filter(|name| match *name {
+                OPENID_SCOPE => {
+                    openid.openid = true;
+                    false
+                }
+                PROFILE_SCOPE => {
+                    openid.profile = true;
+                    false
+                }
+                _ => true,
+            })
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let mut parsed = Self::parse(&ours)?;
+        if openid.profile && !parsed.contains(&KKBScope::PublicRead) {
+            parsed.push(KKBScope::PublicRead);
+        }
+        Ok((parsed, openid))
+    }
+
+    #[must_use]
+    pub fn to_scope_string(&self) -> String {
+        self.int
+            .iter()
+            .map(KKBScope::scope_name)
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    #[must_use]
+    pub fn grants(&self, scope: &KKBScope) -> bool {
+        self.int.iter().any(|granted| granted.grants(scope))
+    }
+
+    // everything these scopes add up to, implied ones included
+    #[must_use]
+    pub fn expanded(&self) -> Self {
+        let mut int = self.int.clone();
+        for scope in &self.int {
+            for implied in scope.implies() {
+                if !int.contains(implied) {
+                    int.push(*implied);
+                }
+            }
+        }
+        Self { int }
+    }
+}
+
+impl From<Vec<KKBScope>> for KKBScopes {
+    fn from(int: Vec<KKBScope>) -> Self {
+        Self { int }
+    }
+}
+
 impl Deref for KKBScopes {
     type Target = Vec<KKBScope>;
 
@@ -38,3 +201,99 @@ impl DerefMut for KKBScopes {
 
 #[cfg(feature = "server")]
 crate::impl_sea_orm!(KKBScopes, KKBScope);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_scope_strings() {
+        let scopes = KKBScopes::parse("records-read  reminders-write\tpublic-read").unwrap();
+        assert_eq!(
+            scopes,
+            KKBScopes::from(vec![
+                KKBScope::RecordsRead,
+                KKBScope::RemindersWrite,
+                KKBScope::PublicRead
+            ])
+        );
+        assert_eq!(
+            scopes.to_scope_string(),
+            "records-read reminders-write public-read"
+        );
+        assert!(KKBScopes::parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn duplicates_are_dropped() {
+        let scopes = KKBScopes::parse("badges-read public-read badges-read").unwrap();
+        assert_eq!(
+            scopes,
+            KKBScopes::from(vec![KKBScope::BadgesRead, KKBScope::PublicRead])
+        );
+    }
+
+    #[test]
+    fn one_unknown_scope_fails_everything() {
+        assert!(matches!(
+            KKBScopes::parse("public-read launch-missiles badges-read"),
+            Err(ParseEnumError::FailToParse(name)) if name == "launch-missiles"
+        ));
+        // names are kebab case, not the variant
+        assert!(KKBScopes::parse("PublicRead").is_err());
+    }
+
+    #[test]
+    fn writing_grants_reading() {
+        let scopes = KKBScopes::from(vec![KKBScope::RemindersWrite]);
+        assert!(scopes.grants(&KKBScope::RemindersWrite));
+        assert!(scopes.grants(&KKBScope::RecordsRead));
+        assert!(!scopes.grants(&KKBScope::SobersWrite));
+        assert!(!scopes.grants(&KKBScope::PreferencesRead));
+        assert!(!KKBScopes::from(vec![KKBScope::RecordsRead]).grants(&KKBScope::RemindersWrite));
+    }
+
+    #[test]
+    fn expanded_adds_implied_scopes_once() {
+        let scopes = KKBScopes::from(vec![
+            KKBScope::RemindersWrite,
+            KKBScope::SobersWrite,
+            KKBScope::UserdataWrite,
+            KKBScope::UserdataRead,
+        ]);
+        assert_eq!(
+            scopes.expanded(),
+            KKBScopes::from(vec![
+                KKBScope::RemindersWrite,
+                KKBScope::SobersWrite,
+                KKBScope::UserdataWrite,
+                KKBScope::UserdataRead,
+                KKBScope::RecordsRead,
+            ])
+        );
+        assert!(KKBScopes::default().expanded().is_empty());
+    }
+
+    #[test]
+    fn openid_scopes_are_let_through() {
+        let (scopes, openid) = KKBScopes::parse_with_openid("openid records-read profile").unwrap();
+        assert_eq!(
+            scopes,
+            KKBScopes::from(vec![KKBScope::RecordsRead, KKBScope::PublicRead])
+        );
+        assert_eq!(
+            openid,
+            OpenIdScopes {
+                openid: true,
+                profile: true
+            }
+        );
+
+        let (scopes, openid) = KKBScopes::parse_with_openid("openid").unwrap();
+        assert!(scopes.is_empty());
+        assert!(openid.openid && !openid.profile);
+
+        assert!(KKBScopes::parse("openid").is_err());
+        assert!(KKBScopes::parse_with_openid("openid email").is_err());
+    }
+}
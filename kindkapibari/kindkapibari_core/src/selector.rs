@@ -0,0 +1,317 @@
+use crate::{
+    error::KKBCoreError,
+    responses::{Message, Response},
+    tags::Tags,
+    templater::Templater,
+    text::TextContainer,
+};
+use language_tags::LanguageTag;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::collections::VecDeque;
+
+// how many times we try the two stage roll before giving up and saying nothing
+const MAX_ROLLS: usize = 64;
+
+// Picks responses the way the coconutpak docs describe: a response is drawn from the pool, then
+// rolled again against its own probability. Both have to succeed for it to be said.
+#[derive(Clone, Debug)]
+pub struct ResponseSelector<'a, R: Rng> {
+    pool: Vec<&'a Response>,
+    rng: R,
+    recent: VecDeque<usize>,
+    memory: usize,
+}
+
+impl<'a> ResponseSelector<'a, SmallRng> {
+    #[must_use]
+    pub fn new(containers: &'a [TextContainer], language: &LanguageTag, tags: &[Tags]) -> Self {
+        Self::with_rng(containers, language, tags, SmallRng::from_entropy())
+    }
+
+    // same seed + same containers = same responses, in the same order
+    #[must_use]
+    pub fn seeded(
+        containers: &'a [TextContainer],
+        language: &LanguageTag,
+        tags: &[Tags],
+        seed: u64,
+    ) -> Self {
+        Self::with_rng(containers, language, tags, SmallRng::seed_from_u64(seed))
+    }
+}
+
+impl<'a, R: Rng> ResponseSelector<'a, R> {
+    // An empty `tags` matches every container. Languages match on the primary language, so an
+    // `en` container is used for `en-CA`.
+    #[must_use]
+    pub fn with_rng(
+        containers: &'a [TextContainer],
+        language: &LanguageTag,
+        tags: &[Tags],
+        rng: R,
+    ) -> Self {
+        let pool = containers
+            .iter()
+            .filter(|container| tags.is_empty() || tags.contains(container.tags()))
+            .filter(|container| {
+                container.language() == language
+                    || container.language().primary_language() == language.primary_language()
+            })
+            .flat_map(TextContainer::responses)
+            .collect::<Vec<&'a Response>>();
+
+        Self {
+            pool,
+            rng,
+            recent: VecDeque::new(),
+            memory: 1,
+        }
+    }
+
+    // how many of the last responses can't be picked again, 1 stops back-to-back repeats
+    pub fn set_memory(&mut self, memory: usize) {
+        self.memory = memory;
+        self.forget();
+    }
+
+    #[must_use]
+    pub fn pool(&self) -> &[&'a Response] {
+        &self.pool
+    }
+
+    pub fn select(&mut self) -> Option<&'a Response> {
+        self.roll(false)
+    }
+
+    // only responses that are `usable_for_welcome`, for the startup message
+    pub fn select_welcome(&mut self) -> Option<&'a Response> {
+        self.roll(true)
+    }
+
+    // a picked response, rendered for whoever `templater` was made for
+    pub fn say(&mut self, templater: &mut Templater) -> Result<Option<Vec<Message>>, KKBCoreError> {
+        self.select()
+            .map(|response| templater.render_response(response))
+            .transpose()
+    }
+
+    pub fn say_welcome(
+        &mut self,
+        templater: &mut Templater,
+    ) -> Result<Option<Vec<Message>>, KKBCoreError> {
+        self.select_welcome()
+            .map(|response| templater.render_response(response))
+            .transpose()
+    }
+
+    fn roll(&mut self, welcome: bool) -> Option<&'a Response> {
+        let usable = self
+            .pool
+            .iter()
+            .enumerate()
+            .filter(|(_, response)| !welcome || response.usable_for_welcome)
+            .map(|(idx, _)| idx)
+            .collect::<Vec<usize>>();
+        let fresh = usable
+            .iter()
+            .copied()
+            .filter(|idx| !self.recent.contains(idx))
+            .collect::<Vec<usize>>();
+        // if everything was said recently, repeating something beats saying nothing
+        let candidates = if fresh.is_empty() { usable } else { fresh };
+        if candidates.is_empty() {
+            return None;
+        }
+
+        for _ in 0..MAX_ROLLS {
+            let idx = candidates[self.rng.gen_range(0..candidates.len())];
+            let response = self.pool[idx];
+            if self.rng.gen::<f32>() < response.probability.clamp(0.0, 1.0) {
+                self.remember(idx);
+                return Some(response);
+            }
+        }
+        None
+    }
+
+    fn remember(&mut self, idx: usize) {
+        self.recent.push_back(idx);
+        self.forget();
+    }
+
+    fn forget(&mut self) {
+        while self.recent.len() > self.memory {
+            self.recent.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(name: &str, probability: f32, usable_for_welcome: bool) -> Response {
+        Response {
+            name: name.to_string(),
+            messages: vec![],
+            probability,
+            usable_for_welcome,
+        }
+    }
+
+    fn container(tags: Tags, language: &str, responses: Vec<Response>) -> TextContainer {
+        TextContainer::new(
+            "test".to_string(),
+            tags,
+            String::new(),
+            LanguageTag::parse(language).unwrap(),
+            responses,
+        )
+    }
+
+    fn lang(language: &str) -> LanguageTag {
+        LanguageTag::parse(language).unwrap()
+    }
+
+    fn names<'a>(selector: &ResponseSelector<'a, SmallRng>) -> Vec<&'a str> {
+        selector
+            .pool()
+            .iter()
+            .map(|response| response.name.as_str())
+            .collect()
+    }
+
+    fn said(selector: &mut ResponseSelector<'_, SmallRng>, times: usize) -> Vec<String> {
+        (0..times)
+            .map(|_| selector.select().unwrap().name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn filters_on_tags() {
+        let containers = [
+            container(Tags::Advice, "en", vec![response("advice", 1.0, false)]),
+            container(Tags::Humor, "en", vec![response("joke", 1.0, false)]),
+        ];
+        let selector = ResponseSelector::seeded(&containers, &lang("en"), &[Tags::Advice], 1);
+        assert_eq!(names(&selector), ["advice"]);
+        let selector = ResponseSelector::seeded(&containers, &lang("en"), &[], 1);
+        assert_eq!(names(&selector), ["advice", "joke"]);
+        let mut selector = ResponseSelector::seeded(&containers, &lang("en"), &[Tags::Anime], 1);
+        assert!(selector.pool().is_empty());
+        assert!(selector.select().is_none());
+    }
+
+    #[test]
+    fn filters_on_primary_language() {
+        let containers = [
+            container(Tags::Advice, "en", vec![response("en", 1.0, false)]),
+            container(Tags::Advice, "en-CA", vec![response("en-CA", 1.0, false)]),
+            container(Tags::Advice, "ko", vec![response("ko", 1.0, false)]),
+        ];
+        let selector = ResponseSelector::seeded(&containers, &lang("en-CA"), &[], 1);
+        assert_eq!(names(&selector), ["en", "en-CA"]);
+        let selector = ResponseSelector::seeded(&containers, &lang("en-GB"), &[], 1);
+        assert_eq!(names(&selector), ["en", "en-CA"]);
+        let selector = ResponseSelector::seeded(&containers, &lang("ko-KR"), &[], 1);
+        assert_eq!(names(&selector), ["ko"]);
+        let mut selector = ResponseSelector::seeded(&containers, &lang("fr"), &[], 1);
+        assert!(selector.select().is_none());
+    }
+
+    #[test]
+    fn same_seed_same_responses() {
+        let containers = [container(
+            Tags::Advice,
+            "en",
+            (0..8)
+                .map(|n| response(&n.to_string(), 0.5, false))
+                .collect(),
+        )];
+        let mut first = ResponseSelector::seeded(&containers, &lang("en"), &[], 42);
+        let mut second = ResponseSelector::seeded(&containers, &lang("en"), &[], 42);
+        assert_eq!(said(&mut first, 50), said(&mut second, 50));
+    }
+
+    #[test]
+    fn avoids_recent_responses() {
+        let containers = [container(
+            Tags::Advice,
+            "en",
+            vec![
+                response("a", 1.0, false),
+                response("b", 1.0, false),
+                response("c", 1.0, false),
+            ],
+        )];
+        for seed in 0..16 {
+            let mut selector = ResponseSelector::seeded(&containers, &lang("en"), &[], seed);
+            let picked = said(&mut selector, 30);
+            assert!(
+                picked.windows(2).all(|pair| pair[0] != pair[1]),
+                "{picked:?}"
+            );
+
+            selector.set_memory(2);
+            let picked = said(&mut selector, 30);
+            assert!(
+                picked
+                    .windows(3)
+                    .all(|w| w[0] != w[1] && w[1] != w[2] && w[0] != w[2]),
+                "{picked:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn repeats_when_everything_was_said() {
+        let containers = [container(
+            Tags::Advice,
+            "en",
+            vec![response("only", 1.0, false)],
+        )];
+        let mut selector = ResponseSelector::seeded(&containers, &lang("en"), &[], 7);
+        assert_eq!(said(&mut selector, 3), ["only", "only", "only"]);
+    }
+
+    #[test]
+    fn never_says_what_cant_be_rolled() {
+        let containers = [container(
+            Tags::Advice,
+            "en",
+            vec![response("never", 0.0, true)],
+        )];
+        let mut selector = ResponseSelector::seeded(&containers, &lang("en"), &[], 3);
+        assert!(selector.select().is_none());
+        assert!(selector.select_welcome().is_none());
+    }
+
+    #[test]
+    fn welcome_only_picks_welcome_responses() {
+        let containers = [container(
+            Tags::Advice,
+            "en",
+            vec![
+                response("hello", 1.0, true),
+                response("advice", 1.0, false),
+                response("hi", 1.0, true),
+            ],
+        )];
+        for seed in 0..16 {
+            let mut selector = ResponseSelector::seeded(&containers, &lang("en"), &[], seed);
+            for _ in 0..10 {
+                let picked = selector.select_welcome().unwrap();
+                assert!(picked.usable_for_welcome, "{}", picked.name);
+            }
+        }
+
+        let containers = [container(
+            Tags::Advice,
+            "en",
+            vec![response("advice", 1.0, false)],
+        )];
+        let mut selector = ResponseSelector::seeded(&containers, &lang("en"), &[], 1);
+        assert!(selector.select_welcome().is_none());
+        assert!(selector.select().is_some());
+    }
+}
@@ -0,0 +1,78 @@
+use async_trait::async_trait;
+use axum::extract::ConnectInfo;
+use axum_core::extract::{FromRequest, RequestParts};
+use http::header::USER_AGENT;
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+use utoipa::Component;
+
+const MAX_USER_AGENT: usize = 256;
+
+// Where a login came from. Only ever shown back to the user, so the IP is cut down to the network
+// it came from and never stored in full.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionClient {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for SessionClient
+where
+    B: Send,
+{
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let user_agent = req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|user_agent| user_agent.to_str().ok())
+            .map(|user_agent| user_agent.chars().take(MAX_USER_AGENT).collect());
+        // we sit behind a reverse proxy, the last address is the one it added
+        let forwarded = req
+            .headers()
+            .get("X-Forwarded-For")
+            .and_then(|forwarded| forwarded.to_str().ok())
+            .and_then(|forwarded| forwarded.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+        let ip = forwarded.or_else(|| {
+            req.extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        });
+
+        Ok(Self {
+            user_agent,
+            ip: ip.map(coarse_ip),
+        })
+    }
+}
+
+// a /24 for IPv4, a /48 for IPv6
+#[must_use]
+pub fn coarse_ip(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            format!("{a}.{b}.{c}.0/24")
+        }
+        IpAddr::V6(v6) => {
+            let segments = v6.segments();
+            format!("{:x}:{:x}:{:x}::/48", segments[0], segments[1], segments[2])
+        }
+    }
+}
+
+// One login, from the first time the user signed in on it through every refresh since.
+// `id` is the refresh token family.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct Session {
+    pub id: u64,
+    pub created: u64,
+    pub last_used: u64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
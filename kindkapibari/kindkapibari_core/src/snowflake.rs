@@ -1,50 +1,207 @@
-use chrono::{DateTime, Utc};
-use crossbeam::atomic::AtomicCell;
-use std::sync::atomic::{AtomicU16, Ordering};
+use chrono::{DateTime, Duration, Utc};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+};
+use thiserror::Error;
+
+// 1 unused sign bit (ids have to fit in a postgres bigint), 41 bits of milliseconds since the
+// epoch (~69 years), 6 bits of machine ID, 16 bits of sequence
+const TIMESTAMP_BITS: u32 = 41;
+const MACHINE_BITS: u32 = 6;
+const SEQUENCE_BITS: u32 = 16;
+const MACHINE_SHIFT: u32 = SEQUENCE_BITS;
+const TIMESTAMP_SHIFT: u32 = SEQUENCE_BITS + MACHINE_BITS;
+const MAX_TIMESTAMP: u64 = (1 << TIMESTAMP_BITS) - 1;
+const MAX_MACHINE_ID: u8 = (1 << MACHINE_BITS) - 1;
+const MAX_SEQUENCE: u64 = (1 << SEQUENCE_BITS) - 1;
+// small regressions (NTP nudging us) keep counting on the last timestamp, anything bigger is refused
+const MAX_REGRESSION_MS: u64 = 5000;
+
+#[derive(Copy, Clone, Debug, Error, PartialEq, Eq)]
+pub enum SnowflakeError {
+    #[error("Clock went back {0}ms, refusing to generate IDs")]
+    ClockRegressed(u64),
+    #[error("Ran out of timestamp bits, pick a newer epoch")]
+    EpochExhausted,
+}
 
 #[derive(Debug)]
 pub struct SnowflakeIdGenerator {
     epoch: DateTime<Utc>,
-    last: AtomicCell<DateTime<Utc>>,
-    sequence: AtomicU16,
+    // last timestamp << SEQUENCE_BITS | last sequence, swapped as one so threads can't race
+    state: AtomicU64,
     machine_id: u8,
 }
 
 impl SnowflakeIdGenerator {
+    // `None` if the epoch is in the future or the machine ID doesn't fit in 6 bits
     #[must_use]
     pub fn new(epoch: DateTime<Utc>, machine_id: u8) -> Option<Self> {
         let now = Utc::now();
-        if epoch >= now {
+        if epoch >= now || machine_id > MAX_MACHINE_ID {
             return None;
         }
         Some(Self {
             epoch,
-            last: AtomicCell::new(epoch),
-            sequence: AtomicU16::default(),
+            state: AtomicU64::new(0),
             machine_id,
         })
     }
 
     #[allow(clippy::cast_sign_loss)]
-    #[allow(clippy::cast_lossless)]
-    pub fn generate_id(&self) -> u64 {
-        let sequence = self.sequence.load(Ordering::SeqCst);
-        let now = Utc::now();
-        let epoch = self.epoch;
-        let last_gen = self.last.load();
+    fn millis(&self) -> u64 {
+        (Utc::now() - self.epoch).num_milliseconds().max(0) as u64
+    }
+
+    // Never hands out the same ID twice, even across threads. Running out of IDs for a millisecond
+    // waits for the next one, which after a small regression is up to `MAX_REGRESSION_MS` away.
+    // The clock going back further than that is an error the caller can retry later.
+    pub fn generate_id(&self) -> Result<u64, SnowflakeError> {
+        loop {
+            let now = self.millis();
+            let state = self.state.load(Ordering::Acquire);
+            let last = state >> SEQUENCE_BITS;
+            let last_sequence = state & MAX_SEQUENCE;
+
+            if now > MAX_TIMESTAMP {
+                return Err(SnowflakeError::EpochExhausted);
+            }
+            if now + MAX_REGRESSION_MS < last {
+                return Err(SnowflakeError::ClockRegressed(last - now));
+            }
+
+            let (timestamp, sequence) = if now > last {
+                (now, 0)
+            } else if last_sequence < MAX_SEQUENCE {
+                // same millisecond, or the clock went back a little. keep counting from the last
+                // timestamp we used so ids stay unique and increasing.
+                (last, last_sequence + 1)
+            } else {
+                // all taken, wait for the clock to get past `last`
+                if last > now {
+                    thread::sleep(std::time::Duration::from_millis(last - now));
+                } else {
+                    thread::yield_now();
+                }
+                continue;
+            };
 
-        let difference = now - epoch;
-        if now == last_gen {
-            self.sequence.fetch_add(1, Ordering::SeqCst);
+            let next = timestamp << SEQUENCE_BITS | sequence;
+            // only fails if another thread got an id in between, so try again with theirs
+            if self
+                .state
+                .compare_exchange_weak(state, next, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                return Ok(timestamp << TIMESTAMP_SHIFT
+                    | u64::from(self.machine_id) << MACHINE_SHIFT
+                    | sequence);
+            }
         }
-        if now > last_gen {
-            self.sequence.store(0, Ordering::SeqCst);
-            self.last.store(now);
+    }
+
+    // (time it was generated, machine ID, sequence)
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn decode(&self, id: u64) -> (DateTime<Utc>, u8, u16) {
+        let millis = (id >> TIMESTAMP_SHIFT) & MAX_TIMESTAMP;
+        let machine_id = (id >> MACHINE_SHIFT) & u64::from(MAX_MACHINE_ID);
+        let sequence = id & MAX_SEQUENCE;
+        (
+            self.epoch + Duration::milliseconds(millis as i64),
+            machine_id as u8,
+            sequence as u16,
+        )
+    }
+
+    #[must_use]
+    pub fn machine_id(&self) -> u8 {
+        self.machine_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashSet, sync::Arc};
+
+    const THREADS: usize = 8;
+    const IDS_PER_THREAD: usize = 20_000;
+
+    fn generator() -> SnowflakeIdGenerator {
+        SnowflakeIdGenerator::new(Utc::now() - Duration::days(1), 7).unwrap()
+    }
+
+    // as if the last id was handed out `ahead` ms from now
+    fn set_last(generator: &SnowflakeIdGenerator, ahead: u64, sequence: u64) {
+        let last = generator.millis() + ahead;
+        generator
+            .state
+            .store(last << SEQUENCE_BITS | sequence, Ordering::Release);
+    }
+
+    #[test]
+    fn unique_and_increasing_across_threads() {
+        let generator = Arc::new(generator());
+        let per_thread = (0..THREADS)
+            .map(|_| {
+                let generator = generator.clone();
+                thread::spawn(move || {
+                    (0..IDS_PER_THREAD)
+                        .map(|_| generator.generate_id().unwrap())
+                        .collect::<Vec<u64>>()
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<Vec<u64>>>();
+
+        for ids in &per_thread {
+            assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
         }
+        let unique = per_thread.iter().flatten().collect::<HashSet<_>>();
+        assert_eq!(unique.len(), THREADS * IDS_PER_THREAD);
+    }
+
+    #[test]
+    fn decodes_what_it_generates() {
+        let generator = generator();
+        let before = Utc::now();
+        let id = generator.generate_id().unwrap();
+        let (generated, machine_id, _) = generator.decode(id);
+        assert_eq!(machine_id, 7);
+        assert!(generated >= before - Duration::milliseconds(1));
+        assert!(generated <= Utc::now());
+    }
+
+    #[test]
+    fn small_regressions_keep_counting() {
+        let generator = generator();
+        set_last(&generator, 1000, 41);
+        let (_, _, sequence) = generator.decode(generator.generate_id().unwrap());
+        assert_eq!(sequence, 42);
+    }
+
+    #[test]
+    fn big_regressions_are_refused() {
+        let generator = generator();
+        set_last(&generator, MAX_REGRESSION_MS + 1000, 0);
+        assert!(matches!(
+            generator.generate_id(),
+            Err(SnowflakeError::ClockRegressed(ms)) if ms > MAX_REGRESSION_MS
+        ));
+    }
 
-        // store 44 bits of time, 7 bits of machine ID, 16 bits of sequence
-        (difference.num_milliseconds() as u64) << 22
-            | (self.machine_id as u64) << 16 // FIXME: Check if this is right (i am dumb shit)
-            | (sequence as u64)
+    #[test]
+    fn running_out_of_sequence_waits_for_the_clock() {
+        let generator = generator();
+        set_last(&generator, 50, MAX_SEQUENCE);
+        let last = generator.state.load(Ordering::Acquire) >> SEQUENCE_BITS;
+        let id = generator.generate_id().unwrap();
+        assert_eq!(id & MAX_SEQUENCE, 0);
+        assert!(id >> TIMESTAMP_SHIFT > last);
     }
 }
@@ -1,5 +1,7 @@
-use chrono::{DateTime, Utc};
+use crate::{milestones::Milestone, user_data::Timezone};
+use chrono::{DateTime, Datelike, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 // const SOBER_SCHEMA: u64 = 0;
 // const SOBERS_SCHEMA: u64 = 0;
@@ -11,6 +13,9 @@ pub struct Sober {
     pub id: u64,
     pub name: String,
     pub start_time: DateTime<Utc>,
+    // on top of the builtin ones
+    #[serde(default)]
+    pub milestones: Vec<Milestone>,
 }
 
 impl Default for Sober {
@@ -19,6 +24,7 @@ impl Default for Sober {
             id: 0,
             name: "".to_string(),
             start_time: Utc::now(),
+            milestones: vec![],
         }
     }
 }
@@ -29,7 +35,163 @@ pub struct Sobers {
     pub sobers: Vec<Sober>,
 }
 
+// a streak that ended, `started` to `reset`
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::Component))]
+pub struct SoberReset {
+    pub started: DateTime<Utc>,
+    pub reset: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::Component))]
+pub struct SoberMonth {
+    pub year: i32,
+    pub month: u32,
+    pub resets: u32,
+    pub reset_days: Vec<u32>,
+}
+
+// all streak lengths are in seconds
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::Component))]
+pub struct SoberStats {
+    pub id: u64,
+    pub current_streak: i64,
+    pub longest_streak: i64,
+    pub average_streak: i64,
+    pub total_resets: u64,
+    pub calendar: Vec<SoberMonth>,
+}
+
+impl SoberStats {
+    // the calendar is in `timezone`, a reset late in the evening belongs to that day wherever UTC is
+    #[must_use]
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn compute(
+        sober: &Sober,
+        resets: &[SoberReset],
+        timezone: Timezone,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let current_streak = (now - sober.start_time).num_seconds().max(0);
+        let streaks = resets
+            .iter()
+            .map(|reset| (reset.reset - reset.started).num_seconds().max(0))
+            .chain(std::iter::once(current_streak))
+            .collect::<Vec<i64>>();
+
+        let longest_streak = streaks.iter().copied().max().unwrap_or_default();
+        // there is always at least the current streak
+        let average_streak = streaks.iter().sum::<i64>() / streaks.len() as i64;
+
+        let mut months: BTreeMap<(i32, u32), SoberMonth> = BTreeMap::new();
+        for reset in resets {
+            let local = reset.reset.with_timezone(&*timezone);
+            let month = months
+                .entry((local.year(), local.month()))
+                .or_insert_with(|| SoberMonth {
+                    year: local.year(),
+                    month: local.month(),
+                    resets: 0,
+                    reset_days: vec![],
+                });
+            month.resets += 1;
+            if !month.reset_days.contains(&local.day()) {
+                month.reset_days.push(local.day());
+            }
+        }
+        let calendar = months
+            .into_values()
+            .map(|mut month| {
+                month.reset_days.sort_unstable();
+                month
+            })
+            .collect::<Vec<SoberMonth>>();
+
+        Self {
+            id: sober.id,
+            current_streak,
+            longest_streak,
+            average_streak,
+            total_resets: resets.len() as u64,
+            calendar,
+        }
+    }
+}
+
 #[cfg(feature = "server")]
 crate::impl_sea_orm!(Sober, Sobers);
 #[cfg(feature = "server")]
 crate::impl_redis!(Sober, Sobers);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn at(year: i32, month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, 0, 0).unwrap()
+    }
+
+    fn reset(started: DateTime<Utc>, reset: DateTime<Utc>) -> SoberReset {
+        SoberReset { started, reset }
+    }
+
+    #[test]
+    fn streaks() {
+        let now = at(2022, 3, 1, 0);
+        let sober = Sober {
+            start_time: now - Duration::days(4),
+            ..Sober::default()
+        };
+        let resets = [
+            reset(at(2022, 1, 1, 0), at(2022, 1, 11, 0)),
+            reset(at(2022, 1, 11, 0), at(2022, 1, 13, 0)),
+        ];
+        let stats = SoberStats::compute(&sober, &resets, Timezone::default(), now);
+        let days = |days: i64| Duration::days(days).num_seconds();
+        assert_eq!(stats.current_streak, days(4));
+        assert_eq!(stats.longest_streak, days(10));
+        assert_eq!(stats.average_streak, days(16) / 3);
+        assert_eq!(stats.total_resets, 2);
+    }
+
+    #[test]
+    fn nothing_to_average_but_the_current_streak() {
+        let now = at(2022, 3, 1, 0);
+        let sober = Sober {
+            start_time: now - Duration::days(2),
+            ..Sober::default()
+        };
+        let stats = SoberStats::compute(&sober, &[], Timezone::default(), now);
+        assert_eq!(stats.average_streak, stats.current_streak);
+        assert!(stats.calendar.is_empty());
+    }
+
+    #[test]
+    fn calendar_is_in_the_users_timezone() {
+        let sober = Sober::default();
+        // 02:00 UTC on the 1st of February is still the evening of January 31st in Vancouver
+        let resets = [
+            reset(at(2022, 1, 1, 0), at(2022, 1, 5, 12)),
+            reset(at(2022, 1, 5, 12), at(2022, 1, 5, 20)),
+            reset(at(2022, 1, 5, 20), at(2022, 2, 1, 2)),
+        ];
+        let vancouver = "America/Vancouver".parse::<Timezone>().unwrap();
+        let stats = SoberStats::compute(&sober, &resets, vancouver, Utc::now());
+        assert_eq!(
+            stats.calendar,
+            vec![SoberMonth {
+                year: 2022,
+                month: 1,
+                resets: 3,
+                reset_days: vec![5, 31],
+            }]
+        );
+
+        let stats = SoberStats::compute(&sober, &resets, Timezone::default(), Utc::now());
+        assert_eq!(stats.calendar.len(), 2);
+        assert_eq!(stats.calendar[1].reset_days, vec![1]);
+    }
+}
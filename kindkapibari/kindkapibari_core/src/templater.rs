@@ -1,17 +1,488 @@
-// use crate::{error::KKBCoreError, user_data::UserData};
-// use tera::{Context, Tera};
-//
-// #[derive(Clone, Debug)]
-// pub struct Templater {
-//     tera: Tera,
-//     context: Context,
-// }
-//
-// impl Templater {
-//     pub fn new(user_data: &UserData) -> Result<Templater, KKBCoreError> {
-//         let tera = Tera::default();
-//         let context = Context::from_value(serde_json::to_value(user_data).unwrap_or_default())
-//             .map_err(|why| KKBCoreError::TemplateInit(why.to_string()))?;
-//         Ok(Templater { tera, context })
-//     }
-// }
+use crate::{
+    error::KKBCoreError,
+    gender::Gender,
+    preferences::SkinTone,
+    pronouns::PronounForms,
+    responses::{Message, Response},
+    user_data::{Locale, Timezone, UserData},
+};
+use chrono::{DateTime, Locale as DateLocale, Utc};
+use std::{borrow::Cow, collections::HashMap, fmt::Write, time::UNIX_EPOCH};
+use tera::{Context, Tera, Value};
+
+#[derive(Clone, Debug)]
+pub struct Templater {
+    tera: Tera,
+    context: Context,
+}
+
+impl Templater {
+    // `session` keeps any/all pronouns the same for everything rendered in one conversation
+    #[must_use]
+    pub fn new(
+        user_data: &UserData,
+        user_id: u64,
+        username: &str,
+        registered: DateTime<Utc>,
+        skin_tone: SkinTone,
+        session: u64,
+    ) -> Templater {
+        let mut tera = Tera::default();
+        // no birthday is the start of time, as promised by the coconutpak docs
+        let birthday = user_data
+            .birthday
+            .unwrap_or_else(|| DateTime::<Utc>::from(UNIX_EPOCH));
+
+        let dates = Dates {
+            birthday,
+            registered,
+            timezone: user_data.timezone,
+            locale: date_locale(&user_data.locale),
+        };
+        let field_dates = dates.clone();
+        let gender = user_data.gender.clone();
+
+        tera.register_function("genderreplace", move |args: &HashMap<String, Value>| {
+            gender_replace(&gender, args)
+        });
+        tera.register_function("datefmt", move |args: &HashMap<String, Value>| {
+            field_dates.format_field(args)
+        });
+        tera.register_filter(
+            "datefmt",
+            move |value: &Value, args: &HashMap<String, Value>| dates.format_value(value, args),
+        );
+        tera.register_function("efmt", move |args: &HashMap<String, Value>| {
+            emoji_fmt(string_arg(args, "e")?, skin_tone)
+        });
+        tera.register_filter(
+            "efmt",
+            move |value: &Value, _: &HashMap<String, Value>| match value.as_str() {
+                Some(emoji) => emoji_fmt(emoji, skin_tone),
+                None => Err(tera::Error::msg("efmt can only format strings")),
+            },
+        );
+
+        let mut context = Context::new();
+        let pronouns = user_data
+            .pronouns
+            .for_session(user_id, session, &user_data.any_pronouns)
+            .forms(&user_data.locale)
+            .unwrap_or_else(|| PronounForms::name_only(&user_data.locale, username));
+        context.insert("pronouns", &pronouns);
+        context.insert("gender", gender_name(&user_data.gender));
+        context.insert("username", username);
+        context.insert("birthday", &birthday);
+        context.insert("registerdate", &registered);
+        context.insert("langtag", user_data.locale.as_str());
+        context.insert("lang", user_data.locale.primary_language());
+
+        Templater { tera, context }
+    }
+
+    pub fn render(&mut self, template: &str) -> Result<String, KKBCoreError> {
+        self.tera
+            .render_str(&positional_efmt(template), &self.context)
+            .map_err(|why| KKBCoreError::TemplateRender(why.to_string()))
+    }
+
+    // every message of a picked response, the way this user should see it
+    pub fn render_response(&mut self, response: &Response) -> Result<Vec<Message>, KKBCoreError> {
+        response
+            .messages
+            .iter()
+            .map(|message| {
+                Ok(Message {
+                    message: self.render(&message.message)?,
+                    wait_after: message.wait_after,
+                })
+            })
+            .collect()
+    }
+}
+
+const EFMT_CALL: &str = "efmt(";
+
+// The coconutpak docs write `{{efmt(:open_hands:)}}`, but tera functions only take named
+// arguments, so inside `{{ }}` it becomes `efmt(e=":open_hands:")` before tera sees it.
+fn positional_efmt(template: &str) -> Cow<'_, str> {
+    if !template.contains(EFMT_CALL) {
+        return Cow::Borrowed(template);
+    }
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some((before, expression, after)) = split_expression(rest) {
+        out.push_str(before);
+        name_efmt_args(expression, &mut out);
+        rest = after;
+    }
+    out.push_str(rest);
+    Cow::Owned(out)
+}
+
+// the text before, the first `{{ }}` and the text after
+fn split_expression(template: &str) -> Option<(&str, &str, &str)> {
+    let open = template.find("{{")?;
+    let close = open + template[open..].find("}}")? + 2;
+    Some((
+        &template[..open],
+        &template[open..close],
+        &template[close..],
+    ))
+}
+
+fn name_efmt_args(expression: &str, out: &mut String) {
+    let is_quote = |c: char| c == '"' || c == '\'';
+    let mut rest = expression;
+    while let Some(at) = rest.find(EFMT_CALL) {
+        let args = at + EFMT_CALL.len();
+        let end = match rest[args..].find(')') {
+            Some(end) => args + end,
+            None => break,
+        };
+        let arg = rest[args..end].trim();
+        let emoji = arg.trim_matches(is_quote);
+        // part of a longer name, already named or nothing we could quote, tera gets it as is
+        let positional = !rest[..at].ends_with(|c: char| c.is_alphanumeric() || c == '_')
+            && !emoji.is_empty()
+            && !arg.contains('=')
+            && !emoji.contains(is_quote);
+
+        out.push_str(&rest[..at]);
+        if positional {
+            out.push_str("efmt(e=\"");
+            out.push_str(emoji);
+            out.push_str("\")");
+        } else {
+            out.push_str(&rest[at..=end]);
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+}
+
+#[derive(Clone, Debug)]
+struct Dates {
+    birthday: DateTime<Utc>,
+    registered: DateTime<Utc>,
+    timezone: Timezone,
+    locale: DateLocale,
+}
+
+impl Dates {
+    fn format(&self, date: DateTime<Utc>, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let fmt = string_arg(args, "fmt")?;
+        let local = date.with_timezone(&*self.timezone);
+        // chrono panics on bad format strings if we `to_string()` it
+        let mut formatted = String::new();
+        write!(formatted, "{}", local.format_localized(fmt, self.locale))
+            .map_err(|_| tera::Error::msg(format!("bad date format `{fmt}`")))?;
+        Ok(Value::String(formatted))
+    }
+
+    fn format_field(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let date = match string_arg(args, "field")? {
+            "birthday" => self.birthday,
+            "registerdate" => self.registered,
+            other => return Err(tera::Error::msg(format!("`{other}` is not a date field"))),
+        };
+        self.format(date, args)
+    }
+
+    fn format_value(&self, value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let date = value
+            .as_str()
+            .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+            .ok_or_else(|| tera::Error::msg("datefmt can only format dates"))?;
+        self.format(date.with_timezone(&Utc), args)
+    }
+}
+
+fn string_arg<'a>(args: &'a HashMap<String, Value>, name: &str) -> tera::Result<&'a str> {
+    args.get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| tera::Error::msg(format!("missing string argument `{name}`")))
+}
+
+fn gender_name(gender: &Gender) -> &str {
+    match gender {
+        Gender::Man => "man",
+        Gender::Woman => "woman",
+        Gender::NonBinary => "non-binary",
+        Gender::Custom(custom) => custom.as_str(),
+    }
+}
+
+fn gender_replace(gender: &Gender, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let form = match gender {
+        Gender::Man => string_arg(args, "masc")?,
+        Gender::Woman => string_arg(args, "fem")?,
+        Gender::NonBinary => string_arg(args, "nb")?,
+        Gender::Custom(custom) => match args.get("cs").and_then(Value::as_str) {
+            Some(".") => custom.as_str(),
+            Some(form) => form,
+            // no custom form given, neutral is the safest bet
+            None => string_arg(args, "nb")?,
+        },
+    };
+    Ok(Value::String(form.to_string()))
+}
+
+// chrono wants POSIX style names (ko_KR), we have BCP 47 tags (ko-KR or just ko)
+fn date_locale(locale: &Locale) -> DateLocale {
+    let language = locale.primary_language();
+    [
+        locale
+            .region()
+            .map(|region| format!("{language}_{}", region.to_uppercase())),
+        Some(format!("{language}_{}", language.to_uppercase())),
+        Some(language.to_string()),
+    ]
+    .into_iter()
+    .flatten()
+    .find_map(|name| DateLocale::try_from(name.as_str()).ok())
+    .unwrap_or(DateLocale::POSIX)
+}
+
+fn emoji_skin_tone(skin_tone: SkinTone) -> emojis::SkinTone {
+    match skin_tone {
+        SkinTone::Default => emojis::SkinTone::Default,
+        SkinTone::Light => emojis::SkinTone::Light,
+        SkinTone::MediumLight => emojis::SkinTone::MediumLight,
+        SkinTone::Medium => emojis::SkinTone::Medium,
+        SkinTone::MediumDark => emojis::SkinTone::MediumDark,
+        SkinTone::Dark => emojis::SkinTone::Dark,
+    }
+}
+
+// takes either a shortcode (:open_hands:) or the emoji itself
+fn emoji_fmt(emoji: &str, skin_tone: SkinTone) -> tera::Result<Value> {
+    let found = emojis::get_by_shortcode(emoji.trim_matches(':'))
+        .or_else(|| emojis::get(emoji))
+        .ok_or_else(|| tera::Error::msg(format!("unknown emoji `{emoji}`")))?;
+    // not every emoji has skin tones, those stay as they are
+    let toned = found
+        .with_skin_tone(emoji_skin_tone(skin_tone))
+        .unwrap_or(found);
+    Ok(Value::String(toned.as_str().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pronouns::Pronouns;
+    use chrono::TimeZone;
+
+    fn user(gender: Gender, locale: &str, timezone: &str) -> UserData {
+        UserData::new(
+            gender,
+            Pronouns::SheHer,
+            Some(Utc.with_ymd_and_hms(1999, 4, 20, 0, 0, 0).unwrap()),
+            locale.parse().unwrap(),
+            timezone.parse().unwrap(),
+            vec![],
+        )
+    }
+
+    fn templater(user_data: &UserData, skin_tone: SkinTone) -> Templater {
+        let registered = Utc.with_ymd_and_hms(2022, 6, 1, 23, 30, 0).unwrap();
+        Templater::new(user_data, 1, "kapi", registered, skin_tone, 0)
+    }
+
+    fn render(user_data: &UserData, template: &str) -> String {
+        templater(user_data, SkinTone::Default)
+            .render(template)
+            .unwrap()
+    }
+
+    const GENDER_REPLACE: &str = r#"{{genderreplace(fem="girl", masc="boy", nb="kid", cs=".")}}"#;
+
+    #[test]
+    fn genderreplace_picks_the_form() {
+        assert_eq!(
+            render(&user(Gender::Woman, "en", "UTC"), GENDER_REPLACE),
+            "girl"
+        );
+        assert_eq!(
+            render(&user(Gender::Man, "en", "UTC"), GENDER_REPLACE),
+            "boy"
+        );
+        assert_eq!(
+            render(&user(Gender::NonBinary, "en", "UTC"), GENDER_REPLACE),
+            "kid"
+        );
+    }
+
+    #[test]
+    fn genderreplace_custom() {
+        let custom = user(Gender::Custom("demigirl".to_string()), "en", "UTC");
+        assert_eq!(render(&custom, GENDER_REPLACE), "demigirl");
+        assert_eq!(
+            render(
+                &custom,
+                r#"{{genderreplace(fem="girl", masc="boy", nb="kid", cs="cat")}}"#
+            ),
+            "cat"
+        );
+        assert_eq!(
+            render(
+                &custom,
+                r#"{{genderreplace(fem="girl", masc="boy", nb="kid")}}"#
+            ),
+            "kid"
+        );
+    }
+
+    #[test]
+    fn datefmt_field() {
+        let user_data = user(Gender::Woman, "en", "UTC");
+        assert_eq!(
+            render(
+                &user_data,
+                r#"{{datefmt(field="birthday", fmt="%Y-%m-%d")}}"#
+            ),
+            "1999-04-20"
+        );
+        assert!(templater(&user_data, SkinTone::Default)
+            .render(r#"{{datefmt(field="username", fmt="%Y")}}"#)
+            .is_err());
+    }
+
+    #[test]
+    fn datefmt_uses_the_users_timezone_and_language() {
+        let user_data = user(Gender::Woman, "fr-FR", "Asia/Seoul");
+        assert_eq!(
+            render(
+                &user_data,
+                r#"{{datefmt(field="registerdate", fmt="%d %B")}}"#
+            ),
+            "02 juin"
+        );
+    }
+
+    #[test]
+    fn datefmt_filter() {
+        let user_data = user(Gender::Woman, "en", "UTC");
+        assert_eq!(
+            render(&user_data, r#"{{birthday | datefmt(fmt="%Y")}}"#),
+            "1999"
+        );
+        assert_eq!(
+            render(&user_data, r#"{{registerdate | datefmt(fmt="%m/%d")}}"#),
+            "06/01"
+        );
+    }
+
+    #[test]
+    fn no_birthday_is_the_start_of_time() {
+        let mut user_data = user(Gender::Woman, "en", "UTC");
+        user_data.birthday = None;
+        assert_eq!(
+            render(
+                &user_data,
+                r#"{{datefmt(field="birthday", fmt="%Y-%m-%d")}}"#
+            ),
+            "1970-01-01"
+        );
+    }
+
+    #[test]
+    fn efmt_every_way_its_written() {
+        let user_data = user(Gender::Woman, "en", "UTC");
+        let mut templater = templater(&user_data, SkinTone::Dark);
+        for template in [
+            "{{efmt(:open_hands:)}}",
+            r#"{{efmt(":open_hands:")}}"#,
+            r#"{{efmt(e=":open_hands:")}}"#,
+            r#"{{ efmt(e="👐") }}"#,
+            r#"{{":open_hands:" | efmt}}"#,
+        ] {
+            assert_eq!(templater.render(template).unwrap(), "👐🏿", "{template}");
+        }
+    }
+
+    #[test]
+    fn efmt_default_tone_and_toneless_emoji() {
+        let user_data = user(Gender::Woman, "en", "UTC");
+        assert_eq!(render(&user_data, "{{efmt(:open_hands:)}}"), "👐");
+        assert_eq!(
+            templater(&user_data, SkinTone::Light)
+                .render("{{efmt(:turtle:)}}")
+                .unwrap(),
+            "🐢"
+        );
+        assert!(templater(&user_data, SkinTone::Default)
+            .render("{{efmt(:not_an_emoji:)}}")
+            .is_err());
+    }
+
+    #[test]
+    fn positional_efmt_only_touches_expressions() {
+        assert_eq!(positional_efmt("say efmt(:wave:)"), "say efmt(:wave:)");
+        assert_eq!(
+            positional_efmt("{{ myefmt(:wave:) }} {{efmt(:wave:)}}"),
+            r#"{{ myefmt(:wave:) }} {{efmt(e=":wave:")}}"#
+        );
+    }
+
+    #[test]
+    fn variables() {
+        let user_data = user(Gender::Woman, "en-US", "UTC");
+        assert_eq!(
+            render(
+                &user_data,
+                "{{pronouns.nominative}} {{pronouns.reflexive}} {{gender}} {{username}} {{langtag}} {{lang}}"
+            ),
+            "she herself woman kapi en-US en"
+        );
+        assert_eq!(
+            render(&user(Gender::NonBinary, "en", "UTC"), "{{gender}}"),
+            "non-binary"
+        );
+    }
+
+    #[test]
+    fn readme_example() {
+        let user_data = user(Gender::Woman, "en", "UTC");
+        assert_eq!(
+            render(&user_data, r#"Turtle says "Hi {{username}}!""#),
+            r#"Turtle says "Hi kapi!""#
+        );
+    }
+
+    #[test]
+    fn render_response_keeps_the_waits() {
+        let user_data = user(Gender::Woman, "en", "UTC");
+        let response = Response {
+            name: "hi".to_string(),
+            messages: vec![
+                Message {
+                    message: "hi {{username}}".to_string(),
+                    wait_after: 1.5,
+                },
+                Message {
+                    message: "{{efmt(:wave:)}}".to_string(),
+                    wait_after: 0.0,
+                },
+            ],
+            probability: 1.0,
+            usable_for_welcome: true,
+        };
+        let rendered = templater(&user_data, SkinTone::Default)
+            .render_response(&response)
+            .unwrap();
+        assert_eq!(
+            rendered,
+            vec![
+                Message {
+                    message: "hi kapi".to_string(),
+                    wait_after: 1.5,
+                },
+                Message {
+                    message: "👋".to_string(),
+                    wait_after: 0.0,
+                },
+            ]
+        );
+    }
+}
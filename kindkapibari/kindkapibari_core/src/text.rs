@@ -34,6 +34,10 @@ impl TextContainer {
         &self.sub_namespace
     }
     #[must_use]
+    pub fn tags(&self) -> &Tags {
+        &self.tags
+    }
+    #[must_use]
     pub fn language(&self) -> &LanguageTag {
         &self.language
     }
@@ -49,6 +53,9 @@ impl TextContainer {
     pub fn set_sub_namespace(&mut self, sub_namespace: String) {
         self.sub_namespace = sub_namespace;
     }
+    pub fn set_tags(&mut self, tags: Tags) {
+        self.tags = tags;
+    }
     pub fn set_language(&mut self, language: LanguageTag) {
         self.language = language;
     }
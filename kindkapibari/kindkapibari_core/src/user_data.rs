@@ -1,23 +1,36 @@
-use crate::{gender::Gender, pronouns::Pronouns};
+use crate::{
+    gender::Gender,
+    pronouns::{PronounRotation, Pronouns},
+    versioned::{Upgrade, Versioned},
+};
 use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use language_tags::LanguageTag;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::{
+    cmp::Ordering,
     fmt::{Display, Formatter},
     ops::{Deref, DerefMut},
     str::FromStr,
 };
 
-pub const CURRENT_SCHEMA: u64 = 0;
+// 0: gender, pronouns, birthday, locale
+// 1: + timezone, any_pronouns
+pub const USER_DATA_VERSION: u32 = 1;
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 #[cfg_attr(feature = "server", derive(utoipa::Component))]
 pub struct UserData {
-    // pub schema: u64,
     pub gender: Gender,
     pub pronouns: Pronouns,
     pub birthday: Option<DateTime<Utc>>,
     pub locale: Locale,
+    #[serde(default)]
+    pub timezone: Timezone,
+    // what any/all rotates through, empty means every builtin
+    #[serde(default)]
+    pub any_pronouns: Vec<Pronouns>,
 }
 
 impl UserData {
@@ -27,13 +40,16 @@ impl UserData {
         pronouns: Pronouns,
         birthday: Option<DateTime<Utc>>,
         locale: Locale,
+        timezone: Timezone,
+        any_pronouns: Vec<Pronouns>,
     ) -> Self {
         Self {
-            // schema: CURRENT_SCHEMA,
             gender,
             pronouns,
             birthday,
             locale,
+            timezone,
+            any_pronouns,
         }
     }
 
@@ -51,10 +67,29 @@ impl UserData {
 
         let date = self.birthday.unwrap_or_else(Utc::now) > Utc::now();
 
-        gender && pronoun && date
+        let rotation = PronounRotation::from(self.any_pronouns.clone()).verify();
+
+        gender && pronoun && date && rotation
     }
 }
 
+impl Versioned for UserData {
+    const VERSION: u32 = USER_DATA_VERSION;
+    const UPGRADES: &'static [Upgrade] = &[user_data_v0_to_v1];
+}
+
+const _: () = assert!(UserData::UPGRADES.len() == UserData::VERSION as usize);
+
+fn user_data_v0_to_v1(mut data: Value) -> Value {
+    if let Some(fields) = data.as_object_mut() {
+        fields
+            .entry("timezone")
+            .or_insert_with(|| json!(Timezone::default()));
+        fields.entry("any_pronouns").or_insert_with(|| json!([]));
+    }
+    data
+}
+
 impl Default for UserData {
     fn default() -> Self {
         Self {
@@ -62,6 +97,8 @@ impl Default for UserData {
             pronouns: Pronouns::default(),
             birthday: Option::from(Utc::now()),
             locale: LanguageTag::parse("en").unwrap().into(), // Panics: This is a valid locale and thus shouldn't crash.
+            timezone: Timezone::default(),
+            any_pronouns: vec![],
         }
     }
 }
@@ -115,6 +152,62 @@ impl From<Locale> for LanguageTag {
     }
 }
 
+// IANA timezone, e.g. "America/Vancouver"
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::Component))]
+#[cfg_attr(feature = "server", component(value_type = String, default = timezone_default))]
+pub struct Timezone(Tz);
+
+fn timezone_default() -> String {
+    "UTC".to_string()
+}
+
+impl Default for Timezone {
+    fn default() -> Self {
+        Self(Tz::UTC)
+    }
+}
+
+impl PartialOrd for Timezone {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.0.name().partial_cmp(other.0.name())
+    }
+}
+
+impl Deref for Timezone {
+    type Target = Tz;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(Tz::from_str(s)?))
+    }
+}
+
+impl Display for Timezone {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.name())
+    }
+}
+
+impl From<Tz> for Timezone {
+    fn from(tz: Tz) -> Self {
+        Self(tz)
+    }
+}
+
+impl From<Timezone> for Tz {
+    fn from(tz: Timezone) -> Self {
+        tz.0
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[cfg_attr(feature = "server", derive(utoipa::Component))]
 pub struct UserSignupRequest {
@@ -125,6 +218,32 @@ pub struct UserSignupRequest {
 }
 
 #[cfg(feature = "server")]
-crate::impl_redis!(UserData, UserSignupRequest, Locale);
+crate::impl_redis!(UserSignupRequest, Locale, Timezone);
+#[cfg(feature = "server")]
+crate::impl_sea_orm!(UserSignupRequest, Locale, Timezone);
+#[cfg(feature = "server")]
+crate::impl_redis!(versioned UserData);
 #[cfg(feature = "server")]
-crate::impl_sea_orm!(UserData, UserSignupRequest, Locale);
+crate::impl_sea_orm!(versioned UserData);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrades_rows_from_before_timezones() {
+        let row = json!({
+            "gender": { "t": "NonBinary" },
+            "pronouns": { "t": "TheyThem" },
+            "birthday": null,
+            "locale": "en-CA",
+        });
+        let user_data = UserData::from_versioned(row).unwrap();
+        assert_eq!(user_data.timezone, Timezone::default());
+        assert!(user_data.any_pronouns.is_empty());
+
+        let stored = user_data.to_versioned().unwrap();
+        assert_eq!(stored["v"], json!(USER_DATA_VERSION));
+        assert_eq!(UserData::from_versioned(stored).unwrap(), user_data);
+    }
+}
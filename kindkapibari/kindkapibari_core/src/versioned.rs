@@ -0,0 +1,55 @@
+use crate::error::KKBCoreError;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+const VERSION_KEY: &str = "v";
+const DATA_KEY: &str = "d";
+
+// turns a payload of version n into version n + 1
+pub type Upgrade = fn(Value) -> Value;
+
+// Stored as `{"v": VERSION, "d": <payload>}`. On read the payload is walked up through `UPGRADES`
+// until it's current, so old rows keep working after a struct changes. Rows written before we had
+// versions at all are version 0.
+pub trait Versioned: Serialize + DeserializeOwned {
+    const VERSION: u32;
+    // `UPGRADES[n]` upgrades version n, so there are always `VERSION` of them. Put a const assert
+    // next to the impl, see `Preferences`.
+    const UPGRADES: &'static [Upgrade];
+
+    fn to_versioned(&self) -> Result<Value, KKBCoreError> {
+        let data =
+            serde_json::to_value(self).map_err(|why| KKBCoreError::Parse(why.to_string()))?;
+        Ok(json!({ VERSION_KEY: Self::VERSION, DATA_KEY: data }))
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn from_versioned(value: Value) -> Result<Self, KKBCoreError> {
+        let (version, mut data) = split_envelope(value);
+        if version > u64::from(Self::VERSION) {
+            return Err(KKBCoreError::SchemaVersion(version, Self::VERSION));
+        }
+        for upgrade in Self::UPGRADES.iter().skip(version as usize) {
+            data = upgrade(data);
+        }
+        serde_json::from_value(data).map_err(|why| KKBCoreError::Parse(why.to_string()))
+    }
+}
+
+fn split_envelope(value: Value) -> (u64, Value) {
+    match value {
+        Value::Object(mut fields)
+            if fields.len() == 2
+                && fields.get(VERSION_KEY).map_or(false, Value::is_u64)
+                && fields.contains_key(DATA_KEY) =>
+        {
+            let version = fields
+                .remove(VERSION_KEY)
+                .and_then(|version| version.as_u64())
+                .unwrap_or_default();
+            let data = fields.remove(DATA_KEY).unwrap_or_default();
+            (version, data)
+        }
+        unversioned => (0, unversioned),
+    }
+}
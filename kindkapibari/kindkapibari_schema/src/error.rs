@@ -3,6 +3,7 @@ use axum_core::{
     response::{IntoResponse, Response},
 };
 use http::StatusCode;
+use kindkapibari_core::snowflake::SnowflakeError;
 use redis::{ErrorKind, RedisError};
 use sea_orm::error::DbErr;
 use std::borrow::Cow;
@@ -16,6 +17,8 @@ pub enum ServerError {
     RedisError(#[from] RedisError),
     #[error("Internal Server Database Error.")]
     DatabaseError(#[from] DbErr),
+    #[error("Internal Error: {0}")]
+    IdGeneration(#[from] SnowflakeError),
     #[error("fucky wuckyy uwu :c")]
     InternalServer(Box<dyn std::error::Error + Send + Sync>),
     #[error("Internal Error: {0}")]
@@ -90,7 +93,10 @@ impl IntoResponse for ServerError {
             ServerError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
             ServerError::LegalReasons(_) => StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
             ServerError::NotImplemented => StatusCode::NOT_IMPLEMENTED,
-            ServerError::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            // ID generation: the clock will catch up, retrying in a bit works
+            ServerError::Unavailable | ServerError::IdGeneration(_) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
         };
 
         Response::builder()
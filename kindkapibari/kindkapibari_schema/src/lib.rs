@@ -6,7 +6,9 @@
 use crate::error::ServerError;
 
 pub mod error;
+pub mod oauth;
 pub mod redis;
+pub mod refresh;
 pub mod schema;
 
 /// handler error type
@@ -0,0 +1,158 @@
+use crate::{
+    schema::users::{oauth_authorizations, refresh_tokens},
+    SResult,
+};
+use chrono::Utc;
+use kindkapibari_core::secret::{ACCESS_TOKEN_LEEWAY, ACCESS_TOKEN_SECONDS};
+use sea_orm::{sea_query::Expr, ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, Select};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RevokedAuthorizations {
+    // how many were still live
+    pub revoked: u64,
+    // every access token handed out under them that might not have expired yet, deny these
+    pub access_tokens: Vec<u64>,
+}
+
+// Everything `authorizations` matches that's still live, along with its refresh tokens. Both the
+// auth server (replayed refresh tokens, RFC 7009) and the API (the user, deleted apps) go through
+// here so they agree on what revoking takes with it.
+#[allow(clippy::cast_sign_loss)]
+pub async fn revoke_authorizations(
+    db: &impl ConnectionTrait,
+    authorizations: Select<oauth_authorizations::Entity>,
+) -> SResult<RevokedAuthorizations> {
+    let live = authorizations
+        .filter(oauth_authorizations::Column::Revoked.eq(false))
+        .all(db)
+        .await?;
+    if live.is_empty() {
+        return Ok(RevokedAuthorizations::default());
+    }
+    let ids = live
+        .iter()
+        .map(|authorization| authorization.id)
+        .collect::<Vec<u64>>();
+
+    oauth_authorizations::Entity::update_many()
+        .col_expr(oauth_authorizations::Column::Revoked, Expr::value(true))
+        .filter(oauth_authorizations::Column::Id.is_in(ids.clone()))
+        .exec(db)
+        .await?;
+    refresh_tokens::Entity::update_many()
+        .col_expr(refresh_tokens::Column::Revoked, Expr::value(true))
+        .filter(refresh_tokens::Column::Authorization.is_in(ids.clone()))
+        .filter(refresh_tokens::Column::Revoked.eq(false))
+        .exec(db)
+        .await?;
+
+    // every access token but the first came with a refresh token, apps without offline access
+    // never got one so theirs is only on the authorization
+    let cutoff = (Utc::now().timestamp() as u64)
+        .saturating_sub((ACCESS_TOKEN_SECONDS + ACCESS_TOKEN_LEEWAY) as u64);
+    let mut access_tokens = refresh_tokens::Entity::find()
+        .filter(refresh_tokens::Column::Authorization.is_in(ids))
+        .filter(refresh_tokens::Column::Created.gt(cutoff))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|token| token.related)
+        .chain(live.iter().map(|authorization| authorization.access_token))
+        .collect::<Vec<u64>>();
+    access_tokens.sort_unstable();
+    access_tokens.dedup();
+
+    Ok(RevokedAuthorizations {
+        revoked: live.len() as u64,
+        access_tokens,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kindkapibari_core::{
+        scopes::{KKBScope, KKBScopes},
+        secret::{RefreshClaims, TokenClaims},
+    };
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult};
+
+    fn authorization(id: u64, access_token: u64) -> oauth_authorizations::Model {
+        let now = Utc::now();
+        oauth_authorizations::Model {
+            id,
+            owner: 1,
+            application: 2,
+            scopes: KKBScopes::from(vec![KKBScope::OfflineRead]),
+            expire: now,
+            created: now,
+            last_used: now,
+            access_token,
+            revoked: false,
+        }
+    }
+
+    fn refresh_token(id: u64, authorization: u64, related: u64) -> refresh_tokens::Model {
+        refresh_tokens::Model {
+            id,
+            owner: 1,
+            related,
+            family: authorization,
+            authorization: Some(authorization),
+            expire: u64::MAX,
+            created: u64::MAX,
+            revoked: true,
+            session_start: 0,
+            last_used: 0,
+            user_agent: None,
+            ip: None,
+            stored_secret: RefreshClaims::from(TokenClaims::new().set_id(id)),
+        }
+    }
+
+    fn rows(rows_affected: u64) -> MockExecResult {
+        MockExecResult {
+            last_insert_id: 0,
+            rows_affected,
+        }
+    }
+
+    #[tokio::test]
+    async fn every_live_access_token_is_denied() {
+        // 10 was refreshed twice (21 then 22), 11 never had offline access
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results(vec![vec![authorization(10, 22), authorization(11, 30)]])
+            .append_exec_results(vec![rows(2), rows(1)])
+            .append_query_results(vec![vec![
+                refresh_token(1, 10, 20),
+                refresh_token(2, 10, 21),
+                refresh_token(3, 10, 22),
+            ]])
+            .into_connection();
+
+        assert_eq!(
+            revoke_authorizations(&db, oauth_authorizations::Entity::find())
+                .await
+                .unwrap(),
+            RevokedAuthorizations {
+                revoked: 2,
+                access_tokens: vec![20, 21, 22, 30],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn nothing_live_touches_nothing() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results(vec![Vec::<oauth_authorizations::Model>::new()])
+            .into_connection();
+
+        assert_eq!(
+            revoke_authorizations(&db, oauth_authorizations::Entity::find())
+                .await
+                .unwrap(),
+            RevokedAuthorizations::default()
+        );
+        assert_eq!(db.into_transaction_log().len(), 1);
+    }
+}
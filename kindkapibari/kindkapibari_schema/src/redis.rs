@@ -1,8 +1,17 @@
-use crate::SResult;
+use crate::{error::ServerError, SResult};
+use kindkapibari_core::{
+    cache::Cached,
+    oauth::{AuthorizationCode, AUTHORIZATION_CODE_SECONDS},
+    secret::{ACCESS_TOKEN_LEEWAY, ACCESS_TOKEN_SECONDS},
+};
 use redis::{aio::ConnectionManager, AsyncCommands, FromRedisValue, ToRedisArgs};
-use std::{fmt::Debug, sync::Arc};
+use std::{borrow::Cow, fmt::Debug, sync::Arc};
 use tracing::instrument;
 
+pub const ACCESS_DENYLIST_PREFIX: &str = "dl";
+pub const AUTHORIZATION_CODE_PREFIX: &str = "ac";
+pub const CLIENT_TOKENS_PREFIX: &str = "ct";
+
 pub trait RedisState: Debug + Sized + Send + Sync {
     fn redis(&self) -> &ConnectionManager;
     fn redis_owned(&self) -> ConnectionManager {
@@ -11,13 +20,16 @@ pub trait RedisState: Debug + Sized + Send + Sync {
 }
 
 #[instrument]
-pub async fn insert_into_cache(
+pub async fn insert_into_cache<V: Cached + Debug + Send + Sync>(
     state: Arc<impl RedisState>,
     key: impl ToRedisArgs + Debug + Send + Sync,
-    value: impl ToRedisArgs + Debug + Send + Sync,
+    value: &V,
     timeout: Option<usize>,
 ) -> SResult<()> {
-    state.redis_owned().set(&key, value).await?;
+    let data = value
+        .to_cache()
+        .map_err(|why| ServerError::ISErr(Cow::from(why.to_string())))?;
+    state.redis_owned().set(&key, data).await?;
     if timeout.is_some() {
         ref_red_cac_raw(state, key, timeout).await?;
     }
@@ -61,8 +73,177 @@ pub async fn read_from_cache<T>(
     key: impl ToRedisArgs + Debug + Send + Sync,
 ) -> SResult<T>
 where
-    T: FromRedisValue + Debug + Send + Sync,
+    T: Cached + Debug + Send + Sync,
 {
-    let dbresult: T = state.redis_owned().get(key).await?;
-    Ok(dbresult)
+    let data: Option<Vec<u8>> = state.redis_owned().get(&key).await?;
+    let data =
+        data.ok_or_else(|| ServerError::NotFound(Cow::from("cache entry"), Cow::from(T::TAG)))?;
+    if let Some(value) = decode_entry(&data)? {
+        return Ok(value);
+    }
+    let _: () = state.redis_owned().del(&key).await?;
+    Err(ServerError::NotFound(
+        Cow::from("cache entry"),
+        Cow::from(T::TAG),
+    ))
+}
+
+// `None` if it was written by an older (or newer) build, it's never going to decode so it should
+// be evicted and treated as a miss
+fn decode_entry<T: Cached>(data: &[u8]) -> SResult<Option<T>> {
+    match T::from_cache(data) {
+        Ok(value) => Ok(Some(value)),
+        Err(why) if why.is_incompatible() => {
+            tracing::warn!("evicting incompatible cache entry: {why}");
+            Ok(None)
+        }
+        Err(why) => Err(ServerError::ISErr(Cow::from(why.to_string()))),
+    }
+}
+
+// Access tokens can't be taken back once they're out, so revoked ones are remembered here until
+// they would have expired anyway.
+#[instrument]
+pub async fn deny_access_token(state: Arc<impl RedisState>, jti: u64) -> SResult<()> {
+    Ok(state
+        .redis_owned()
+        .set_ex(
+            format!("{ACCESS_DENYLIST_PREFIX}{jti}"),
+            true,
+            ACCESS_TOKEN_SECONDS + ACCESS_TOKEN_LEEWAY,
+        )
+        .await?)
+}
+
+#[instrument]
+pub async fn is_access_token_denied(state: Arc<impl RedisState>, jti: u64) -> SResult<bool> {
+    Ok(state
+        .redis_owned()
+        .exists(format!("{ACCESS_DENYLIST_PREFIX}{jti}"))
+        .await?)
+}
+
+// Client credentials tokens aren't stored with an authorization, so this is where they're found
+// again when the app's secret is rotated or it's deleted. Kept as long as the newest could be live.
+#[instrument]
+pub async fn record_client_token(
+    state: Arc<impl RedisState>,
+    application: u64,
+    jti: u64,
+) -> SResult<()> {
+    let key = format!("{CLIENT_TOKENS_PREFIX}{application}");
+    Ok(redis::pipe()
+        .atomic()
+        .sadd(&key, jti)
+        .ignore()
+        .expire(&key, ACCESS_TOKEN_SECONDS + ACCESS_TOKEN_LEEWAY)
+        .ignore()
+        .query_async(&mut state.redis_owned())
+        .await?)
+}
+
+// every client credentials token the application could still be using, forgotten once returned
+#[instrument]
+pub async fn take_client_tokens(
+    state: Arc<impl RedisState>,
+    application: u64,
+) -> SResult<Vec<u64>> {
+    let key = format!("{CLIENT_TOKENS_PREFIX}{application}");
+    let (tokens,): (Vec<u64>,) = redis::pipe()
+        .atomic()
+        .smembers(&key)
+        .del(&key)
+        .ignore()
+        .query_async(&mut state.redis_owned())
+        .await?;
+    Ok(tokens)
+}
+
+#[instrument(skip(code))]
+pub async fn store_authorization_code(
+    state: Arc<impl RedisState>,
+    code: &str,
+    grant: &AuthorizationCode,
+) -> SResult<()> {
+    insert_into_cache(
+        state,
+        format!("{AUTHORIZATION_CODE_PREFIX}{code}"),
+        grant,
+        Some(AUTHORIZATION_CODE_SECONDS),
+    )
+    .await
+}
+
+// `None` if the code expired, never existed or was already used
+#[instrument(skip(code))]
+pub async fn redeem_authorization_code(
+    state: Arc<impl RedisState>,
+    code: &str,
+) -> SResult<Option<AuthorizationCode>> {
+    let key = format!("{AUTHORIZATION_CODE_PREFIX}{code}");
+    let grant = match read_from_cache::<AuthorizationCode>(state.clone(), &key).await {
+        Ok(grant) => grant,
+        Err(ServerError::NotFound(_, _)) => return Ok(None),
+        Err(why) => return Err(why),
+    };
+    // only whoever manages to delete it gets to use it, so a code can't be redeemed twice
+    let deleted: u64 = state.redis_owned().del(&key).await?;
+    Ok(if deleted == 1 { Some(grant) } else { None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kindkapibari_core::{cache::CacheError, scopes::KKBScopes};
+
+    fn grant() -> AuthorizationCode {
+        AuthorizationCode {
+            user: 1,
+            application: 2,
+            redirect_uri: "https://example.com/callback".to_string(),
+            scopes: KKBScopes::default(),
+            code_challenge: String::new(),
+            openid: None,
+        }
+    }
+
+    // `AuthorizationCode` as it was before it had a version
+    #[derive(Debug)]
+    struct OldGrant(AuthorizationCode);
+
+    impl Cached for OldGrant {
+        const TAG: &'static str = AuthorizationCode::TAG;
+        const VERSION: u32 = 0;
+
+        fn encode_payload(&self) -> Result<Vec<u8>, CacheError> {
+            self.0.encode_payload()
+        }
+
+        fn decode_payload(_: &[u8], _: u32) -> Result<Self, CacheError> {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn current_entries_are_hits() {
+        let cached = grant().to_cache().unwrap();
+        assert_eq!(
+            decode_entry::<AuthorizationCode>(&cached).unwrap(),
+            Some(grant())
+        );
+    }
+
+    #[test]
+    fn other_versions_are_evicted() {
+        let cached = OldGrant(grant()).to_cache().unwrap();
+        assert_eq!(decode_entry::<AuthorizationCode>(&cached).unwrap(), None);
+    }
+
+    #[test]
+    fn garbage_is_evicted() {
+        assert_eq!(
+            decode_entry::<AuthorizationCode>(b"not ours").unwrap(),
+            None
+        );
+    }
 }
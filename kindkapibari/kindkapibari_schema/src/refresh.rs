@@ -0,0 +1,184 @@
+use crate::{schema::users::refresh_tokens, SResult};
+use chrono::Utc;
+use kindkapibari_core::secret::{ACCESS_TOKEN_LEEWAY, ACCESS_TOKEN_SECONDS};
+use sea_orm::{sea_query::Expr, ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    Rotated,
+    // the token had already been rotated (or revoked) and its whole family is gone now
+    Replayed(RevokedFamily),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RevokedFamily {
+    // how many refresh tokens were still live
+    pub revoked: u64,
+    // handed out next to the family's refresh tokens and maybe not expired yet, deny these
+    pub access_tokens: Vec<u64>,
+}
+
+// Only one refresh can flip `revoked`, so two racing requests can't both rotate the same token.
+// If it was already flipped someone is replaying an old token. We can't tell if it's the user or
+// whoever stole it, so the whole family goes and the user has to log in again.
+pub async fn rotate_refresh_token(
+    db: &impl ConnectionTrait,
+    token: &refresh_tokens::Model,
+) -> SResult<Rotation> {
+    let rotated = refresh_tokens::Entity::update_many()
+        .col_expr(refresh_tokens::Column::Revoked, Expr::value(true))
+        .filter(refresh_tokens::Column::Id.eq(token.id))
+        .filter(refresh_tokens::Column::Revoked.eq(false))
+        .exec(db)
+        .await?;
+    if rotated.rows_affected == 0 {
+        return Ok(Rotation::Replayed(
+            revoke_refresh_family(db, token.family).await?,
+        ));
+    }
+    Ok(Rotation::Rotated)
+}
+
+#[allow(clippy::cast_sign_loss)]
+pub async fn revoke_refresh_family(
+    db: &impl ConnectionTrait,
+    family: u64,
+) -> SResult<RevokedFamily> {
+    let revoked = refresh_tokens::Entity::update_many()
+        .col_expr(refresh_tokens::Column::Revoked, Expr::value(true))
+        .filter(refresh_tokens::Column::Family.eq(family))
+        .filter(refresh_tokens::Column::Revoked.eq(false))
+        .exec(db)
+        .await?;
+
+    let cutoff = (Utc::now().timestamp() as u64)
+        .saturating_sub((ACCESS_TOKEN_SECONDS + ACCESS_TOKEN_LEEWAY) as u64);
+    let access_tokens = refresh_tokens::Entity::find()
+        .filter(refresh_tokens::Column::Family.eq(family))
+        .filter(refresh_tokens::Column::Created.gt(cutoff))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|token| token.related)
+        .collect();
+
+    Ok(RevokedFamily {
+        revoked: revoked.rows_affected,
+        access_tokens,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kindkapibari_core::secret::{RefreshClaims, TokenClaims};
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult, Transaction};
+
+    const FAMILY: u64 = 100;
+
+    // `id` was rotated into `id + 1` and so on, all in the same family
+    fn token(id: u64) -> refresh_tokens::Model {
+        refresh_tokens::Model {
+            id,
+            owner: 1,
+            related: id + 1000,
+            family: FAMILY,
+            authorization: None,
+            expire: u64::MAX,
+            created: u64::MAX,
+            revoked: false,
+            session_start: 0,
+            last_used: 0,
+            user_agent: None,
+            ip: None,
+            stored_secret: RefreshClaims::from(TokenClaims::new().set_id(id)),
+        }
+    }
+
+    fn rows(rows_affected: u64) -> MockExecResult {
+        MockExecResult {
+            last_insert_id: 0,
+            rows_affected,
+        }
+    }
+
+    fn rotate_statement(id: u64) -> Transaction {
+        Transaction::from_sql_and_values(
+            DatabaseBackend::Postgres,
+            r#"UPDATE "refresh_tokens" SET "revoked" = $1 WHERE "refresh_tokens"."id" = $2 AND "refresh_tokens"."revoked" = $3"#,
+            vec![true.into(), id.into(), false.into()],
+        )
+    }
+
+    fn revoke_family_statement() -> Transaction {
+        Transaction::from_sql_and_values(
+            DatabaseBackend::Postgres,
+            r#"UPDATE "refresh_tokens" SET "revoked" = $1 WHERE "refresh_tokens"."family" = $2 AND "refresh_tokens"."revoked" = $3"#,
+            vec![true.into(), FAMILY.into(), false.into()],
+        )
+    }
+
+    #[tokio::test]
+    async fn rotating_uses_up_the_token() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results(vec![rows(1)])
+            .into_connection();
+
+        assert_eq!(
+            rotate_refresh_token(&db, &token(1)).await.unwrap(),
+            Rotation::Rotated
+        );
+        assert_eq!(db.into_transaction_log(), vec![rotate_statement(1)]);
+    }
+
+    #[tokio::test]
+    async fn replaying_a_rotated_token_revokes_its_family() {
+        // 1 is rotated into 2, then 1 shows up again
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results(vec![rows(1), rows(0), rows(1)])
+            .append_query_results(vec![vec![token(1), token(2)]])
+            .into_connection();
+
+        assert_eq!(
+            rotate_refresh_token(&db, &token(1)).await.unwrap(),
+            Rotation::Rotated
+        );
+        assert_eq!(
+            rotate_refresh_token(&db, &token(1)).await.unwrap(),
+            Rotation::Replayed(RevokedFamily {
+                revoked: 1,
+                access_tokens: vec![1001, 1002],
+            })
+        );
+
+        let log = db.into_transaction_log();
+        assert_eq!(
+            log[..3],
+            [
+                rotate_statement(1),
+                rotate_statement(1),
+                revoke_family_statement()
+            ]
+        );
+        // and then which access tokens to deny
+        assert_eq!(log.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn the_newest_token_is_refused_after_a_replay() {
+        // 2 was live until 1 got replayed, now it's revoked like the rest of the family
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results(vec![rows(0), rows(0)])
+            .append_query_results(vec![Vec::<refresh_tokens::Model>::new()])
+            .into_connection();
+
+        assert_eq!(
+            rotate_refresh_token(&db, &token(2)).await.unwrap(),
+            Rotation::Replayed(RevokedFamily::default())
+        );
+        assert_eq!(
+            db.into_transaction_log()[..2],
+            [rotate_statement(2), revoke_family_statement()]
+        );
+    }
+}
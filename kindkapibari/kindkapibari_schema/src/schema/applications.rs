@@ -1,4 +1,4 @@
-use kindkapibari_core::scopes::KKBScopes;
+use kindkapibari_core::{application::RedirectUris, scopes::KKBScopes};
 use sea_orm::{prelude::*, EnumIter};
 use serde::{Deserialize, Serialize};
 
@@ -15,10 +15,12 @@ pub struct Model {
     #[sea_orm(column_type = "Text", nullable)]
     pub description: Option<String>,
     pub homepage: String,
-    pub callback: String,
+    #[sea_orm(column_type = "JsonBinary")]
+    pub redirect_uris: RedirectUris,
     pub logo: String,
-    // #[sea_orm(column_type = "JsonBinary", indexed, nullable)]
-    // pub signed_secret: Option<StoredSecret>,
+    // argon2 PHC string of the client secret, only confidential apps have one
+    #[sea_orm(column_type = "Text", nullable)]
+    pub secret_hash: Option<String>,
     #[sea_orm(column_type = "JsonBinary")]
     pub scopes: KKBScopes,
     pub confidential: bool,
@@ -17,6 +17,9 @@ pub struct Model {
     pub twitter_id: Option<u64>,
     #[sea_orm(column_type = "Text", nullable)]
     pub reddit_id: Option<String>,
+    // `{account id}@{instance}`, see the auth server's fediverse login
+    #[sea_orm(column_type = "Text", indexed, nullable)]
+    pub fediverse_id: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter)]
@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    prelude::{DeriveEntityModel, EntityTrait, PrimaryKeyTrait, RelationTrait},
+    ActiveModelBehavior, DerivePrimaryKey, EnumIter, IdenStatic, Related, RelationDef,
+};
+use serde::{Deserialize, Serialize};
+
+// An account on a login provider that logs in as this user. `provider` is the name it's
+// configured under, so renaming one in the config unlinks everyone that used it.
+#[derive(
+    Clone, Debug, Hash, PartialOrd, PartialEq, Eq, Serialize, Deserialize, DeriveEntityModel,
+)]
+#[sea_orm(table_name = "identities")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false, column_type = "Text")]
+    pub provider: String,
+    // the user's ID on the provider, `sub` for OIDC ones
+    #[sea_orm(primary_key, auto_increment = false, column_type = "Text")]
+    pub subject: String,
+    #[sea_orm(indexed)]
+    pub user_id: u64,
+    pub linked: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter)]
+pub enum Relation {
+    User,
+}
+
+impl RelationTrait for Relation {
+    fn def(&self) -> RelationDef {
+        match self {
+            Self::User => Entity::belongs_to(super::user::Entity)
+                .from(Column::UserId)
+                .to(super::user::Column::Id)
+                .into(),
+        }
+    }
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
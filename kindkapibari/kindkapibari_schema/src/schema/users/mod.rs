@@ -3,12 +3,16 @@ use serde::{Deserialize, Serialize};
 
 pub mod badges;
 pub mod connections;
+pub mod identities;
 pub mod oauth_authorizations;
 pub mod onetime_reminders;
 pub mod passwords;
 pub mod preferences;
 pub mod recurring_reminders;
 pub mod refresh_tokens;
+pub mod reminder_events;
+pub mod sober_milestones;
+pub mod sober_resets;
 pub mod sobers;
 pub mod statistics;
 pub mod user;
@@ -20,4 +24,5 @@ pub struct AuthorizedUser {
     pub user: user::Model,
 }
 
-impl_redis!(AuthorizedUser);
+// 1: user::Model 1
+impl_redis!(AuthorizedUser => 1);
@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc};
+use kindkapibari_core::scopes::KKBScopes;
 use sea_orm::{
     prelude::{DeriveEntityModel, EntityTrait, PrimaryKeyTrait, RelationTrait},
     ActiveModelBehavior, DerivePrimaryKey, EnumIter, IdenStatic, Related, RelationDef,
@@ -10,16 +11,21 @@ use serde::{Deserialize, Serialize};
 )]
 #[sea_orm(table_name = "oauth_authorizations")]
 pub struct Model {
+    // also the family of the refresh tokens handed out under it
     #[sea_orm(primary_key)]
     pub id: u64,
+    #[sea_orm(indexed)]
     pub owner: u64,
+    #[sea_orm(indexed)]
     pub application: u64,
+    #[sea_orm(column_type = "JsonBinary")]
+    pub scopes: KKBScopes,
     pub expire: DateTime<Utc>,
     pub created: DateTime<Utc>,
-    // #[sea_orm(unique, indexed)]
-    // pub access_token: StoredSecret,
-    // #[sea_orm(unique, indexed, nullable)]
-    // pub refresh_token: Option<StoredSecret>,
+    pub last_used: DateTime<Utc>,
+    // the newest access token's ID, so it can be denied when this is revoked
+    pub access_token: u64,
+    pub revoked: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter)]
@@ -49,4 +55,10 @@ impl Related<super::user::Entity> for Entity {
     }
 }
 
+impl Related<super::super::applications::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Application.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
@@ -1,4 +1,9 @@
 use chrono::NaiveTime;
+use kindkapibari_core::{
+    recurrence::RecurrenceRule,
+    reminder::{u8_bitflag_to_days, RecurringReminder},
+    user_data::Timezone,
+};
 use sea_orm::{
     prelude::{DeriveEntityModel, EntityTrait, PrimaryKeyTrait, RelationTrait},
     ActiveModelBehavior, DerivePrimaryKey, EnumIter, IdenStatic, Related, RelationDef,
@@ -19,6 +24,23 @@ pub struct Model {
     pub name: String,
     pub days: u8, // use a u8 bitflag, see u8_bitflag_to_days
     pub time: NaiveTime,
+    pub recurrence: Option<RecurrenceRule>, // if this is None, fall back to `days`
+    pub timezone: Option<Timezone>,         // if this is None, use the owner's timezone
+}
+
+impl Model {
+    #[must_use]
+    pub fn into_recurring_reminder(self) -> RecurringReminder {
+        RecurringReminder {
+            id: self.id,
+            name: self.name,
+            time: self.time,
+            days: u8_bitflag_to_days(self.days),
+            recurrence: self.recurrence,
+            timezone: self.timezone,
+            next_fire: None,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialOrd, PartialEq, Eq, EnumIter)]
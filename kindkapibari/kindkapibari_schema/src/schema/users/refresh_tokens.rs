@@ -12,9 +12,22 @@ pub struct Model {
     pub id: u64,
     pub owner: u64,
     pub related: u64,
+    // the ID of the first refresh token in the rotation chain this one came from
+    #[sea_orm(indexed)]
+    pub family: u64,
+    // the OAuth authorization this was handed out under, `None` for login sessions
+    #[sea_orm(indexed, nullable)]
+    pub authorization: Option<u64>,
     pub expire: u64,
     pub created: u64,
     pub revoked: bool,
+    // carried over on every rotation, so it's when the user actually logged in
+    pub session_start: u64,
+    pub last_used: u64,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub user_agent: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub ip: Option<String>,
     #[sea_orm(column_type = "JsonBinary", unique, indexed)]
     pub stored_secret: RefreshClaims,
 }
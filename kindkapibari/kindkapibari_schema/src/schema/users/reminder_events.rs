@@ -0,0 +1,49 @@
+use chrono::{DateTime, Utc};
+use kindkapibari_core::delivery::{DeliveryState, ReminderKind};
+use sea_orm::{
+    prelude::{DeriveEntityModel, EntityTrait, PrimaryKeyTrait, RelationTrait},
+    ActiveModelBehavior, DerivePrimaryKey, EnumIter, IdenStatic, Related, RelationDef,
+};
+use serde::{Deserialize, Serialize};
+
+// every time a reminder fires, one of these gets written
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "reminder_events")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u64,
+    pub owner: u64,
+    #[sea_orm(column_type = "JsonBinary")]
+    pub kind: ReminderKind,
+    pub reminder: u64,
+    #[sea_orm(column_type = "Text")]
+    pub name: String,
+    pub fire_at: DateTime<Utc>,
+    #[sea_orm(column_type = "JsonBinary")]
+    pub state: DeliveryState,
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, PartialOrd, PartialEq, Eq, EnumIter)]
+pub enum Relation {
+    User,
+}
+
+impl RelationTrait for Relation {
+    fn def(&self) -> RelationDef {
+        match self {
+            Relation::User => Entity::belongs_to(super::user::Entity)
+                .from(Column::Owner)
+                .to(super::user::Column::Id)
+                .into(),
+        }
+    }
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,59 @@
+use chrono::{DateTime, Utc};
+use kindkapibari_core::milestones::Milestone;
+use sea_orm::{
+    prelude::{DeriveEntityModel, EntityTrait, PrimaryKeyTrait, RelationTrait},
+    ActiveModelBehavior, DerivePrimaryKey, EnumIter, IdenStatic, Related, RelationDef,
+};
+use serde::{Deserialize, Serialize};
+
+// milestones a streak has reached, `streak_start` is the `time_since_reset` of that streak
+#[derive(
+    Clone, Debug, Hash, PartialOrd, PartialEq, Eq, Serialize, Deserialize, DeriveEntityModel,
+)]
+#[sea_orm(table_name = "sober_milestones")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u64,
+    #[sea_orm(indexed)]
+    pub sober: u64,
+    pub owner: u64,
+    #[sea_orm(column_type = "JsonBinary")]
+    pub milestone: Milestone,
+    pub streak_start: DateTime<Utc>,
+    pub reached: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, PartialOrd, PartialEq, Eq, EnumIter)]
+pub enum Relation {
+    Sober,
+    User,
+}
+
+impl RelationTrait for Relation {
+    fn def(&self) -> RelationDef {
+        match self {
+            Relation::Sober => Entity::belongs_to(super::sobers::Entity)
+                .from(Column::Sober)
+                .to(super::sobers::Column::Id)
+                .into(),
+            Relation::User => Entity::belongs_to(super::user::Entity)
+                .from(Column::Owner)
+                .to(super::user::Column::Id)
+                .into(),
+        }
+    }
+}
+
+impl Related<super::sobers::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Sober.def()
+    }
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,56 @@
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    prelude::{DeriveEntityModel, EntityTrait, PrimaryKeyTrait, RelationTrait},
+    ActiveModelBehavior, DerivePrimaryKey, EnumIter, IdenStatic, Related, RelationDef,
+};
+use serde::{Deserialize, Serialize};
+
+// append only, one row for every time a sober was reset
+#[derive(
+    Clone, Debug, Hash, PartialOrd, PartialEq, Eq, Serialize, Deserialize, DeriveEntityModel,
+)]
+#[sea_orm(table_name = "sober_resets")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u64,
+    #[sea_orm(indexed)]
+    pub sober: u64,
+    pub owner: u64,
+    pub started: DateTime<Utc>,
+    pub reset: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, PartialOrd, PartialEq, Eq, EnumIter)]
+pub enum Relation {
+    Sober,
+    User,
+}
+
+impl RelationTrait for Relation {
+    fn def(&self) -> RelationDef {
+        match self {
+            Relation::Sober => Entity::belongs_to(super::sobers::Entity)
+                .from(Column::Sober)
+                .to(super::sobers::Column::Id)
+                .into(),
+            Relation::User => Entity::belongs_to(super::user::Entity)
+                .from(Column::Owner)
+                .to(super::user::Column::Id)
+                .into(),
+        }
+    }
+}
+
+impl Related<super::sobers::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Sober.def()
+    }
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
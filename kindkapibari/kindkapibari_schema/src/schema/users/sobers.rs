@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc};
+use kindkapibari_core::{milestones::Milestones, sober::Sober};
 use sea_orm::{
     prelude::{DeriveEntityModel, EntityTrait, PrimaryKeyTrait, RelationTrait},
     ActiveModelBehavior, DerivePrimaryKey, EnumIter, IdenStatic, Related, RelationDef,
@@ -16,16 +17,34 @@ pub struct Model {
     #[sea_orm(column_type = "Text")]
     pub name: String,
     pub time_since_reset: DateTime<Utc>,
+    #[sea_orm(column_type = "JsonBinary")]
+    pub milestones: Milestones, // custom ones only
+}
+
+impl Model {
+    #[must_use]
+    pub fn into_sober(self) -> Sober {
+        Sober {
+            id: self.id,
+            name: self.name,
+            start_time: self.time_since_reset,
+            milestones: self.milestones.into(),
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialOrd, PartialEq, Eq, EnumIter)]
 pub enum Relation {
+    Milestones,
+    Resets,
     User,
 }
 
 impl RelationTrait for Relation {
     fn def(&self) -> RelationDef {
         match self {
+            Relation::Milestones => Entity::has_many(super::sober_milestones::Entity).into(),
+            Relation::Resets => Entity::has_many(super::sober_resets::Entity).into(),
             Relation::User => Entity::belongs_to(super::user::Entity)
                 .from(Column::Owner)
                 .to(super::user::Column::Id)
@@ -34,6 +53,18 @@ impl RelationTrait for Relation {
     }
 }
 
+impl Related<super::sober_milestones::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Milestones.def()
+    }
+}
+
+impl Related<super::sober_resets::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Resets.def()
+    }
+}
+
 impl Related<super::user::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::User.def()
@@ -25,8 +25,9 @@ pub struct Model {
     pub id: u64,
     #[sea_orm(column_type = "Text", unique, indexed)]
     pub username: String,
-    #[sea_orm(column_type = "Text", unique, indexed)]
-    pub email: String,
+    // only ever one a login provider vouched for, fediverse accounts don't have one
+    #[sea_orm(column_type = "Text", unique, indexed, nullable)]
+    pub email: Option<String>,
     #[sea_orm(column_type = "Text", nullable)]
     pub profile_picture: Option<String>,
     pub creation_date: DateTime<Utc>,
@@ -41,6 +42,7 @@ pub enum Relation {
     Badges,
     Bans,
     Connections,
+    Identities,
     // LoginTokens,
     Passwords,
     Preferences,
@@ -48,6 +50,7 @@ pub enum Relation {
     OneTimeReminders,
     RecurringReminders,
     RefreshTokens,
+    ReminderEvents,
     Sobers,
     Statistics,
 }
@@ -62,6 +65,7 @@ impl RelationTrait for Relation {
             Relation::Badges => Entity::has_one(super::badges::Entity).into(),
             Relation::Bans => Entity::has_many(super::super::bans::Entity).into(),
             Relation::Connections => Entity::has_one(super::connections::Entity).into(),
+            Relation::Identities => Entity::has_many(super::identities::Entity).into(),
             // Relation::LoginTokens => Entity::has_many(super::login_tokens::Entity).into(),
             Relation::Passwords => Entity::has_one(super::passwords::Entity).into(),
             Relation::Preferences => Entity::has_one(super::preferences::Entity).into(),
@@ -71,6 +75,7 @@ impl RelationTrait for Relation {
                 Entity::has_many(super::recurring_reminders::Entity).into()
             }
             Relation::RefreshTokens => Entity::has_many(super::refresh_tokens::Entity).into(),
+            Relation::ReminderEvents => Entity::has_many(super::reminder_events::Entity).into(),
             Relation::Sobers => Entity::has_many(super::sobers::Entity).into(),
             Relation::Statistics => Entity::has_one(super::statistics::Entity).into(),
         }
@@ -107,6 +112,12 @@ impl Related<super::connections::Entity> for Entity {
     }
 }
 
+impl Related<super::identities::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Identities.def()
+    }
+}
+
 // impl Related<super::login_tokens::Entity> for Entity {
 //     fn to() -> RelationDef {
 //         Relation::LoginTokens.def()
@@ -143,6 +154,12 @@ impl Related<super::recurring_reminders::Entity> for Entity {
     }
 }
 
+impl Related<super::reminder_events::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ReminderEvents.def()
+    }
+}
+
 impl Related<super::sobers::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Sobers.def()
@@ -162,4 +179,5 @@ impl Related<super::refresh_tokens::Entity> for Entity {
 
 impl ActiveModelBehavior for ActiveModel {}
 
-impl_redis!(Model);
+// 1: email is optional
+impl_redis!(Model => 1);
@@ -1,8 +1,8 @@
 use chrono::{DateTime, Utc};
 use kindkapibari_core::{
     gender::Gender,
-    pronouns::Pronouns,
-    user_data::{Locale, UserData},
+    pronouns::{PronounRotation, Pronouns},
+    user_data::{Locale, Timezone, UserData},
 };
 use sea_orm::{
     prelude::{DeriveEntityModel, EntityTrait, PrimaryKeyTrait, RelationTrait},
@@ -25,12 +25,23 @@ pub struct Model {
     pub birthday: Option<DateTime<Utc>>,
     #[sea_orm(column_type = "JsonBinary")]
     pub locale: Locale,
+    #[sea_orm(column_type = "JsonBinary")]
+    pub timezone: Timezone,
+    #[sea_orm(column_type = "JsonBinary")]
+    pub any_pronouns: PronounRotation,
 }
 
 impl Model {
     #[must_use]
     pub fn into_userdata(self) -> UserData {
-        UserData::new(self.gender, self.pronouns, self.birthday, self.locale)
+        UserData::new(
+            self.gender,
+            self.pronouns,
+            self.birthday,
+            self.locale,
+            self.timezone,
+            self.any_pronouns.into(),
+        )
     }
 }
 
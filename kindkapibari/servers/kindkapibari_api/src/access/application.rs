@@ -1,67 +1,269 @@
-// use crate::{
-//     appdata_traits::{AppDataCache, AppDataDatabase, AppDataRedis},
-//     schema::applications,
-//     SResult, ServerError,
-// };
-// use redis::{AsyncCommands, ControlFlow, Msg};
-// use sea_orm::{ActiveValue, EntityTrait};
-// use std::{fmt::Display, sync::Arc};
-// use tracing::instrument;
-
-// #[instrument]
-// pub async fn application_by_id(
-//     state: Arc<impl AppDataDatabase + AppDataCache<u64, applications::Model>>,
-//     id: u64,
-// ) -> SResult<applications::Model> {
-//     // from_cache
-//     if let Some(app) = state.cache::<u64, applications::Model>().get(&id) {
-//         return app.ok_or(ServerError::NotFound(
-//             format!("{id}").into(),
-//             "Not Found".into(),
-//         ));
-//     }
-//
-//     let application_query = match applications::Entity::find_by_id(id)
-//         .one(state.database())
-//         .await?
-//     {
-//         Some(app) => {
-//             state
-//                 .cache::<u64, applications::Model>()
-//                 .insert(id, app.clone()); // rip alloc
-//             app
-//         }
-//         None => ServerError::NotFound("application".into(), format!("{id}").into()),
-//     };
-//     // commit to cache
-//
-//     application_query.ok_or(ServerError::NotFound(
-//         format!("{id}").into(),
-//         "Not Found".into(),
-//     ))
-// }
-//
-// pub fn invalidate_application_cache(
-//     state: Arc<impl AppDataRedis + AppDataCache<u64, applications::Model>>,
-//     msg: Msg,
-// ) -> ControlFlow<()> {
-//     if let Ok(id) = msg.get_pattern::<u64>() {
-//         state
-//             .cache::<u64, applications::Model>()
-//             .blocking_invalidate(id);
-//     }
-//     ControlFlow::Continue
-// }
-//
-// #[instrument]
-// pub async fn new_application(
-//     state: Arc<impl AppDataRedis + AppDataDatabase + AppDataCache<u64, applications::Model>>, // impl L + Ratio + AppData + UrBad + Studie
-//     application: applications::ActiveModel,
-// ) -> SResult<u64> {
-//     let mut application = application;
-//     let new_id = state.id_generator.generate_id();
-//     application.id = ActiveValue::Set(new_id);
-//     application.insert(state.database()).await?;
-//     state.redis().publish("APPLICATION_CACHE", new_id);
-//     Ok(new_id)
-// }
+use crate::{
+    access::{oauth::revoke_authorizations, sessions::deny_token},
+    State,
+};
+use chrono::Utc;
+use kindkapibari_core::{
+    application::{
+        is_valid_redirect_uri, Application, ApplicationRequest, ApplicationUpdate, ClientSecret,
+        NewApplication, MAX_APPLICATION_DESCRIPTION, MAX_APPLICATION_NAME, MAX_REDIRECT_URIS,
+    },
+    oauth::new_client_secret,
+    scopes::{KKBScope, KKBScopes},
+    secret::hash_client_secret,
+};
+use kindkapibari_schema::{
+    error::ServerError,
+    redis::take_client_tokens,
+    schema::{applications, users::oauth_authorizations},
+    SResult,
+};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, IntoActiveModel, ModelTrait,
+    PaginatorTrait, QueryFilter, QueryOrder, QuerySelect,
+};
+use std::{borrow::Cow, sync::Arc};
+use tracing::instrument;
+
+#[instrument]
+pub async fn application_by_id(state: Arc<State>, id: u64) -> SResult<applications::Model> {
+    applications::Entity::find_by_id(id)
+        .one(&state.database)
+        .await?
+        .ok_or_else(|| ServerError::NotFound(Cow::from("application"), Cow::from(format!("{id}"))))
+}
+
+// someone else's application might as well not exist
+#[instrument]
+async fn owned_application(state: Arc<State>, user: u64, id: u64) -> SResult<applications::Model> {
+    let application = application_by_id(state, id).await?;
+    if application.creator != user {
+        return Err(ServerError::NotFound(
+            Cow::from("application"),
+            Cow::from(format!("{id}")),
+        ));
+    }
+    Ok(application)
+}
+
+// users with a live authorization, however many times they've authorized it
+#[allow(clippy::cast_possible_truncation)]
+#[instrument]
+async fn authorized_users(state: Arc<State>, application: u64) -> SResult<u64> {
+    let users = oauth_authorizations::Entity::find()
+        .select_only()
+        .column(oauth_authorizations::Column::Owner)
+        .filter(oauth_authorizations::Column::Application.eq(application))
+        .filter(oauth_authorizations::Column::Revoked.eq(false))
+        .filter(oauth_authorizations::Column::Expire.gt(Utc::now()))
+        .group_by(oauth_authorizations::Column::Owner)
+        .count(&state.database)
+        .await?;
+    Ok(users as u64)
+}
+
+fn into_application(application: applications::Model, authorized_users: u64) -> Application {
+    Application {
+        id: application.id,
+        name: application.name,
+        description: application.description,
+        homepage: application.homepage,
+        logo: application.logo,
+        redirect_uris: application.redirect_uris.into(),
+        scopes: application.scopes.to_vec(),
+        confidential: application.confidential,
+        authorized_users,
+    }
+}
+
+fn check_application(
+    name: &str,
+    description: Option<&str>,
+    redirect_uris: &[String],
+) -> SResult<()> {
+    if name.trim().is_empty() || name.len() > MAX_APPLICATION_NAME {
+        return Err(ServerError::BadRequest(Cow::from("bad name")));
+    }
+    if description.map_or(false, |description| {
+        description.len() > MAX_APPLICATION_DESCRIPTION
+    }) {
+        return Err(ServerError::BadRequest(Cow::from("description too long")));
+    }
+    if redirect_uris.is_empty()
+        || redirect_uris.len() > MAX_REDIRECT_URIS
+        || !redirect_uris.iter().all(|uri| is_valid_redirect_uri(uri))
+    {
+        return Err(ServerError::BadRequest(Cow::from("bad redirect_uris")));
+    }
+    Ok(())
+}
+
+fn dedup_scopes(mut scopes: Vec<KKBScope>) -> KKBScopes {
+    scopes.sort();
+    scopes.dedup();
+    scopes.into()
+}
+
+// the secret for the creator, the hash for us
+async fn new_secret(application: u64) -> SResult<(ClientSecret, String)> {
+    let secret = new_client_secret();
+    let to_hash = secret.clone();
+    // argon2 is slow on purpose, keep it off the async threads
+    let hash = tokio::task::spawn_blocking(move || hash_client_secret(&to_hash))
+        .await
+        .map_err(|why| ServerError::InternalServer(Box::new(why)))?
+        .map_err(|why| ServerError::InternalServer(Box::new(why)))?;
+    Ok((
+        ClientSecret {
+            client_id: application.to_string(),
+            client_secret: secret,
+        },
+        hash,
+    ))
+}
+
+#[instrument]
+pub async fn applications(state: Arc<State>, user: u64) -> SResult<Vec<Application>> {
+    let owned = applications::Entity::find()
+        .filter(applications::Column::Creator.eq(user))
+        .order_by_asc(applications::Column::Id)
+        .all(&state.database)
+        .await?;
+
+    let mut applications = Vec::with_capacity(owned.len());
+    for application in owned {
+        let authorized_users = authorized_users(state.clone(), application.id).await?;
+        applications.push(into_application(application, authorized_users));
+    }
+    Ok(applications)
+}
+
+#[instrument]
+pub async fn user_application(state: Arc<State>, user: u64, id: u64) -> SResult<Application> {
+    let application = owned_application(state.clone(), user, id).await?;
+    let authorized_users = authorized_users(state, id).await?;
+    Ok(into_application(application, authorized_users))
+}
+
+#[instrument]
+pub async fn create_application(
+    state: Arc<State>,
+    user: u64,
+    request: ApplicationRequest,
+) -> SResult<NewApplication> {
+    check_application(
+        &request.name,
+        request.description.as_deref(),
+        &request.redirect_uris,
+    )?;
+
+    let id = state.id_generator.application_ids.generate_id()?;
+    let (secret, secret_hash) = if request.confidential {
+        let (secret, hash) = new_secret(id).await?;
+        (Some(secret), Some(hash))
+    } else {
+        (None, None)
+    };
+
+    let application = applications::ActiveModel {
+        id: ActiveValue::Set(id),
+        creator: ActiveValue::Set(user),
+        name: ActiveValue::Set(request.name),
+        description: ActiveValue::Set(request.description),
+        homepage: ActiveValue::Set(request.homepage),
+        logo: ActiveValue::Set(request.logo),
+        redirect_uris: ActiveValue::Set(request.redirect_uris.into()),
+        secret_hash: ActiveValue::Set(secret_hash),
+        scopes: ActiveValue::Set(dedup_scopes(request.scopes)),
+        confidential: ActiveValue::Set(request.confidential),
+    }
+    .insert(&state.database)
+    .await?;
+
+    Ok(NewApplication {
+        application: into_application(application, 0),
+        secret,
+    })
+}
+
+// Taking scopes away doesn't touch existing authorizations, users that granted them keep them
+// until they revoke or downgrade. The application just can't ask for them anymore.
+#[instrument]
+pub async fn update_application(
+    state: Arc<State>,
+    user: u64,
+    id: u64,
+    update: ApplicationUpdate,
+) -> SResult<Application> {
+    let current = owned_application(state.clone(), user, id).await?;
+
+    let name = update.name.unwrap_or_else(|| current.name.clone());
+    let description = update.description.or_else(|| current.description.clone());
+    let redirect_uris = update
+        .redirect_uris
+        .unwrap_or_else(|| current.redirect_uris.to_vec());
+    check_application(&name, description.as_deref(), &redirect_uris)?;
+
+    let mut application = current.into_active_model();
+    application.name = ActiveValue::Set(name);
+    application.description = ActiveValue::Set(description);
+    application.redirect_uris = ActiveValue::Set(redirect_uris.into());
+    if let Some(homepage) = update.homepage {
+        application.homepage = ActiveValue::Set(homepage);
+    }
+    if let Some(logo) = update.logo {
+        application.logo = ActiveValue::Set(logo);
+    }
+    if let Some(scopes) = update.scopes {
+        application.scopes = ActiveValue::Set(dedup_scopes(scopes));
+    }
+    let application = application.update(&state.database).await?;
+
+    let authorized_users = authorized_users(state, id).await?;
+    Ok(into_application(application, authorized_users))
+}
+
+// every user that authorized it loses it right away, not once their tokens run out
+#[instrument]
+pub async fn delete_application(state: Arc<State>, user: u64, id: u64) -> SResult<()> {
+    let application = owned_application(state.clone(), user, id).await?;
+
+    let authorizations = oauth_authorizations::Entity::find()
+        .filter(oauth_authorizations::Column::Application.eq(application.id));
+    revoke_authorizations(state.clone(), authorizations).await?;
+    deny_client_tokens(state.clone(), application.id).await?;
+    oauth_authorizations::Entity::delete_many()
+        .filter(oauth_authorizations::Column::Application.eq(application.id))
+        .exec(&state.database)
+        .await?;
+    application.delete(&state.database).await?;
+
+    Ok(())
+}
+
+// The old secret stops working immediately, and so does anything it got as itself (client
+// credentials). Tokens users gave it stay valid, they were handed out fair and square.
+#[instrument]
+pub async fn rotate_client_secret(state: Arc<State>, user: u64, id: u64) -> SResult<ClientSecret> {
+    let application = owned_application(state.clone(), user, id).await?;
+    if !application.confidential {
+        return Err(ServerError::BadRequest(Cow::from(
+            "public applications don't have a secret",
+        )));
+    }
+
+    let (secret, hash) = new_secret(application.id).await?;
+    let mut application = application.into_active_model();
+    application.secret_hash = ActiveValue::Set(Some(hash));
+    let application = application.update(&state.database).await?;
+    deny_client_tokens(state, application.id).await?;
+
+    Ok(secret)
+}
+
+async fn deny_client_tokens(state: Arc<State>, application: u64) -> SResult<()> {
+    for token in take_client_tokens(state.clone(), application).await? {
+        deny_token(state.clone(), token).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,202 @@
+use crate::State;
+use chrono::{DateTime, Utc};
+use kindkapibari_core::{
+    badges::{Badge, Badges},
+    delivery::ReminderSink,
+    milestones::{newly_crossed, Milestone, MilestoneEvent},
+};
+use kindkapibari_schema::{
+    error::ServerError,
+    redis::RedisState,
+    schema::users::{badges, sober_milestones, sobers},
+    SResult,
+};
+use redis::AsyncCommands;
+use sea_orm::{
+    sea_query::{Expr, OnConflict, Query},
+    ActiveModelTrait, ActiveValue, ConnectionTrait, EntityTrait, IntoActiveModel, QueryFilter,
+    QuerySelect, TransactionTrait,
+};
+use std::{borrow::Cow, collections::HashMap, sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+use tracing::instrument;
+
+const SWEEP_TICK: Duration = Duration::from_secs(300);
+const CLAIM_PREFIX: &str = "milestones:claimed:";
+// only has to outlive the insert, the row keeps it from being claimed again after that
+const CLAIM_TTL: usize = 86_400;
+
+// Anyone who already has badges gets their row locked until the new one is in, so two grants at
+// once (a sweep and a reset, or two servers) can't both start from the same badges and drop one.
+#[instrument]
+pub async fn grant_badge(state: Arc<State>, owner: u64, badge: Badge) -> SResult<()> {
+    let txn = state.database.begin().await?;
+
+    // a first badge is just the new row, if someone beat us to it we add to theirs below
+    let mut insert = Query::insert();
+    insert
+        .into_table(badges::Entity)
+        .columns([
+            badges::Column::UserId,
+            badges::Column::Badges,
+            badges::Column::Primary,
+        ])
+        .values_panic([owner.into(), Badges::from(vec![badge]).into(), 0_u64.into()])
+        .on_conflict(
+            OnConflict::column(badges::Column::UserId)
+                .do_nothing()
+                .to_owned(),
+        );
+    let inserted = txn
+        .execute(txn.get_database_backend().build(&insert))
+        .await?;
+
+    if inserted.rows_affected() == 0 {
+        let current = badges::Entity::find_by_id(owner)
+            .lock_exclusive()
+            .one(&txn)
+            .await?
+            .ok_or_else(|| {
+                ServerError::NotFound(Cow::from("badges"), Cow::from(owner.to_string()))
+            })?;
+        if current.badges.contains(&badge) {
+            return Ok(());
+        }
+        let mut new_badges = current.badges.clone();
+        new_badges.push(badge);
+        let mut badges_active = current.into_active_model();
+        badges_active.badges = ActiveValue::Set(new_badges);
+        badges_active.update(&txn).await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+// SET NX, so when two servers sweep at once only one of them records and celebrates it
+#[instrument]
+async fn claim_milestone(state: Arc<State>, claim_key: &str) -> SResult<bool> {
+    let claimed: Option<String> = redis::cmd("SET")
+        .arg(claim_key)
+        .arg(Utc::now().timestamp())
+        .arg("NX")
+        .arg("EX")
+        .arg(CLAIM_TTL)
+        .query_async(&mut state.redis_owned())
+        .await?;
+    Ok(claimed.is_some())
+}
+
+fn claim_key(sober: u64, streak_start: DateTime<Utc>, milestone: &Milestone) -> SResult<String> {
+    let milestone = serde_json::to_string(milestone)
+        .map_err(|why| ServerError::InternalServer(Box::new(why)))?;
+    Ok(format!(
+        "{CLAIM_PREFIX}{sober}:{}:{milestone}",
+        streak_start.timestamp()
+    ))
+}
+
+// Records every milestone the current streak crossed since we last looked, grants the badges
+// and celebrates through the same sink reminders are delivered through. `reached` is what the
+// current streak already has.
+#[instrument]
+pub async fn evaluate_sober(
+    state: Arc<State>,
+    sink: Arc<dyn ReminderSink>,
+    sober: sobers::Model,
+    reached: &[Milestone],
+    now: DateTime<Utc>,
+) -> SResult<Vec<MilestoneEvent>> {
+    let streak_start = sober.time_since_reset;
+    let owner = sober.owner;
+    let sober = sober.into_sober();
+    let mut events = vec![];
+    for milestone in newly_crossed(&sober, reached, now) {
+        let claim = claim_key(sober.id, streak_start, &milestone)?;
+        if !claim_milestone(state.clone(), &claim).await? {
+            continue;
+        }
+        let reached_at = streak_start + milestone.duration();
+        let milestone_active = sober_milestones::ActiveModel {
+            id: ActiveValue::Set(state.id_generator.sober_milestone_ids.generate_id()?),
+            sober: ActiveValue::Set(sober.id),
+            owner: ActiveValue::Set(owner),
+            milestone: ActiveValue::Set(milestone.clone()),
+            streak_start: ActiveValue::Set(streak_start),
+            reached: ActiveValue::Set(reached_at),
+        };
+        if let Err(why) = milestone_active.insert(&state.database).await {
+            // give it back so the next sweep can try again
+            let _: () = state.redis_owned().del(claim).await?;
+            return Err(why.into());
+        }
+
+        if let Some(badge) = milestone.badge() {
+            grant_badge(state.clone(), owner, badge).await?;
+        }
+
+        let event = MilestoneEvent {
+            sober: sober.id,
+            owner,
+            name: sober.name.clone(),
+            milestone,
+            streak_start,
+            reached: reached_at,
+        };
+        // it's recorded already, a failed celebration isn't worth retrying
+        if let Err(why) = sink.celebrate(&event).await {
+            tracing::warn!(
+                "failed to celebrate milestone for sober {}: {why}",
+                sober.id
+            );
+        }
+        events.push(event);
+    }
+
+    Ok(events)
+}
+
+// Two queries no matter how many sobers there are, only the milestones of current streaks are
+// loaded.
+#[instrument]
+pub async fn sweep(state: Arc<State>, sink: Arc<dyn ReminderSink>) -> SResult<()> {
+    let now = Utc::now();
+    let mut reached: HashMap<u64, Vec<Milestone>> = HashMap::new();
+    for milestone in sober_milestones::Entity::find()
+        .inner_join(sobers::Entity)
+        .filter(
+            Expr::tbl(sobers::Entity, sobers::Column::TimeSinceReset).equals(
+                sober_milestones::Entity,
+                sober_milestones::Column::StreakStart,
+            ),
+        )
+        .all(&state.database)
+        .await?
+    {
+        reached
+            .entry(milestone.sober)
+            .or_default()
+            .push(milestone.milestone);
+    }
+
+    for sober in sobers::Entity::find().all(&state.database).await? {
+        let sober_id = sober.id;
+        let reached = reached.remove(&sober_id).unwrap_or_default();
+        if let Err(why) = evaluate_sober(state.clone(), sink.clone(), sober, &reached, now).await {
+            tracing::error!("failed to evaluate milestones for sober {sober_id}: {why}");
+        }
+    }
+    Ok(())
+}
+
+// on its own task, a slow sweep shouldn't hold up reminders
+pub fn spawn(state: Arc<State>, sink: Arc<dyn ReminderSink>) -> JoinHandle<()> {
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_TICK);
+        loop {
+            interval.tick().await;
+            if let Err(why) = sweep(state.clone(), sink.clone()).await {
+                tracing::error!("milestone sweep failed: {why}");
+            }
+        }
+    })
+}
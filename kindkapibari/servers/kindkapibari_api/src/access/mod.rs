@@ -1,5 +1,9 @@
 pub mod application;
-pub mod sobers;
-pub mod user;
+pub mod milestones;
+pub mod oauth;
 pub mod onetime;
 pub mod recurring;
+pub mod reminder_events;
+pub mod sessions;
+pub mod sobers;
+pub mod user;
@@ -0,0 +1,303 @@
+use crate::{
+    access::{
+        application::application_by_id,
+        sessions::{deny_access_tokens, deny_token},
+    },
+    State,
+};
+use chrono::Utc;
+use kindkapibari_core::{
+    oauth::{
+        is_valid_code_challenge, new_authorization_code, redirect_with, AuthorizationCode,
+        AuthorizeRedirect, AuthorizeRequest, AuthorizedApplication, ConsentPrompt, OpenIdRequest,
+        ScopeConsent, CODE_CHALLENGE_METHOD,
+    },
+    scopes::{KKBScope, KKBScopes, OpenIdScopes},
+};
+use kindkapibari_schema::{
+    error::ServerError,
+    oauth,
+    redis::store_authorization_code,
+    schema::{
+        applications,
+        users::{oauth_authorizations, refresh_tokens},
+    },
+    SResult,
+};
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, IntoActiveModel,
+    QueryFilter, QueryOrder, Select,
+};
+use std::{borrow::Cow, sync::Arc};
+use tracing::instrument;
+
+// Nothing here redirects on a bad request. Until the app and redirect URI check out we can't
+// trust where we'd be sending the user, so the consent page shows the error instead.
+#[instrument]
+async fn validate_authorize_request(
+    state: Arc<State>,
+    request: &AuthorizeRequest,
+) -> SResult<(applications::Model, String, KKBScopes, OpenIdScopes)> {
+    let id = request
+        .client_id
+        .parse::<u64>()
+        .map_err(|why| ServerError::BadArgumentError(Cow::from("client_id"), Box::new(why)))?;
+    let application = application_by_id(state, id).await?;
+
+    let redirect_uri = match &request.redirect_uri {
+        Some(uri) if application.redirect_uris.contains(uri) => uri.clone(),
+        Some(_) => {
+            return Err(ServerError::BadRequest(Cow::from(
+                "redirect_uri isn't registered for this application",
+            )))
+        }
+        // leaving it out is only unambiguous if there's just the one
+        None => match application.redirect_uris.as_slice() {
+            [only] => only.clone(),
+            _ => {
+                return Err(ServerError::BadRequest(Cow::from(
+                    "redirect_uri is required for this application",
+                )))
+            }
+        },
+    };
+
+    if request.response_type != "code" {
+        return Err(ServerError::BadRequest(Cow::from(
+            "only the code response_type is supported",
+        )));
+    }
+    if request.code_challenge_method != CODE_CHALLENGE_METHOD
+        || !is_valid_code_challenge(&request.code_challenge)
+    {
+        return Err(ServerError::BadRequest(Cow::from(
+            "an S256 PKCE code_challenge is required",
+        )));
+    }
+
+    let (requested, openid) = KKBScopes::parse_with_openid(&request.scope)
+        .map_err(|why| ServerError::BadArgumentError(Cow::from("scope"), Box::new(why)))?;
+    // `openid` on its own only asks for an id_token, not for everything the app could have
+    let scopes = if requested.is_empty() && !openid.openid {
+        application.scopes.clone()
+    } else {
+        requested
+    };
+    if let Some(scope) = scopes
+        .iter()
+        .find(|scope| !application.scopes.grants(scope))
+    {
+        return Err(ServerError::BadRequest(Cow::from(format!(
+            "{} isn't one of this application's scopes",
+            scope.scope_name()
+        ))));
+    }
+
+    Ok((application, redirect_uri, scopes, openid))
+}
+
+#[instrument]
+pub async fn consent_prompt(
+    state: Arc<State>,
+    request: &AuthorizeRequest,
+) -> SResult<ConsentPrompt> {
+    let (application, redirect_uri, scopes, _) = validate_authorize_request(state, request).await?;
+    Ok(ConsentPrompt {
+        application: application.id,
+        name: application.name,
+        description: application.description,
+        homepage: application.homepage,
+        logo: application.logo,
+        redirect_uri,
+        scopes: scopes.iter().copied().map(ScopeConsent::from).collect(),
+    })
+}
+
+// the user said yes or no, either way they go back to the app
+#[instrument]
+pub async fn authorize(
+    state: Arc<State>,
+    user: u64,
+    request: &AuthorizeRequest,
+    allow: bool,
+) -> SResult<AuthorizeRedirect> {
+    let (application, redirect_uri, scopes, openid) =
+        validate_authorize_request(state.clone(), request).await?;
+
+    let code = if allow {
+        let code = new_authorization_code();
+        let grant = AuthorizationCode {
+            user,
+            application: application.id,
+            redirect_uri: redirect_uri.clone(),
+            scopes,
+            code_challenge: request.code_challenge.clone(),
+            openid: openid.openid.then(|| OpenIdRequest {
+                profile: openid.profile,
+                nonce: request.nonce.clone(),
+            }),
+        };
+        store_authorization_code(state, &code, &grant).await?;
+        Some(code)
+    } else {
+        None
+    };
+
+    let mut params = match &code {
+        Some(code) => vec![("code", code.as_str())],
+        None => vec![("error", "access_denied")],
+    };
+    if let Some(client_state) = &request.state {
+        params.push(("state", client_state.as_str()));
+    }
+    let redirect = redirect_with(&redirect_uri, &params)
+        .map_err(|why| ServerError::BadArgumentError(Cow::from("redirect_uri"), Box::new(why)))?;
+    Ok(AuthorizeRedirect { redirect })
+}
+
+// Everything `authorizations` matches that's still live, along with its refresh tokens and any
+// access tokens that haven't expired yet. Returns how many were revoked.
+#[instrument(skip(authorizations))]
+pub async fn revoke_authorizations(
+    state: Arc<State>,
+    authorizations: Select<oauth_authorizations::Entity>,
+) -> SResult<u64> {
+    let revoked = oauth::revoke_authorizations(&state.database, authorizations).await?;
+    for token in revoked.access_tokens {
+        deny_token(state.clone(), token).await?;
+    }
+    Ok(revoked.revoked)
+}
+
+#[allow(clippy::cast_sign_loss)]
+fn into_authorized_application(
+    authorization: &oauth_authorizations::Model,
+    application: applications::Model,
+) -> AuthorizedApplication {
+    AuthorizedApplication {
+        id: authorization.id,
+        application: application.id,
+        name: application.name,
+        description: application.description,
+        homepage: application.homepage,
+        logo: application.logo,
+        scopes: authorization
+            .scopes
+            .iter()
+            .copied()
+            .map(ScopeConsent::from)
+            .collect(),
+        created: authorization.created.timestamp() as u64,
+        last_used: authorization.last_used.timestamp() as u64,
+    }
+}
+
+fn live_authorizations(user: u64) -> Select<oauth_authorizations::Entity> {
+    oauth_authorizations::Entity::find()
+        .filter(oauth_authorizations::Column::Owner.eq(user))
+        .filter(oauth_authorizations::Column::Revoked.eq(false))
+        .filter(oauth_authorizations::Column::Expire.gt(Utc::now()))
+}
+
+#[instrument]
+pub async fn authorized_applications(
+    state: Arc<State>,
+    user: u64,
+) -> SResult<Vec<AuthorizedApplication>> {
+    let authorizations = live_authorizations(user)
+        .order_by_desc(oauth_authorizations::Column::LastUsed)
+        .find_also_related(applications::Entity)
+        .all(&state.database)
+        .await?;
+
+    Ok(authorizations
+        .into_iter()
+        .filter_map(|(authorization, application)| {
+            application.map(|application| into_authorized_application(&authorization, application))
+        })
+        .collect())
+}
+
+#[instrument]
+pub async fn revoke_user_authorization(
+    state: Arc<State>,
+    user: u64,
+    authorization: u64,
+) -> SResult<()> {
+    let revoked = revoke_authorizations(
+        state,
+        live_authorizations(user).filter(oauth_authorizations::Column::Id.eq(authorization)),
+    )
+    .await?;
+    if revoked == 0 {
+        return Err(ServerError::NotFound(
+            Cow::from("authorization"),
+            Cow::from(format!("{authorization}")),
+        ));
+    }
+    Ok(())
+}
+
+// The app's access tokens are denied so the old scopes stop working right away, it gets
+// the narrower ones on its next refresh. Taking offline access away takes the refresh tokens too.
+#[instrument]
+pub async fn downgrade_authorization(
+    state: Arc<State>,
+    user: u64,
+    authorization: u64,
+    mut scopes: Vec<KKBScope>,
+) -> SResult<AuthorizedApplication> {
+    let (current, application) = match live_authorizations(user)
+        .filter(oauth_authorizations::Column::Id.eq(authorization))
+        .find_also_related(applications::Entity)
+        .one(&state.database)
+        .await?
+    {
+        Some((current, Some(application))) => (current, application),
+        _ => {
+            return Err(ServerError::NotFound(
+                Cow::from("authorization"),
+                Cow::from(format!("{authorization}")),
+            ))
+        }
+    };
+
+    if scopes.is_empty() {
+        return Err(ServerError::BadRequest(Cow::from(
+            "an authorization needs at least one scope, revoke it instead",
+        )));
+    }
+    if let Some(scope) = scopes.iter().find(|scope| !current.scopes.grants(scope)) {
+        return Err(ServerError::BadRequest(Cow::from(format!(
+            "{} was never granted",
+            scope.scope_name()
+        ))));
+    }
+    scopes.sort();
+    scopes.dedup();
+
+    let lost_offline =
+        current.scopes.contains(&KKBScope::OfflineRead) && !scopes.contains(&KKBScope::OfflineRead);
+    let access_token = current.access_token;
+    let mut downgraded = current.into_active_model();
+    downgraded.scopes = ActiveValue::Set(scopes.into());
+    let downgraded = downgraded.update(&state.database).await?;
+
+    if lost_offline {
+        refresh_tokens::Entity::update_many()
+            .col_expr(refresh_tokens::Column::Revoked, Expr::value(true))
+            .filter(refresh_tokens::Column::Authorization.eq(authorization))
+            .filter(refresh_tokens::Column::Revoked.eq(false))
+            .exec(&state.database)
+            .await?;
+    }
+    deny_token(state.clone(), access_token).await?;
+    deny_access_tokens(
+        state,
+        refresh_tokens::Entity::find()
+            .filter(refresh_tokens::Column::Authorization.eq(authorization)),
+    )
+    .await?;
+
+    Ok(into_authorized_application(&downgraded, application))
+}
@@ -1,6 +1,9 @@
-use crate::State;
+use crate::{scheduler, State};
 use chrono::Utc;
-use kindkapibari_core::reminder::{OneTimeReminder, OneTimeReminders};
+use kindkapibari_core::{
+    delivery::ReminderKind,
+    reminder::{OneTimeReminder, OneTimeReminders},
+};
 use kindkapibari_schema::{
     error::ServerError,
     schema::users::{onetime_reminders, user},
@@ -171,7 +174,8 @@ pub async fn update_onetime_reminder(
     let mut onetime_active_mdl = current_reminder.into_active_model();
     onetime_active_mdl.name = ActiveValue::Set(reminder.name);
     onetime_active_mdl.expire = ActiveValue::Set(reminder.expire);
-    onetime_active_mdl.update(&state.database).await?;
+    let updated = onetime_active_mdl.update(&state.database).await?;
+    scheduler::schedule_onetime(state, &updated).await?;
 
     Ok(())
 }
@@ -193,7 +197,7 @@ pub async fn add_onetime_reminder(
         return Err(ServerError::BadRequest(Cow::from("already exist!")));
     }
 
-    let reminder_id = state.id_generator.onetime_reminder_ids.generate_id();
+    let reminder_id = state.id_generator.onetime_reminder_ids.generate_id()?;
 
     let reminder_active = onetime_reminders::ActiveModel {
         id: ActiveValue::Set(reminder_id),
@@ -203,7 +207,8 @@ pub async fn add_onetime_reminder(
         expire: ActiveValue::Set(reminder.expire),
     };
 
-    reminder_active.insert(&state.database).await?;
+    let inserted = reminder_active.insert(&state.database).await?;
+    scheduler::schedule_onetime(state, &inserted).await?;
     Ok(reminder_id)
 }
 
@@ -212,6 +217,7 @@ pub async fn delete_onetime_reminder(state: Arc<State>, user: u64, reminder: u64
     let onetime = get_onetime_raw_nochk(state.clone(), user, reminder).await?;
 
     onetime.delete(&state.database).await?;
+    scheduler::unschedule(state, ReminderKind::OneTime, reminder).await?;
 
     Ok(())
 }
@@ -1,6 +1,8 @@
-use crate::State;
-use kindkapibari_core::reminder::{
-    days_to_u8, u8_bitflag_to_days, RecurringReminder, RecurringReminders,
+use crate::{access::user::user_data_by_user_id, scheduler, State};
+use chrono::Utc;
+use kindkapibari_core::{
+    delivery::ReminderKind,
+    reminder::{days_to_u8, RecurringReminder, RecurringReminders},
 };
 use kindkapibari_schema::{
     error::ServerError,
@@ -20,14 +22,15 @@ pub async fn get_recurring_reminders(
         .find_related(recurring_reminders::Entity)
         .all(&state.database)
         .await?;
+    let owner_timezone = user_data_by_user_id(state.clone(), user).await?.timezone;
+    let now = Utc::now();
 
     let recurring = recurring
         .into_iter()
-        .map(|reminder_mdl| RecurringReminder {
-            id: reminder_mdl.id,
-            name: reminder_mdl.name,
-            time: reminder_mdl.time,
-            days: u8_bitflag_to_days(reminder_mdl.days),
+        .map(|reminder_mdl| {
+            let mut reminder = reminder_mdl.into_recurring_reminder();
+            reminder.next_fire = reminder.next_fire(now, owner_timezone);
+            reminder
         })
         .collect::<Vec<RecurringReminder>>();
 
@@ -78,6 +81,14 @@ async fn check_if_reminder_already_exists(
     Ok(false)
 }
 
+fn check_recurrence(reminder: &RecurringReminder) -> SResult<()> {
+    if let Some(rule) = &reminder.recurrence {
+        rule.validate()
+            .map_err(|why| ServerError::BadRequest(Cow::from(why.to_string())))?;
+    }
+    Ok(())
+}
+
 #[instrument]
 pub async fn update_recurring_reminder(
     state: Arc<State>,
@@ -89,6 +100,7 @@ pub async fn update_recurring_reminder(
             "invalid recurring reminder",
         )));
     }
+    check_recurrence(&updated_reminder)?;
 
     let updated_date_u8 = days_to_u8(updated_reminder.days);
 
@@ -97,6 +109,8 @@ pub async fn update_recurring_reminder(
     if current_reminder.name == updated_reminder.name
         && current_reminder.days == updated_date_u8
         && current_reminder.time == updated_reminder.time
+        && current_reminder.recurrence == updated_reminder.recurrence
+        && current_reminder.timezone == updated_reminder.timezone
     {
         return Ok(());
     }
@@ -109,7 +123,10 @@ pub async fn update_recurring_reminder(
     recurring_active_mdl.name = ActiveValue::Set(updated_reminder.name);
     recurring_active_mdl.days = ActiveValue::Set(updated_date_u8);
     recurring_active_mdl.time = ActiveValue::Set(updated_reminder.time);
-    recurring_active_mdl.update(&state.database).await?;
+    recurring_active_mdl.recurrence = ActiveValue::Set(updated_reminder.recurrence);
+    recurring_active_mdl.timezone = ActiveValue::Set(updated_reminder.timezone);
+    let updated = recurring_active_mdl.update(&state.database).await?;
+    scheduler::schedule_recurring(state, &updated, Utc::now()).await?;
 
     Ok(())
 }
@@ -125,6 +142,7 @@ pub async fn add_recurring_reminder(
             "invalid recurring reminder",
         )));
     }
+    check_recurrence(&new_reminder)?;
 
     let uid = user.id;
 
@@ -132,7 +150,7 @@ pub async fn add_recurring_reminder(
         return Err(ServerError::BadRequest(Cow::from("already exist!")));
     }
 
-    let new_id = state.id_generator.recurring_reminder_ids.generate_id();
+    let new_id = state.id_generator.recurring_reminder_ids.generate_id()?;
 
     let recurring_active = recurring_reminders::ActiveModel {
         id: ActiveValue::Set(new_id),
@@ -140,9 +158,12 @@ pub async fn add_recurring_reminder(
         name: ActiveValue::Set(new_reminder.name),
         days: ActiveValue::Set(days_to_u8(new_reminder.days)),
         time: ActiveValue::Set(new_reminder.time),
+        recurrence: ActiveValue::Set(new_reminder.recurrence),
+        timezone: ActiveValue::Set(new_reminder.timezone),
     };
 
-    recurring_active.insert(&state.database).await?;
+    let inserted = recurring_active.insert(&state.database).await?;
+    scheduler::schedule_recurring(state, &inserted, Utc::now()).await?;
     Ok(new_id)
 }
 
@@ -151,6 +172,7 @@ pub async fn delete_recurring_reminder(state: Arc<State>, user: u64, reminder: u
     let recurring = get_recurring_reminder(state.clone(), user, reminder).await?;
 
     recurring.delete(&state.database).await?;
+    scheduler::unschedule(state, ReminderKind::Recurring, reminder).await?;
 
     Ok(())
 }
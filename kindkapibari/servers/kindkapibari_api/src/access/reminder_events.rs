@@ -0,0 +1,169 @@
+use crate::{
+    scheduler::{self, Scheduled},
+    State,
+};
+use chrono::{Duration, Utc};
+use kindkapibari_core::delivery::{
+    DeliveryState, ReminderEvent, ReminderHistory, ReminderHistoryEntry, ReminderKind,
+};
+use kindkapibari_schema::{
+    error::ServerError,
+    schema::users::{reminder_events, user},
+    SResult,
+};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, IntoActiveModel, ModelTrait,
+    QueryFilter, QueryOrder, QuerySelect,
+};
+use std::{borrow::Cow, sync::Arc};
+use tracing::instrument;
+
+// a delivered event nobody reacted to within this long counts as missed
+const MISSED_AFTER_MINS: i64 = 60;
+const MAX_SNOOZE_MINS: u32 = 1440;
+const HISTORY_LIMIT: u64 = 100;
+
+#[instrument]
+pub async fn record_delivery(state: Arc<State>, event: &ReminderEvent) -> SResult<u64> {
+    let event_id = state.id_generator.reminder_event_ids.generate_id()?;
+
+    let event_active = reminder_events::ActiveModel {
+        id: ActiveValue::Set(event_id),
+        owner: ActiveValue::Set(event.owner),
+        kind: ActiveValue::Set(event.kind),
+        reminder: ActiveValue::Set(event.reminder),
+        name: ActiveValue::Set(event.name.clone()),
+        fire_at: ActiveValue::Set(event.fire_at),
+        state: ActiveValue::Set(DeliveryState::Delivered),
+        updated: ActiveValue::Set(Utc::now()),
+    };
+
+    event_active.insert(&state.database).await?;
+    Ok(event_id)
+}
+
+#[instrument]
+pub async fn find_event(
+    state: Arc<State>,
+    event: &ReminderEvent,
+) -> SResult<Option<reminder_events::Model>> {
+    Ok(reminder_events::Entity::find()
+        .filter(reminder_events::Column::Owner.eq(event.owner))
+        .filter(reminder_events::Column::Kind.eq(event.kind))
+        .filter(reminder_events::Column::Reminder.eq(event.reminder))
+        .filter(reminder_events::Column::FireAt.eq(event.fire_at))
+        .one(&state.database)
+        .await?)
+}
+
+#[instrument]
+pub async fn set_event_state(
+    state: Arc<State>,
+    event: reminder_events::Model,
+    new_state: DeliveryState,
+) -> SResult<()> {
+    let mut event_active = event.into_active_model();
+    event_active.state = ActiveValue::Set(new_state);
+    event_active.updated = ActiveValue::Set(Utc::now());
+    event_active.update(&state.database).await?;
+    Ok(())
+}
+
+#[instrument]
+async fn latest_event(
+    state: Arc<State>,
+    user: u64,
+    kind: ReminderKind,
+    reminder: u64,
+) -> SResult<reminder_events::Model> {
+    // one time and recurring reminders have their own IDs, they can be the same
+    reminder_events::Entity::find()
+        .filter(reminder_events::Column::Owner.eq(user))
+        .filter(reminder_events::Column::Kind.eq(kind))
+        .filter(reminder_events::Column::Reminder.eq(reminder))
+        .order_by_desc(reminder_events::Column::FireAt)
+        .one(&state.database)
+        .await?
+        .ok_or_else(|| {
+            ServerError::NotFound(
+                Cow::from("reminder event"),
+                Cow::from(format!("{kind}:{reminder}")),
+            )
+        })
+}
+
+#[instrument]
+pub async fn acknowledge_reminder(
+    state: Arc<State>,
+    user: u64,
+    kind: ReminderKind,
+    reminder: u64,
+) -> SResult<()> {
+    let event = latest_event(state.clone(), user, kind, reminder).await?;
+    if event.state == DeliveryState::Acknowledged {
+        return Ok(());
+    }
+    set_event_state(state, event, DeliveryState::Acknowledged).await
+}
+
+#[instrument]
+pub async fn snooze_reminder(
+    state: Arc<State>,
+    user: u64,
+    kind: ReminderKind,
+    reminder: u64,
+    minutes: u32,
+) -> SResult<()> {
+    if minutes == 0 || minutes > MAX_SNOOZE_MINS {
+        return Err(ServerError::BadRequest(Cow::from("bad snooze time")));
+    }
+
+    let event = latest_event(state.clone(), user, kind, reminder).await?;
+    if event.state == DeliveryState::Acknowledged {
+        return Err(ServerError::BadRequest(Cow::from("already acknowledged")));
+    }
+
+    let until = Utc::now() + Duration::minutes(i64::from(minutes));
+    let reminder_event = ReminderEvent {
+        kind: event.kind,
+        reminder: event.reminder,
+        owner: event.owner,
+        name: event.name.clone(),
+        fire_at: event.fire_at,
+    };
+    set_event_state(state.clone(), event, DeliveryState::Snoozed { until }).await?;
+    scheduler::schedule(state, Scheduled::snoozed(&reminder_event), until).await
+}
+
+#[instrument]
+pub async fn reminder_history(
+    state: Arc<State>,
+    user: user::Model,
+    kind: ReminderKind,
+    reminder: u64,
+) -> SResult<ReminderHistory> {
+    let events: Vec<reminder_events::Model> = user
+        .find_related(reminder_events::Entity)
+        .filter(reminder_events::Column::Kind.eq(kind))
+        .filter(reminder_events::Column::Reminder.eq(reminder))
+        .order_by_desc(reminder_events::Column::FireAt)
+        .limit(HISTORY_LIMIT)
+        .all(&state.database)
+        .await?;
+
+    let missed_before = Utc::now() - Duration::minutes(MISSED_AFTER_MINS);
+    let history = events
+        .into_iter()
+        .map(|event| ReminderHistoryEntry {
+            id: event.id,
+            kind: event.kind,
+            reminder: event.reminder,
+            name: event.name,
+            fire_at: event.fire_at,
+            missed: event.state == DeliveryState::Delivered && event.fire_at < missed_before,
+            state: event.state,
+        })
+        .collect::<Vec<ReminderHistoryEntry>>();
+
+    Ok(ReminderHistory { history })
+}
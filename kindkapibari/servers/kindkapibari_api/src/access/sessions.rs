@@ -0,0 +1,127 @@
+use crate::State;
+use chrono::Utc;
+use kindkapibari_core::{
+    secret::{ACCESS_TOKEN_LEEWAY, ACCESS_TOKEN_SECONDS},
+    session::Session,
+};
+use kindkapibari_schema::{
+    error::ServerError,
+    redis::{deny_access_token, is_access_token_denied},
+    schema::users::refresh_tokens,
+    SResult,
+};
+use sea_orm::{sea_query::Expr, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, Select};
+use std::{borrow::Cow, sync::Arc};
+use tracing::instrument;
+
+#[allow(clippy::cast_sign_loss)]
+fn now() -> u64 {
+    Utc::now().timestamp() as u64
+}
+
+// Every family has exactly one live refresh token, the newest one, so that's the session. The
+// ones handed to apps belong to their authorization instead.
+#[instrument]
+pub async fn sessions(state: Arc<State>, user: u64) -> SResult<Vec<Session>> {
+    let live = refresh_tokens::Entity::find()
+        .filter(refresh_tokens::Column::Owner.eq(user))
+        .filter(refresh_tokens::Column::Authorization.is_null())
+        .filter(refresh_tokens::Column::Revoked.eq(false))
+        .filter(refresh_tokens::Column::Expire.gt(now()))
+        .order_by_desc(refresh_tokens::Column::LastUsed)
+        .all(&state.database)
+        .await?;
+
+    Ok(live
+        .into_iter()
+        .map(|token| Session {
+            id: token.family,
+            created: token.session_start,
+            last_used: token.last_used,
+            user_agent: token.user_agent,
+            ip: token.ip,
+        })
+        .collect())
+}
+
+#[instrument]
+pub async fn revoke_session(state: Arc<State>, user: u64, session: u64) -> SResult<()> {
+    let revoked = refresh_tokens::Entity::update_many()
+        .col_expr(refresh_tokens::Column::Revoked, Expr::value(true))
+        .filter(refresh_tokens::Column::Owner.eq(user))
+        .filter(refresh_tokens::Column::Authorization.is_null())
+        .filter(refresh_tokens::Column::Family.eq(session))
+        .filter(refresh_tokens::Column::Revoked.eq(false))
+        .exec(&state.database)
+        .await?;
+    if revoked.rows_affected == 0 {
+        return Err(ServerError::NotFound(
+            Cow::from("session"),
+            Cow::from(format!("{session}")),
+        ));
+    }
+
+    deny_access_tokens(
+        state,
+        refresh_tokens::Entity::find()
+            .filter(refresh_tokens::Column::Owner.eq(user))
+            .filter(refresh_tokens::Column::Authorization.is_null())
+            .filter(refresh_tokens::Column::Family.eq(session)),
+    )
+    .await
+}
+
+// log out everywhere, returns how many sessions were ended
+#[instrument]
+pub async fn revoke_all_sessions(state: Arc<State>, user: u64) -> SResult<u64> {
+    let revoked = refresh_tokens::Entity::update_many()
+        .col_expr(refresh_tokens::Column::Revoked, Expr::value(true))
+        .filter(refresh_tokens::Column::Owner.eq(user))
+        .filter(refresh_tokens::Column::Authorization.is_null())
+        .filter(refresh_tokens::Column::Revoked.eq(false))
+        .exec(&state.database)
+        .await?;
+
+    deny_access_tokens(
+        state,
+        refresh_tokens::Entity::find()
+            .filter(refresh_tokens::Column::Owner.eq(user))
+            .filter(refresh_tokens::Column::Authorization.is_null()),
+    )
+    .await?;
+    Ok(revoked.rows_affected)
+}
+
+// the access tokens handed out next to these refresh tokens might not have expired yet
+pub(crate) async fn deny_access_tokens(
+    state: Arc<State>,
+    tokens: Select<refresh_tokens::Entity>,
+) -> SResult<()> {
+    let cutoff = now().saturating_sub((ACCESS_TOKEN_SECONDS + ACCESS_TOKEN_LEEWAY) as u64);
+    let live = tokens
+        .filter(refresh_tokens::Column::Created.gt(cutoff))
+        .all(&state.database)
+        .await?;
+    for token in live {
+        deny_token(state.clone(), token.related).await?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn deny_token(state: Arc<State>, jti: u64) -> SResult<()> {
+    deny_access_token(state.clone(), jti).await?;
+    // don't wait out our own cache
+    state.caches.revoked_tokens_cache.insert(jti, true).await;
+    Ok(())
+}
+
+// the denylist, cached for a little while so we aren't asking redis on every request
+#[instrument]
+pub async fn token_revoked(state: Arc<State>, jti: u64) -> SResult<bool> {
+    if let Some(revoked) = state.caches.revoked_tokens_cache.get(&jti) {
+        return Ok(revoked);
+    }
+    let revoked = is_access_token_denied(state.clone(), jti).await?;
+    state.caches.revoked_tokens_cache.insert(jti, revoked).await;
+    Ok(revoked)
+}
@@ -1,15 +1,23 @@
-use crate::State;
+use crate::{scheduler::owner_timezone, State};
 use chrono::{Duration, Utc};
-use kindkapibari_core::sober::{Sober, Sobers};
+use kindkapibari_core::{
+    milestones::Milestone,
+    sober::{Sober, SoberReset, SoberStats, Sobers},
+};
 use kindkapibari_schema::{
     error::ServerError,
-    schema::users::{sobers, user},
+    schema::users::{sober_resets, sobers, user},
     SResult,
 };
-use sea_orm::{ActiveModelTrait, ActiveValue, EntityTrait, IntoActiveModel, ModelTrait};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, IntoActiveModel, ModelTrait,
+    QueryFilter, QueryOrder, TransactionTrait,
+};
 use std::{borrow::Cow, sync::Arc};
 use tracing::instrument;
 
+const MAX_CUSTOM_MILESTONES: usize = 32;
+
 #[instrument]
 pub async fn get_sobers(state: Arc<State>, user: user::Model) -> SResult<Sobers> {
     let sobers: Vec<sobers::Model> = user
@@ -18,11 +26,7 @@ pub async fn get_sobers(state: Arc<State>, user: user::Model) -> SResult<Sobers>
         .await?;
     let sobers = sobers
         .into_iter()
-        .map(|sober_mdl| Sober {
-            id: sober_mdl.id,
-            name: sober_mdl.name,
-            start_time: sober_mdl.time_since_reset,
-        })
+        .map(sobers::Model::into_sober)
         .collect::<Vec<Sober>>();
     Ok(Sobers { sobers })
 }
@@ -61,13 +65,31 @@ async fn check_if_sober_already_exists(
     Ok(false)
 }
 
+fn check_milestones(milestones: &[Milestone]) -> bool {
+    milestones.len() <= MAX_CUSTOM_MILESTONES && milestones.iter().all(Milestone::verify)
+}
+
+// the old streak is kept as a sober_resets row, nothing is overwritten
 #[instrument]
 pub async fn reset_sober(state: Arc<State>, sober: u64, user: u64) -> SResult<i64> {
     let sobers = get_sober(state.clone(), user, sober).await?;
-    let mut sober_active_mdl = sobers.into_active_model();
     let new_time = Utc::now();
+
+    let reset_active = sober_resets::ActiveModel {
+        id: ActiveValue::Set(state.id_generator.sober_reset_ids.generate_id()?),
+        sober: ActiveValue::Set(sobers.id),
+        owner: ActiveValue::Set(user),
+        started: ActiveValue::Set(sobers.time_since_reset),
+        reset: ActiveValue::Set(new_time),
+    };
+    let mut sober_active_mdl = sobers.into_active_model();
     sober_active_mdl.time_since_reset = ActiveValue::Set(new_time);
-    sober_active_mdl.update(&state.database).await?;
+
+    // both or neither, a reset without its history row loses the old streak
+    let txn = state.database.begin().await?;
+    reset_active.insert(&txn).await?;
+    sober_active_mdl.update(&txn).await?;
+    txn.commit().await?;
     Ok(new_time.timestamp_millis())
 }
 
@@ -76,20 +98,26 @@ pub async fn update_sober(
     state: Arc<State>,
     sober_id: u64,
     new_name: String,
+    new_milestones: Vec<Milestone>,
     user: user::Model,
 ) -> SResult<()> {
+    if !check_milestones(&new_milestones) {
+        return Err(ServerError::BadRequest(Cow::from("bad milestones")));
+    }
+
     let current_sober = get_sober(state.clone(), user.id, sober_id).await?;
 
     if !check_if_sober_already_exists(state.clone(), &new_name, user).await? {
         return Err(ServerError::BadRequest(Cow::from("already exists!")));
     }
 
-    if current_sober.name == new_name {
+    if current_sober.name == new_name && *current_sober.milestones == new_milestones {
         return Ok(());
     }
 
     let mut sober_active_mdl = current_sober.into_active_model();
     sober_active_mdl.name = ActiveValue::Set(new_name);
+    sober_active_mdl.milestones = ActiveValue::Set(new_milestones.into());
     sober_active_mdl.update(&state.database).await?;
 
     Ok(())
@@ -109,29 +137,63 @@ pub async fn add_sober(state: Arc<State>, user: user::Model, new_sober: Sober) -
         return Err(ServerError::BadRequest(Cow::from("too long!")));
     }
 
+    if !check_milestones(&new_sober.milestones) {
+        return Err(ServerError::BadRequest(Cow::from("bad milestones")));
+    }
+
     let uid = user.id;
 
     if !check_if_sober_already_exists(state.clone(), &new_sober.name, user).await? {
         return Err(ServerError::BadRequest(Cow::from("already exists!")));
     }
 
-    let sober_id = state.id_generator.sober_ids.generate_id();
+    let sober_id = state.id_generator.sober_ids.generate_id()?;
 
     let sober_active = sobers::ActiveModel {
         id: ActiveValue::Set(sober_id),
         owner: ActiveValue::Set(uid),
         name: ActiveValue::Set(new_sober.name),
         time_since_reset: ActiveValue::Set(new_sober.start_time),
+        milestones: ActiveValue::Set(new_sober.milestones.into()),
     };
 
     sober_active.insert(&state.database).await?;
     Ok(sober_id)
 }
 
+#[instrument]
+pub async fn get_sober_stats(state: Arc<State>, user: u64, sober: u64) -> SResult<SoberStats> {
+    let sober = get_sober(state.clone(), user, sober).await?;
+    let resets = sober
+        .find_related(sober_resets::Entity)
+        .order_by_asc(sober_resets::Column::Reset)
+        .all(&state.database)
+        .await?
+        .into_iter()
+        .map(|reset| SoberReset {
+            started: reset.started,
+            reset: reset.reset,
+        })
+        .collect::<Vec<SoberReset>>();
+
+    let timezone = owner_timezone(state, user).await?;
+
+    Ok(SoberStats::compute(
+        &sober.into_sober(),
+        &resets,
+        timezone,
+        Utc::now(),
+    ))
+}
+
 #[instrument]
 pub async fn delete_sober(state: Arc<State>, user: u64, sober: u64) -> SResult<()> {
     let sober = get_sober(state.clone(), user, sober).await?;
 
+    sober_resets::Entity::delete_many()
+        .filter(sober_resets::Column::Sober.eq(sober.id))
+        .exec(&state.database)
+        .await?;
     sober.delete(&state.database).await?;
 
     Ok(())
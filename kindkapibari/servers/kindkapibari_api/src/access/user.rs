@@ -1,11 +1,17 @@
 use crate::State;
-use kindkapibari_core::user_data::UserData;
+use chrono::Utc;
+use kindkapibari_core::{pronouns::PronounRotation, user_data::UserData};
 use kindkapibari_schema::{
     error::ServerError,
-    schema::users::{user, userdata},
+    schema::{
+        bans,
+        users::{user, userdata},
+    },
     SResult,
 };
-use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, ModelTrait, QueryFilter};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, Condition, EntityTrait, ModelTrait, QueryFilter,
+};
 use std::{borrow::Cow, sync::Arc};
 use tracing::instrument;
 
@@ -25,6 +31,29 @@ pub async fn user_by_id(state: Arc<State>, id: u64) -> SResult<user::Model> {
     }
 }
 
+// Permanent bans win over temporary ones, otherwise it's whichever ends last. Cached like users
+// are, so lifting a ban can take a little while to show up.
+#[instrument]
+pub async fn active_ban(state: Arc<State>, user: u64) -> SResult<Option<bans::Model>> {
+    if let Some(ban) = state.caches.bans_cache.get(&user) {
+        return Ok(ban);
+    }
+
+    let ban = bans::Entity::find()
+        .filter(bans::Column::User.eq(user))
+        .filter(
+            Condition::any()
+                .add(bans::Column::Until.is_null())
+                .add(bans::Column::Until.gt(Utc::now())),
+        )
+        .all(&state.database)
+        .await?
+        .into_iter()
+        .max_by_key(|ban| (ban.until.is_none(), ban.until));
+    state.caches.bans_cache.insert(user, ban.clone()).await;
+    Ok(ban)
+}
+
 #[instrument]
 pub async fn user_by_username(state: Arc<State>, name: &str) -> SResult<Option<user::Model>> {
     let user = user::Entity::find()
@@ -64,12 +93,21 @@ pub async fn update_user_data_by_user_id(
     user: user::Model,
     userdata: UserData,
 ) -> SResult<()> {
+    let any_pronouns = PronounRotation::from(userdata.any_pronouns);
+    if !any_pronouns.verify() {
+        return Err(ServerError::BadRequest(Cow::from(
+            "bad any/all pronoun list",
+        )));
+    }
+
     let user = user_data_by_user_id(state.clone(), user).await?;
     let mut user_data_active: userdata::ActiveModel = user.into();
     user_data_active.locale = ActiveValue::Set(userdata.locale);
     user_data_active.birthday = ActiveValue::Set(userdata.birthday);
     user_data_active.gender = ActiveValue::Set(userdata.gender);
     user_data_active.pronouns = ActiveValue::Set(userdata.pronouns);
+    user_data_active.timezone = ActiveValue::Set(userdata.timezone);
+    user_data_active.any_pronouns = ActiveValue::Set(any_pronouns);
     user_data_active.update(&state.database).await?;
     Ok(())
 }
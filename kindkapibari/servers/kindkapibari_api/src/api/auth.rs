@@ -1,6 +1,13 @@
-use crate::{access::user::user_by_id, SERVERSTATE};
+use crate::{
+    access::{
+        sessions::token_revoked,
+        user::{active_ban, user_by_id},
+    },
+    SERVERSTATE,
+};
 use kindkapibari_core::{
-    auth::{FromAuth, Located},
+    auth::{AuthenticationRejection, FromAuth, Located, Scoped},
+    scopes::KKBScope,
     secret::decode_access_token,
 };
 use kindkapibari_schema::schema::users::user;
@@ -11,20 +18,29 @@ use std::{
 };
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Serialize, Deserialize)]
-#[serde(transparent)]
-pub struct UserAuthMdl(pub user::Model);
+pub struct UserAuthMdl {
+    pub user: user::Model,
+    // `None` for our own login tokens
+    pub scopes: Option<Vec<KKBScope>>,
+}
 
 impl Deref for UserAuthMdl {
     type Target = user::Model;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.user
     }
 }
 
 impl DerefMut for UserAuthMdl {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.user
+    }
+}
+
+impl Scoped for UserAuthMdl {
+    fn granted_scopes(&self) -> Option<&[KKBScope]> {
+        self.scopes.as_deref()
     }
 }
 
@@ -32,45 +48,49 @@ impl DerefMut for UserAuthMdl {
 impl FromAuth for UserAuthMdl {
     const LOCATION: Located = Located::Header(Cow::Borrowed("Authorization"));
 
-    async fn from_auth(provided: String) -> Option<Self> {
-        let split = provided.split_once(' ');
-        match split {
-            Some((name, content)) => {
-                if name == "Bearer" {
-                    let server_state = SERVERSTATE.get()?;
-                    let claims = decode_access_token(
-                        content,
-                        server_state
-                            .config
-                            .read()
-                            .await
-                            .signing_keys
-                            .login_key
-                            .as_bytes(),
-                    )
-                    .ok()?;
-                    return Some(
-                        user_by_id(server_state.clone(), claims.user_id)
-                            .await
-                            .ok()?
-                            .into(),
-                    );
+    async fn from_auth(provided: String) -> Result<Self, AuthenticationRejection> {
+        let content = match provided.split_once(' ') {
+            Some(("Bearer", content)) => content,
+            _ => return Err(AuthenticationRejection::BadAuthorization),
+        };
+        let server_state = SERVERSTATE
+            .get()
+            .ok_or(AuthenticationRejection::BadAuthorization)?;
+
+        let claims =
+            decode_access_token(content, &*server_state.keyring.read().await).map_err(|why| {
+                if why.is_expired() {
+                    AuthenticationRejection::Expired
+                } else {
+                    AuthenticationRejection::BadAuthorization
                 }
-                None
-            }
-            _ => None,
+            })?;
+        // fail closed if we can't check
+        if token_revoked(server_state.clone(), claims.jti)
+            .await
+            .map_err(|_| AuthenticationRejection::BadAuthorization)?
+        {
+            return Err(AuthenticationRejection::Revoked);
+        }
+        if let Some(ban) = active_ban(server_state.clone(), claims.user_id)
+            .await
+            .map_err(|_| AuthenticationRejection::BadAuthorization)?
+        {
+            return Err(AuthenticationRejection::Banned(ban.until));
         }
-    }
-}
 
-impl From<user::Model> for UserAuthMdl {
-    fn from(m: user::Model) -> Self {
-        Self(m)
+        let user = user_by_id(server_state.clone(), claims.user_id)
+            .await
+            .map_err(|_| AuthenticationRejection::BadAuthorization)?;
+        Ok(UserAuthMdl {
+            user,
+            scopes: claims.granted_scopes().map(<[KKBScope]>::to_vec),
+        })
     }
 }
 
 impl From<UserAuthMdl> for user::Model {
     fn from(uam: UserAuthMdl) -> Self {
-        uam.0
+        uam.user
     }
 }
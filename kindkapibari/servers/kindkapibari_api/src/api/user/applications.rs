@@ -0,0 +1,180 @@
+use crate::{
+    access::application::{
+        applications, create_application, delete_application, rotate_client_secret,
+        update_application, user_application,
+    },
+    api::auth::UserAuthMdl,
+    State,
+};
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Extension, Json,
+};
+use kindkapibari_core::{
+    application::{
+        Application, ApplicationRequest, ApplicationUpdate, ClientSecret, NewApplication,
+    },
+    auth::{require, RequireScopes},
+    route,
+};
+use kindkapibari_schema::SResult;
+use std::sync::Arc;
+use tracing::instrument;
+
+#[instrument]
+#[utoipa::path(
+    get,
+    path = "/users/applications",
+    responses(
+    (status = 200, description = "Applications the user created", body = [Application]),
+    (status = 401, description = "Bad Token"),
+    (status = 403, description = "Bad Token"),
+    (status = 500, description = "Failed")),
+    security(
+    ("api_jwt_token" = []),
+    )
+)]
+pub async fn get_applications(
+    Extension(state): Extension<Arc<State>>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::ApplicationsRead>,
+) -> SResult<Json<Vec<Application>>> {
+    Ok(Json(applications(state, user.id).await?))
+}
+
+#[instrument(skip(request))]
+#[utoipa::path(
+    post,
+    path = "/users/applications",
+    request_body = ApplicationRequest,
+    responses(
+    (status = 200, description = "The new application, with its secret if it is confidential", body = NewApplication),
+    (status = 400, description = "Bad Name/Description/Redirect URIs"),
+    (status = 401, description = "Bad Token"),
+    (status = 403, description = "Bad Token"),
+    (status = 500, description = "Failed")),
+    security(
+    ("api_jwt_token" = []),
+    )
+)]
+pub async fn post_create_application(
+    Extension(state): Extension<Arc<State>>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::FirstParty>,
+    Json(request): Json<ApplicationRequest>,
+) -> SResult<Json<NewApplication>> {
+    Ok(Json(create_application(state, user.id, request).await?))
+}
+
+#[instrument]
+#[utoipa::path(
+    get,
+    path = "/users/applications/{id}",
+    responses(
+    (status = 200, description = "The application", body = Application),
+    (status = 401, description = "Bad Token"),
+    (status = 403, description = "Bad Token"),
+    (status = 404, description = "Application Not Found"),
+    (status = 500, description = "Failed")),
+    params(
+    ("id" = u64, path, description = "Application ID")
+    ),
+    security(
+    ("api_jwt_token" = []),
+    )
+)]
+pub async fn get_application(
+    Extension(state): Extension<Arc<State>>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::ApplicationsRead>,
+    Path(application): Path<u64>,
+) -> SResult<Json<Application>> {
+    Ok(Json(user_application(state, user.id, application).await?))
+}
+
+#[instrument(skip(update))]
+#[utoipa::path(
+    patch,
+    path = "/users/applications/{id}",
+    request_body = ApplicationUpdate,
+    responses(
+    (status = 200, description = "The updated application", body = Application),
+    (status = 400, description = "Bad Name/Description/Redirect URIs"),
+    (status = 401, description = "Bad Token"),
+    (status = 403, description = "Bad Token"),
+    (status = 404, description = "Application Not Found"),
+    (status = 500, description = "Failed")),
+    params(
+    ("id" = u64, path, description = "Application ID")
+    ),
+    security(
+    ("api_jwt_token" = []),
+    )
+)]
+pub async fn patch_update_application(
+    Extension(state): Extension<Arc<State>>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::FirstParty>,
+    Path(application): Path<u64>,
+    Json(update): Json<ApplicationUpdate>,
+) -> SResult<Json<Application>> {
+    Ok(Json(
+        update_application(state, user.id, application, update).await?,
+    ))
+}
+
+#[instrument]
+#[utoipa::path(
+    delete,
+    path = "/users/applications/{id}",
+    responses(
+    (status = 200, description = "Deleted, everyone that authorized it has been logged out of it"),
+    (status = 401, description = "Bad Token"),
+    (status = 403, description = "Bad Token"),
+    (status = 404, description = "Application Not Found"),
+    (status = 500, description = "Failed")),
+    params(
+    ("id" = u64, path, description = "Application ID")
+    ),
+    security(
+    ("api_jwt_token" = []),
+    )
+)]
+pub async fn delete_user_application(
+    Extension(state): Extension<Arc<State>>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::FirstParty>,
+    Path(application): Path<u64>,
+) -> SResult<()> {
+    delete_application(state, user.id, application).await
+}
+
+#[instrument]
+#[utoipa::path(
+    post,
+    path = "/users/applications/{id}/rotate_secret",
+    responses(
+    (status = 200, description = "The new secret, the old one no longer works", body = ClientSecret),
+    (status = 400, description = "Application is Public"),
+    (status = 401, description = "Bad Token"),
+    (status = 403, description = "Bad Token"),
+    (status = 404, description = "Application Not Found"),
+    (status = 500, description = "Failed")),
+    params(
+    ("id" = u64, path, description = "Application ID")
+    ),
+    security(
+    ("api_jwt_token" = []),
+    )
+)]
+pub async fn post_rotate_client_secret(
+    Extension(state): Extension<Arc<State>>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::FirstParty>,
+    Path(application): Path<u64>,
+) -> SResult<Json<ClientSecret>> {
+    Ok(Json(
+        rotate_client_secret(state, user.id, application).await?,
+    ))
+}
+
+route! {
+    "/applications" => get(get_applications).post(post_create_application),
+    "/applications/:id" => get(get_application).patch(patch_update_application).delete(delete_user_application),
+    "/applications/:id/rotate_secret" => post(post_rotate_client_secret)
+}
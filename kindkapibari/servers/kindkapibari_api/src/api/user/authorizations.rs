@@ -0,0 +1,100 @@
+use crate::{
+    access::oauth::{authorized_applications, downgrade_authorization, revoke_user_authorization},
+    api::auth::UserAuthMdl,
+    State,
+};
+use axum::{
+    extract::Path,
+    routing::{get, patch},
+    Extension, Json,
+};
+use kindkapibari_core::{
+    auth::{require, RequireScopes},
+    oauth::{AuthorizedApplication, ScopeDowngrade},
+    route,
+};
+use kindkapibari_schema::SResult;
+use std::sync::Arc;
+use tracing::instrument;
+
+// Only ever the user themselves, an app doesn't get to see or touch what the others were given.
+
+#[instrument]
+#[utoipa::path(
+    get,
+    path = "/users/authorizations",
+    responses(
+    (status = 200, description = "Applications the user has authorized, most recently used first", body = [AuthorizedApplication]),
+    (status = 401, description = "Bad Token"),
+    (status = 403, description = "Bad Token"),
+    (status = 500, description = "Failed")),
+    security(
+    ("api_jwt_token" = []),
+    )
+)]
+pub async fn get_authorizations(
+    Extension(state): Extension<Arc<State>>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::FirstParty>,
+) -> SResult<Json<Vec<AuthorizedApplication>>> {
+    Ok(Json(authorized_applications(state, user.id).await?))
+}
+
+#[instrument]
+#[utoipa::path(
+    patch,
+    path = "/users/authorizations/{id}",
+    request_body = ScopeDowngrade,
+    responses(
+    (status = 200, description = "The authorization with only the scopes kept", body = AuthorizedApplication),
+    (status = 400, description = "Scope was never granted/No scopes left"),
+    (status = 401, description = "Bad Token"),
+    (status = 403, description = "Bad Token"),
+    (status = 404, description = "Authorization does not exist/already revoked"),
+    (status = 500, description = "Failed")),
+    params(
+    ("id" = u64, path, description = "Authorization ID")
+    ),
+    security(
+    ("api_jwt_token" = []),
+    )
+)]
+pub async fn patch_downgrade_authorization(
+    Extension(state): Extension<Arc<State>>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::FirstParty>,
+    Path(authorization): Path<u64>,
+    Json(downgrade): Json<ScopeDowngrade>,
+) -> SResult<Json<AuthorizedApplication>> {
+    Ok(Json(
+        downgrade_authorization(state, user.id, authorization, downgrade.scopes).await?,
+    ))
+}
+
+#[instrument]
+#[utoipa::path(
+    delete,
+    path = "/users/authorizations/{id}",
+    responses(
+    (status = 200, description = "Revoked, along with every token the application got from it"),
+    (status = 401, description = "Bad Token"),
+    (status = 403, description = "Bad Token"),
+    (status = 404, description = "Authorization does not exist/already revoked"),
+    (status = 500, description = "Failed")),
+    params(
+    ("id" = u64, path, description = "Authorization ID")
+    ),
+    security(
+    ("api_jwt_token" = []),
+    )
+)]
+pub async fn delete_authorization(
+    Extension(state): Extension<Arc<State>>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::FirstParty>,
+    Path(authorization): Path<u64>,
+) -> SResult<()> {
+    revoke_user_authorization(state, user.id, authorization).await
+}
+
+route! {
+    "/authorizations" => get(get_authorizations),
+    "/authorizations/:id" => patch(patch_downgrade_authorization).delete(delete_authorization)
+}
@@ -1,8 +1,12 @@
 // use kindkapibari_core::route;
 
+pub mod applications;
+pub mod authorizations;
 pub mod oauth;
 pub mod onetime;
 pub mod recurring;
+pub mod reminders;
+pub mod sessions;
 pub mod sober;
 pub mod users;
 
@@ -16,8 +20,13 @@ pub mod users;
 #[must_use]
 pub fn routes() -> axum::Router {
     axum::Router::new()
+        .merge(applications::routes())
+        .merge(authorizations::routes())
+        .merge(oauth::routes())
         .merge(onetime::routes())
         .merge(recurring::routes())
+        .merge(reminders::routes())
+        .merge(sessions::routes())
         .merge(sober::routes())
         .merge(users::routes())
 }
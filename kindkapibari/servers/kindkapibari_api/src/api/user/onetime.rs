@@ -15,7 +15,7 @@ use axum::{
     Extension, Json,
 };
 use kindkapibari_core::{
-    auth::Authentication,
+    auth::{require, RequireScopes},
     reminder::{OneTimeReminder, OneTimeReminders},
     roles::Role,
     route,
@@ -38,20 +38,19 @@ use tracing::instrument;
     ("user_id" = u64, path, description = "User ID")
     ),
     security(
-    ("api_jwt_token" = []),
+    ("api_jwt_token" = ["records-read"]),
     )
 )]
 pub async fn get_user_onetime_reminders(
     Extension(state): Extension<Arc<State>>,
-    Authentication(user): Authentication<UserAuthMdl>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::RecordsRead>,
     Path(user_id): Path<u64>,
 ) -> SResult<Json<OneTimeReminders>> {
-    let mut user = user;
     if user.roles >= Role::Server || user.id != user_id {
         return Err(ServerError::Unauthorized);
     }
-    user = user_by_id(state.clone(), user_id).await?.into();
-    let recurring = get_onetime_reminders(state, user.into()).await?;
+    let owner = user_by_id(state.clone(), user_id).await?;
+    let recurring = get_onetime_reminders(state, owner).await?;
     Ok(Json(recurring))
 }
 
@@ -68,12 +67,12 @@ pub async fn get_user_onetime_reminders(
     (status = 500, description = "Failed")
     ),
     security(
-    ("api_jwt_token" = []),
+    ("api_jwt_token" = ["reminders-write"]),
     )
 )]
 pub async fn patch_update_onetime_reminders(
     Extension(state): Extension<Arc<State>>,
-    Authentication(user): Authentication<UserAuthMdl>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::RemindersWrite>,
     Json(onetime): Json<OneTimeReminder>,
 ) -> SResult<()> {
     update_onetime_reminder(state, user.into(), onetime).await?;
@@ -92,12 +91,12 @@ pub async fn patch_update_onetime_reminders(
     (status = 404, description = "User does not exist/Reminder does not exist"),
     (status = 500, description = "Failed")),
     security(
-    ("api_jwt_token" = []),
+    ("api_jwt_token" = ["reminders-write"]),
     )
 )]
 pub async fn post_add_onetime_reminder(
     Extension(state): Extension<Arc<State>>,
-    Authentication(user): Authentication<UserAuthMdl>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::RemindersWrite>,
     Json(onetime): Json<OneTimeReminder>,
 ) -> SResult<Json<u64>> {
     let id = add_onetime_reminder(state, user.into(), onetime).await?;
@@ -119,12 +118,12 @@ pub async fn post_add_onetime_reminder(
     ("onetime_id" = u64, path, description = "Onetime Reminder ID")
     ),
     security(
-    ("api_jwt_token" = []),
+    ("api_jwt_token" = ["reminders-write"]),
     )
 )]
 pub async fn delete_user_onetime_reminder(
     Extension(state): Extension<Arc<State>>,
-    Authentication(user): Authentication<UserAuthMdl>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::RemindersWrite>,
     id: Path<u64>,
 ) -> SResult<()> {
     delete_onetime_reminder(state, user.id, id.0).await?;
@@ -15,7 +15,7 @@ use axum::{
     Extension, Json,
 };
 use kindkapibari_core::{
-    auth::Authentication,
+    auth::{require, RequireScopes},
     reminder::{RecurringReminder, RecurringReminders},
     roles::Role,
     route,
@@ -29,7 +29,7 @@ use tracing::instrument;
     get,
     path = "/users/recurring_reminders/{user_id}",
     responses(
-    (status = 200, description = "Sucessfully Updated Recurring Reminder"),
+    (status = 200, description = "Recurring Reminders, with their next fire time", body = RecurringReminders),
     (status = 401, description = "Bad Token"),
     (status = 403, description = "Bad Token"),
     (status = 404, description = "User does not exist/Reminder does not exist"),
@@ -38,21 +38,19 @@ use tracing::instrument;
     ("user_id" = u64, path, description = "User ID")
     ),
     security(
-    ("api_jwt_token" = []),
+    ("api_jwt_token" = ["records-read"]),
     )
 )]
 pub async fn get_user_recurring_reminders(
     Extension(state): Extension<Arc<State>>,
-    Authentication(user): Authentication<UserAuthMdl>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::RecordsRead>,
     Path(user_id): Path<u64>,
 ) -> SResult<Json<RecurringReminders>> {
-    let mut user = user;
     if user.roles >= Role::Server || user.id != user_id {
         return Err(ServerError::Unauthorized);
     }
-
-    user = user_by_id(state.clone(), user_id).await?.into();
-    let recurring = get_recurring_reminders(state, user.into()).await?;
+    let owner = user_by_id(state.clone(), user_id).await?;
+    let recurring = get_recurring_reminders(state, owner).await?;
     Ok(Json(recurring))
 }
 
@@ -68,12 +66,12 @@ pub async fn get_user_recurring_reminders(
     (status = 404, description = "User does not exist/Reminder does not exist"),
     (status = 500, description = "Failed")),
     security(
-    ("api_jwt_token" = []),
+    ("api_jwt_token" = ["reminders-write"]),
     )
 )]
 pub async fn patch_update_recurring_reminders(
     Extension(state): Extension<Arc<State>>,
-    Authentication(user): Authentication<UserAuthMdl>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::RemindersWrite>,
     Json(recurring): Json<RecurringReminder>,
 ) -> SResult<()> {
     update_recurring_reminder(state, user.into(), recurring).await?;
@@ -92,12 +90,12 @@ pub async fn patch_update_recurring_reminders(
     (status = 404, description = "User does not exist/Reminder does not exist"),
     (status = 500, description = "Failed")),
     security(
-    ("api_jwt_token" = []),
+    ("api_jwt_token" = ["reminders-write"]),
     )
 )]
 pub async fn post_add_recurring_reminder(
     Extension(state): Extension<Arc<State>>,
-    Authentication(user): Authentication<UserAuthMdl>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::RemindersWrite>,
     Json(recurring): Json<RecurringReminder>,
 ) -> SResult<Json<u64>> {
     let id = add_recurring_reminder(state, user.into(), recurring).await?;
@@ -119,12 +117,12 @@ pub async fn post_add_recurring_reminder(
     ("recurring_reminder_id" = u64, path, description = "Recurring Reminder ID")
     ),
     security(
-    ("api_jwt_token" = []),
+    ("api_jwt_token" = ["reminders-write"]),
     )
 )]
 pub async fn delete_user_recurring_reminder(
     Extension(state): Extension<Arc<State>>,
-    Authentication(user): Authentication<UserAuthMdl>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::RemindersWrite>,
     recurring_reminder_id: Path<u64>,
 ) -> SResult<()> {
     delete_recurring_reminder(state, user.id, recurring_reminder_id.0).await?;
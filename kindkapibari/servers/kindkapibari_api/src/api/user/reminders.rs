@@ -0,0 +1,108 @@
+use crate::{
+    access::reminder_events::{acknowledge_reminder, reminder_history, snooze_reminder},
+    api::auth::UserAuthMdl,
+    State,
+};
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Extension, Json,
+};
+use kindkapibari_core::{
+    auth::{require, RequireScopes},
+    delivery::{ReminderHistory, ReminderKind, SnoozeRequest},
+    route,
+};
+use kindkapibari_schema::SResult;
+use std::sync::Arc;
+use tracing::instrument;
+
+#[instrument]
+#[utoipa::path(
+    post,
+    path = "/users/reminders/{kind}/{reminder_id}/snooze",
+    request_body = SnoozeRequest,
+    responses(
+    (status = 200, description = "Sucessfully Snoozed Reminder"),
+    (status = 400, description = "Bad snooze time/Already acknowledged"),
+    (status = 401, description = "Bad Token"),
+    (status = 403, description = "Bad Token"),
+    (status = 404, description = "Reminder does not exist/has not fired yet"),
+    (status = 500, description = "Failed")),
+    params(
+    ("kind" = ReminderKind, path, description = "OneTime or Recurring"),
+    ("reminder_id" = u64, path, description = "One Time or Recurring Reminder ID")
+    ),
+    security(
+    ("api_jwt_token" = ["reminders-write"]),
+    )
+)]
+pub async fn post_snooze_reminder(
+    Extension(state): Extension<Arc<State>>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::RemindersWrite>,
+    Path((kind, reminder_id)): Path<(ReminderKind, u64)>,
+    Json(snooze): Json<SnoozeRequest>,
+) -> SResult<()> {
+    snooze_reminder(state, user.id, kind, reminder_id, snooze.minutes).await?;
+    Ok(())
+}
+
+#[instrument]
+#[utoipa::path(
+    post,
+    path = "/users/reminders/{kind}/{reminder_id}/ack",
+    responses(
+    (status = 200, description = "Sucessfully Acknowledged Reminder"),
+    (status = 401, description = "Bad Token"),
+    (status = 403, description = "Bad Token"),
+    (status = 404, description = "Reminder does not exist/has not fired yet"),
+    (status = 500, description = "Failed")),
+    params(
+    ("kind" = ReminderKind, path, description = "OneTime or Recurring"),
+    ("reminder_id" = u64, path, description = "One Time or Recurring Reminder ID")
+    ),
+    security(
+    ("api_jwt_token" = ["reminders-write"]),
+    )
+)]
+pub async fn post_ack_reminder(
+    Extension(state): Extension<Arc<State>>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::RemindersWrite>,
+    Path((kind, reminder_id)): Path<(ReminderKind, u64)>,
+) -> SResult<()> {
+    acknowledge_reminder(state, user.id, kind, reminder_id).await?;
+    Ok(())
+}
+
+#[instrument]
+#[utoipa::path(
+    get,
+    path = "/users/reminders/{kind}/{reminder_id}/history",
+    responses(
+    (status = 200, description = "Most recent firings of this reminder, newest first", body = ReminderHistory),
+    (status = 401, description = "Bad Token"),
+    (status = 403, description = "Bad Token"),
+    (status = 404, description = "User does not exist"),
+    (status = 500, description = "Failed")),
+    params(
+    ("kind" = ReminderKind, path, description = "OneTime or Recurring"),
+    ("reminder_id" = u64, path, description = "One Time or Recurring Reminder ID")
+    ),
+    security(
+    ("api_jwt_token" = ["records-read"]),
+    )
+)]
+pub async fn get_reminder_history(
+    Extension(state): Extension<Arc<State>>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::RecordsRead>,
+    Path((kind, reminder_id)): Path<(ReminderKind, u64)>,
+) -> SResult<Json<ReminderHistory>> {
+    let history = reminder_history(state, user.into(), kind, reminder_id).await?;
+    Ok(Json(history))
+}
+
+route! {
+    "/reminders/:kind/:reminder_id/snooze" => post(post_snooze_reminder),
+    "/reminders/:kind/:reminder_id/ack" => post(post_ack_reminder),
+    "/reminders/:kind/:reminder_id/history" => get(get_reminder_history)
+}
@@ -0,0 +1,89 @@
+use crate::{
+    access::sessions::{revoke_all_sessions, revoke_session, sessions},
+    api::auth::UserAuthMdl,
+    State,
+};
+use axum::{
+    extract::Path,
+    routing::{delete, get, post},
+    Extension, Json,
+};
+use kindkapibari_core::{
+    auth::{require, RequireScopes},
+    route,
+    session::Session,
+};
+use kindkapibari_schema::SResult;
+use std::sync::Arc;
+use tracing::instrument;
+
+#[instrument]
+#[utoipa::path(
+    get,
+    path = "/users/sessions",
+    responses(
+    (status = 200, description = "Everywhere the user is logged in, most recently used first", body = [Session]),
+    (status = 401, description = "Bad Token"),
+    (status = 403, description = "Bad Token"),
+    (status = 500, description = "Failed")),
+    security(
+    ("api_jwt_token" = []),
+    )
+)]
+pub async fn get_sessions(
+    Extension(state): Extension<Arc<State>>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::FirstParty>,
+) -> SResult<Json<Vec<Session>>> {
+    Ok(Json(sessions(state, user.id).await?))
+}
+
+#[instrument]
+#[utoipa::path(
+    delete,
+    path = "/users/sessions/{id}",
+    responses(
+    (status = 200, description = "Sucessfully Logged Out Session"),
+    (status = 401, description = "Bad Token"),
+    (status = 403, description = "Bad Token"),
+    (status = 404, description = "Session does not exist/already ended"),
+    (status = 500, description = "Failed")),
+    params(
+    ("id" = u64, path, description = "Session ID")
+    ),
+    security(
+    ("api_jwt_token" = []),
+    )
+)]
+pub async fn delete_session(
+    Extension(state): Extension<Arc<State>>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::FirstParty>,
+    Path(session): Path<u64>,
+) -> SResult<()> {
+    revoke_session(state, user.id, session).await
+}
+
+#[instrument]
+#[utoipa::path(
+    post,
+    path = "/users/sessions/logout_everywhere",
+    responses(
+    (status = 200, description = "Number of sessions logged out, including this one", body = u64),
+    (status = 401, description = "Bad Token"),
+    (status = 403, description = "Bad Token"),
+    (status = 500, description = "Failed")),
+    security(
+    ("api_jwt_token" = []),
+    )
+)]
+pub async fn post_logout_everywhere(
+    Extension(state): Extension<Arc<State>>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::FirstParty>,
+) -> SResult<Json<u64>> {
+    Ok(Json(revoke_all_sessions(state, user.id).await?))
+}
+
+route! {
+    "/sessions" => get(get_sessions),
+    "/sessions/:id" => delete(delete_session),
+    "/sessions/logout_everywhere" => post(post_logout_everywhere)
+}
@@ -1,6 +1,6 @@
 use crate::{
     access::{
-        sobers::{add_sober, delete_sober, get_sobers, reset_sober, update_sober},
+        sobers::{add_sober, delete_sober, get_sober_stats, get_sobers, reset_sober, update_sober},
         user::user_by_id,
     },
     api::auth::UserAuthMdl,
@@ -12,10 +12,10 @@ use axum::{
     Extension, Json,
 };
 use kindkapibari_core::{
-    auth::Authentication,
+    auth::{require, RequireScopes},
     roles::Role,
     route,
-    sober::{Sober, Sobers},
+    sober::{Sober, SoberStats, Sobers},
 };
 use kindkapibari_schema::{error::ServerError, SResult};
 use std::sync::Arc;
@@ -35,24 +35,49 @@ use tracing::instrument;
     ("id" = u64, path, description = "User ID")
     ),
     security(
-    ("api_jwt_token" = []),
+    ("api_jwt_token" = ["records-read"]),
     )
 )]
 pub async fn get_user_sobers(
     Extension(state): Extension<Arc<State>>,
-    Authentication(user): Authentication<UserAuthMdl>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::RecordsRead>,
     Path(user_id): Path<u64>,
 ) -> SResult<Json<Sobers>> {
-    let mut user = user;
     if user.roles <= Role::Server || user.id != user_id {
         return Err(ServerError::Unauthorized);
     }
-    user = user_by_id(state.clone(), user_id).await?.into();
+    let owner = user_by_id(state.clone(), user_id).await?;
 
-    let sobers = get_sobers(state, user.into()).await?;
+    let sobers = get_sobers(state, owner).await?;
     Ok(Json(sobers))
 }
 
+#[instrument]
+#[utoipa::path(
+    get,
+    path = "/users/sobers/{sober_id}/stats",
+    responses(
+    (status = 200, description = "Streak statistics and reset calendar", body = SoberStats),
+    (status = 401, description = "Bad Token"),
+    (status = 403, description = "Bad Token"),
+    (status = 404, description = "Sober does not exist"),
+    (status = 500, description = "Failed")),
+    params(
+    ("sober_id" = u64, path, description = "Sober ID")
+    ),
+    security(
+    ("api_jwt_token" = ["records-read"]),
+    )
+)]
+pub async fn get_user_sober_stats(
+    Extension(state): Extension<Arc<State>>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::RecordsRead>,
+    Path(sober_id): Path<u64>,
+) -> SResult<Json<SoberStats>> {
+    let stats = get_sober_stats(state, user.id, sober_id).await?;
+    Ok(Json(stats))
+}
+
 #[instrument]
 #[utoipa::path(
     patch,
@@ -68,12 +93,12 @@ pub async fn get_user_sobers(
     ("sober_id" = u64, path, description = "Sober ID"),
     ),
     security(
-    ("api_jwt_token" = []),
+    ("api_jwt_token" = ["sobers-write"]),
     )
 )]
 pub async fn patch_user_sober_reset_time(
     Extension(state): Extension<Arc<State>>,
-    Authentication(user): Authentication<UserAuthMdl>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::SobersWrite>,
     Path(sober_id): Path<u64>,
 ) -> SResult<()> {
     reset_sober(state, sober_id, user.id).await?;
@@ -92,15 +117,15 @@ pub async fn patch_user_sober_reset_time(
     (status = 404, description = "User does not exist/Sober does not exist"),
     (status = 500, description = "Failed")),
     security(
-    ("api_jwt_token" = []),
+    ("api_jwt_token" = ["sobers-write"]),
     )
 )]
 pub async fn patch_update_sober(
     Extension(state): Extension<Arc<State>>,
-    Authentication(user): Authentication<UserAuthMdl>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::SobersWrite>,
     Json(sober): Json<Sober>,
 ) -> SResult<()> {
-    update_sober(state, sober.id, sober.name, user.into()).await?;
+    update_sober(state, sober.id, sober.name, sober.milestones, user.into()).await?;
     Ok(())
 }
 
@@ -116,12 +141,12 @@ pub async fn patch_update_sober(
     (status = 404, description = "User does not exist/Sober does not exist"),
     (status = 500, description = "Failed")),
     security(
-    ("api_jwt_token" = []),
+    ("api_jwt_token" = ["sobers-write"]),
     )
 )]
 pub async fn post_add_sober(
     Extension(state): Extension<Arc<State>>,
-    Authentication(user): Authentication<UserAuthMdl>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::SobersWrite>,
     Json(sober): Json<Sober>,
 ) -> SResult<Json<u64>> {
     let id = add_sober(state, user.into(), sober).await?;
@@ -143,12 +168,12 @@ pub async fn post_add_sober(
     ("id" = u64, path, description = "Sober ID")
     ),
     security(
-    ("api_jwt_token" = []),
+    ("api_jwt_token" = ["sobers-write"]),
     )
 )]
 pub async fn delete_user_sober(
     Extension(state): Extension<Arc<State>>,
-    Authentication(user): Authentication<UserAuthMdl>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::SobersWrite>,
     id: Path<u64>,
 ) -> SResult<()> {
     delete_sober(state, user.id, id.0).await?;
@@ -157,6 +182,7 @@ pub async fn delete_user_sober(
 
 route! {
     "/sobers/:id" => get(get_user_sobers),
+    "/sobers/:id/stats" => get(get_user_sober_stats),
     "/reset/:id" => patch(patch_user_sober_reset_time),
     "/update_sober" => patch(patch_update_sober),
     "/add_sober" => post(post_add_sober),
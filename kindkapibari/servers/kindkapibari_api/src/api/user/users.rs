@@ -10,7 +10,11 @@ use axum::{
     routing::{get, patch},
     Extension, Json,
 };
-use kindkapibari_core::{auth::Authentication, route, user_data::UserData};
+use kindkapibari_core::{
+    auth::{require, RequireScopes},
+    route,
+    user_data::UserData,
+};
 use kindkapibari_schema::SResult;
 use std::{borrow::Cow, sync::Arc};
 use tracing::instrument;
@@ -121,12 +125,12 @@ pub async fn get_account_creation_date(
     (status = 404, description = "User does not exist."),
     (status = 500, description = "Failed")),
     security(
-    ("api_jwt_token" = []),
+    ("api_jwt_token" = ["userdata-read"]),
     )
 )]
 pub async fn get_user_data(
     Extension(state): Extension<Arc<State>>,
-    Authentication(user): Authentication<UserAuthMdl>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::UserdataRead>,
 ) -> SResult<Json<UserData>> {
     let userdata = user_data_by_user_id(state.clone(), user.into()).await?;
     Ok(Json(userdata.into_userdata()))
@@ -144,12 +148,12 @@ pub async fn get_user_data(
     (status = 404, description = "User does not exist."),
     (status = 500, description = "Failed")),
     security(
-    ()
+    ("api_jwt_token" = ["userdata-write"]),
     )
 )]
 pub async fn patch_set_user_data(
     Extension(state): Extension<Arc<State>>,
-    Authentication(user): Authentication<UserAuthMdl>,
+    RequireScopes(user, _): RequireScopes<UserAuthMdl, require::UserdataWrite>,
     Json(new_data): Json<UserData>,
 ) -> SResult<()> {
     update_user_data_by_user_id(state, user.into(), new_data).await?;
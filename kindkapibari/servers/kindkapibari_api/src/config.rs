@@ -1,4 +1,5 @@
 use color_eyre::eyre::Result;
+use kindkapibari_core::secret::PublicKey;
 use serde::{Deserialize, Serialize};
 use std::{
     fs::File,
@@ -70,9 +71,8 @@ pub struct OAuth {
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SigningKeys {
-    pub oauth_key: String,
-    pub oauth_thirdparty_key: String,
-    pub login_key: String,
+    // public keys only, the auth server is the only one that can sign
+    pub public_keys: Vec<PublicKey>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
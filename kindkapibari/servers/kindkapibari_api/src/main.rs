@@ -4,23 +4,48 @@
 pub mod access;
 mod api;
 mod config;
+pub mod scheduler;
 
 use crate::{
-    api::user::{onetime, recurring, sober, users},
+    api::user::{
+        applications, authorizations, oauth, onetime, recurring, reminders, sessions, sober, users,
+    },
     config::Config,
 };
+use axum::Extension;
+use chrono::{TimeZone, Utc};
 use kindkapibari_core::{
+    application::{
+        Application, ApplicationRequest, ApplicationUpdate, ClientSecret, NewApplication,
+    },
+    delivery::{
+        DeliveryState, InMemorySink, ReminderEvent, ReminderHistory, ReminderHistoryEntry,
+        ReminderKind, ReminderSink, SnoozeRequest,
+    },
     gender::Gender,
     make_caches,
-    pronouns::{PronounProfile, Pronouns},
+    milestones::{Milestone, MilestoneEvent},
+    oauth::{
+        AuthorizeRedirect, AuthorizedApplication, ConsentDecision, ConsentPrompt, ScopeConsent,
+        ScopeDowngrade,
+    },
+    pronouns::{PronounForms, PronounProfile, Pronouns},
+    recurrence::{Day, Frequency, RecurrenceRule},
     reminder::{OneTimeReminder, OneTimeReminders, RecurringReminder, RecurringReminders},
     roles::Role,
-    secret::JWTPair,
+    scopes::KKBScope,
+    secret::{JWTPair, VerifyingKeyring},
+    session::Session,
     snowflake::SnowflakeIdGenerator,
-    sober::{Sober, Sobers},
-    user_data::{Locale, UserData, UserSignupRequest},
+    sober::{Sober, SoberMonth, SoberReset, SoberStats, Sobers},
+    user_data::{Locale, Timezone, UserData, UserSignupRequest},
+};
+use kindkapibari_schema::{
+    error::ServerError,
+    redis::RedisState,
+    schema::{bans, users::user::Model},
 };
-use kindkapibari_schema::{error::ServerError, redis::RedisState, schema::users::user::Model};
+use moka::future::Cache;
 use once_cell::sync::OnceCell;
 use redis::{
     aio::{ConnectionLike, ConnectionManager},
@@ -32,6 +57,7 @@ use std::{
     net::SocketAddr,
     ops::{Deref, DerefMut},
     sync::Arc,
+    time::Duration,
 };
 use tokio::{fs::File, io::AsyncWriteExt, sync::RwLock};
 use utoipa::{
@@ -48,27 +74,6 @@ pub const THIS_SITE_URL: &str = "https://kindkapibari.land";
 
 pub static SERVERSTATE: OnceCell<Arc<State>> = OnceCell::new();
 
-// pub struct OAuthState {
-//     registrar: Mutex<OAuthRegistrar>,
-//     authorizer: Mutex<OAuthAuthorizer>,
-//     issuer: Mutex<OAuthIssuer>,
-// }
-//
-// impl OAuthState {
-//     pub async fn endpoint(
-//         &self,
-//     ) -> Generic<impl Registrar + '_, impl Authorizer + '_, impl Issuer + '_> {
-//         Generic {
-//             registrar: self.registrar.lock().await,
-//             authorizer: self.authorizer.lock().await,
-//             issuer: self.issuer.lock().await,
-//             solicitor: Vacant,
-//             scopes: Vacant,
-//             response: Vacant,
-//         }
-//     }
-// }
-
 #[derive(Clone)]
 pub struct RedisCMWithDebug {
     pub redis: ConnectionManager,
@@ -101,6 +106,7 @@ pub struct State {
     pub config: RwLock<Config>,
     pub caches: Caches,
     pub id_generator: IdGenerators,
+    pub keyring: RwLock<VerifyingKeyring>,
 }
 
 #[derive(Debug)]
@@ -108,8 +114,32 @@ pub struct IdGenerators {
     user_ids: SnowflakeIdGenerator,
     redirect_ids: SnowflakeIdGenerator,
     sober_ids: SnowflakeIdGenerator,
+    sober_reset_ids: SnowflakeIdGenerator,
+    sober_milestone_ids: SnowflakeIdGenerator,
     onetime_reminder_ids: SnowflakeIdGenerator,
     recurring_reminder_ids: SnowflakeIdGenerator,
+    reminder_event_ids: SnowflakeIdGenerator,
+    application_ids: SnowflakeIdGenerator,
+}
+
+impl IdGenerators {
+    fn new(machine_id: u8) -> Option<Self> {
+        let epoch = Utc
+            .timestamp_opt(i64::try_from(EPOCH_START).ok()?, 0)
+            .single()?;
+        let generator = || SnowflakeIdGenerator::new(epoch, machine_id);
+        Some(Self {
+            user_ids: generator()?,
+            redirect_ids: generator()?,
+            sober_ids: generator()?,
+            sober_reset_ids: generator()?,
+            sober_milestone_ids: generator()?,
+            onetime_reminder_ids: generator()?,
+            recurring_reminder_ids: generator()?,
+            reminder_event_ids: generator()?,
+            application_ids: generator()?,
+        })
+    }
 }
 
 impl RedisState for State {
@@ -119,7 +149,29 @@ impl RedisState for State {
 }
 
 make_caches! {
-    users: u64 : Model
+    users: u64 : Model,
+    bans: u64 : Option<bans::Model>,
+    revoked_tokens: u64 : bool
+}
+
+// how stale a ban or logout can be on a server that didn't make it
+const AUTH_CHECK_TTL: Duration = Duration::from_secs(15);
+const CACHE_CAPACITY: u64 = 10_000;
+
+impl Caches {
+    fn new() -> Self {
+        Self {
+            users_cache: Cache::new(CACHE_CAPACITY),
+            bans_cache: Cache::builder()
+                .max_capacity(CACHE_CAPACITY)
+                .time_to_live(AUTH_CHECK_TTL)
+                .build(),
+            revoked_tokens_cache: Cache::builder()
+                .max_capacity(CACHE_CAPACITY)
+                .time_to_live(AUTH_CHECK_TTL)
+                .build(),
+        }
+    }
 }
 
 #[tokio::main]
@@ -127,6 +179,17 @@ async fn main() {
     #[derive(OpenApi)]
     #[openapi(
         handlers(
+            applications::get_applications,
+            applications::post_create_application,
+            applications::get_application,
+            applications::patch_update_application,
+            applications::delete_user_application,
+            applications::post_rotate_client_secret,
+            authorizations::get_authorizations,
+            authorizations::patch_downgrade_authorization,
+            authorizations::delete_authorization,
+            oauth::get_authorize,
+            oauth::post_authorize,
             onetime::get_user_onetime_reminders,
             onetime::patch_update_onetime_reminders,
             onetime::post_add_onetime_reminder,
@@ -135,7 +198,14 @@ async fn main() {
             recurring::patch_update_recurring_reminders,
             recurring::post_add_recurring_reminder,
             recurring::delete_user_recurring_reminder,
+            reminders::post_snooze_reminder,
+            reminders::post_ack_reminder,
+            reminders::get_reminder_history,
+            sessions::get_sessions,
+            sessions::delete_session,
+            sessions::post_logout_everywhere,
             sober::get_user_sobers,
+            sober::get_user_sober_stats,
             sober::patch_user_sober_reset_time,
             sober::patch_update_sober,
             sober::post_add_sober,
@@ -154,15 +224,44 @@ async fn main() {
             UserData,
             Pronouns,
             PronounProfile,
+            PronounForms,
             Gender,
             Locale,
+            Timezone,
             Role,
             OneTimeReminder,
             OneTimeReminders,
             RecurringReminder,
             RecurringReminders,
+            RecurrenceRule,
+            Frequency,
+            Day,
+            ReminderKind,
+            ReminderEvent,
+            DeliveryState,
+            ReminderHistoryEntry,
+            ReminderHistory,
+            SnoozeRequest,
             Sober,
             Sobers,
+            SoberReset,
+            SoberMonth,
+            SoberStats,
+            Milestone,
+            MilestoneEvent,
+            Session,
+            KKBScope,
+            ConsentPrompt,
+            ScopeConsent,
+            ConsentDecision,
+            AuthorizeRedirect,
+            Application,
+            ApplicationRequest,
+            ApplicationUpdate,
+            ClientSecret,
+            NewApplication,
+            AuthorizedApplication,
+            ScopeDowngrade,
         ),
         modifiers(&SecurityAddon)
     )]
@@ -201,9 +300,11 @@ async fn main() {
     let addr = SocketAddr::from(([127, 0, 0, 1], 8000));
     tracing::debug!("listening on {}", addr);
 
-    // FIXME: instantiate State
-
     let config = Config::load().expect("Failed to read config");
+    let id_generator = IdGenerators::new(config.machine_id).expect("Machine ID must fit in 6 bits");
+    let keyring = VerifyingKeyring::new(&config.signing_keys.public_keys)
+        .expect("Failed to load signing keys");
+    let caches = Caches::new();
     let database: DatabaseConnection = Database::connect(&config.database.postgres_url)
         .await
         .expect("Failed to connect to PostgreSQL");
@@ -215,7 +316,32 @@ async fn main() {
         .expect("Failed to open Redis ConnectionManager"),
     };
 
-    let routes = api::user::routes();
+    let state = Arc::new(State {
+        redis,
+        database,
+        config: RwLock::new(config),
+        caches,
+        id_generator,
+        keyring: RwLock::new(keyring),
+    });
+    SERVERSTATE
+        .set(state.clone())
+        .expect("Server state was already set");
+
+    // nothing pushes to clients yet, so for now notifications only end up in the log
+    // FIXME: hand these to a real sink (websocket/push) once clients can receive them, until then
+    // reminders and badges are never actually shown to anyone
+    let (sink, notifications) = InMemorySink::new();
+    tokio::task::spawn(async move {
+        while let Ok(notification) = notifications.recv_async().await {
+            tracing::info!("notification: {notification:?}");
+        }
+    });
+    let sink: Arc<dyn ReminderSink> = Arc::new(sink);
+    scheduler::spawn(state.clone(), sink.clone());
+    access::milestones::spawn(state.clone(), sink);
+
+    let routes = api::user::routes().layer(Extension(state));
 
     axum::Server::bind(&addr)
         .serve(routes.into_make_service())
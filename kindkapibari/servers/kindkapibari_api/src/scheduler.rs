@@ -0,0 +1,542 @@
+use crate::{
+    access::reminder_events::{find_event, record_delivery, set_event_state},
+    State,
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use kindkapibari_core::{
+    delivery::{DeliveryState, ReminderEvent, ReminderKind, ReminderSink},
+    user_data::Timezone,
+};
+use kindkapibari_schema::{
+    error::ServerError,
+    redis::RedisState,
+    schema::users::{onetime_reminders, recurring_reminders, userdata},
+    SResult,
+};
+use redis::AsyncCommands;
+use sea_orm::EntityTrait;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    str::FromStr,
+    sync::Arc,
+};
+use tokio::task::JoinHandle;
+use tracing::instrument;
+
+// sorted set, member is a `Scheduled`, score is the unix timestamp it is due at
+const DUE_SET: &str = "reminders:due";
+const CLAIM_PREFIX: &str = "reminders:claimed:";
+const CLAIM_TTL: usize = 604_800; // a week
+const BATCH: isize = 64;
+const TICK: std::time::Duration = std::time::Duration::from_secs(1);
+// one time reminders that expired longer than this ago while we were down are not fired
+const MISSED_GRACE_SECS: i64 = 3600;
+// failed deliveries are retried after 30s, 1m, 2m... up to an hour apart, then dropped
+const RETRY_BASE_SECS: i64 = 30;
+const RETRY_MAX_SECS: i64 = 3600;
+const MAX_RETRIES: u32 = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Scheduled {
+    pub kind: ReminderKind,
+    pub reminder: u64,
+    // the original fire time if this is a snoozed event
+    pub snoozed_from: Option<i64>,
+    // the original fire time and how many deliveries failed if this is a retry
+    pub retry: Option<(i64, u32)>,
+}
+
+impl Scheduled {
+    #[must_use]
+    pub fn new(kind: ReminderKind, reminder: u64) -> Self {
+        Self {
+            kind,
+            reminder,
+            snoozed_from: None,
+            retry: None,
+        }
+    }
+
+    #[must_use]
+    pub fn snoozed(event: &ReminderEvent) -> Self {
+        Self {
+            kind: event.kind,
+            reminder: event.reminder,
+            snoozed_from: Some(event.fire_at.timestamp()),
+            retry: None,
+        }
+    }
+
+    // The same event again after it failed to deliver, `None` once it ran out of retries.
+    #[must_use]
+    pub fn retry(
+        self,
+        fire_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Option<(Self, DateTime<Utc>)> {
+        let failures = self.retry.map_or(0, |(_, failures)| failures) + 1;
+        if failures > MAX_RETRIES {
+            return None;
+        }
+        let backoff = RETRY_BASE_SECS
+            .saturating_mul(1 << (failures - 1))
+            .min(RETRY_MAX_SECS);
+        Some((
+            Self {
+                retry: Some((fire_at.timestamp(), failures)),
+                ..self
+            },
+            now + Duration::seconds(backoff),
+        ))
+    }
+}
+
+// `kind:reminder`, then `:from` if snoozed, then `:retry:fire_at:failures` if retried
+impl Display for Scheduled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.kind, self.reminder)?;
+        if let Some(from) = self.snoozed_from {
+            write!(f, ":{from}")?;
+        }
+        if let Some((fire_at, failures)) = self.retry {
+            write!(f, ":retry:{fire_at}:{failures}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Scheduled {
+    type Err = ServerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || ServerError::ISErr(Cow::from(format!("bad scheduled reminder {s}")));
+        let mut parts = s.split(':');
+        let kind = parts
+            .next()
+            .and_then(|kind| ReminderKind::from_str(kind).ok())
+            .ok_or_else(bad)?;
+        let reminder = parts
+            .next()
+            .and_then(|id| id.parse::<u64>().ok())
+            .ok_or_else(bad)?;
+        let mut next = parts.next();
+        let snoozed_from = match next {
+            Some(from) if from != "retry" => {
+                next = parts.next();
+                Some(from.parse::<i64>().map_err(|_| bad())?)
+            }
+            _ => None,
+        };
+        let retry = match next {
+            Some("retry") => {
+                let fire_at = parts.next().and_then(|at| at.parse::<i64>().ok());
+                let failures = parts.next().and_then(|n| n.parse::<u32>().ok());
+                Some((fire_at.ok_or_else(bad)?, failures.ok_or_else(bad)?))
+            }
+            Some(_) => return Err(bad()),
+            None => None,
+        };
+        if parts.next().is_some() {
+            return Err(bad());
+        }
+        Ok(Self {
+            kind,
+            reminder,
+            snoozed_from,
+            retry,
+        })
+    }
+}
+
+// `None` for anything chrono can't represent, only a corrupted entry would have that
+fn from_timestamp(timestamp: i64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(timestamp, 0).single()
+}
+
+fn claim_key(event_key: &str) -> String {
+    format!("{CLAIM_PREFIX}{event_key}")
+}
+
+#[instrument]
+pub async fn schedule(state: Arc<State>, scheduled: Scheduled, at: DateTime<Utc>) -> SResult<()> {
+    Ok(state
+        .redis_owned()
+        .zadd(DUE_SET, scheduled.to_string(), at.timestamp())
+        .await?)
+}
+
+// does not move an already scheduled reminder, used on startup
+#[instrument]
+async fn schedule_if_absent(
+    state: Arc<State>,
+    scheduled: Scheduled,
+    at: DateTime<Utc>,
+) -> SResult<()> {
+    Ok(redis::cmd("ZADD")
+        .arg(DUE_SET)
+        .arg("NX")
+        .arg(at.timestamp())
+        .arg(scheduled.to_string())
+        .query_async(&mut state.redis_owned())
+        .await?)
+}
+
+#[instrument]
+pub async fn unschedule(state: Arc<State>, kind: ReminderKind, reminder: u64) -> SResult<()> {
+    Ok(state
+        .redis_owned()
+        .zrem(DUE_SET, Scheduled::new(kind, reminder).to_string())
+        .await?)
+}
+
+#[instrument]
+pub async fn owner_timezone(state: Arc<State>, owner: u64) -> SResult<Timezone> {
+    Ok(userdata::Entity::find_by_id(owner)
+        .one(&state.database)
+        .await?
+        .map(|userdata| userdata.timezone)
+        .unwrap_or_default())
+}
+
+#[instrument]
+pub async fn schedule_onetime(
+    state: Arc<State>,
+    reminder: &onetime_reminders::Model,
+) -> SResult<()> {
+    schedule(
+        state,
+        Scheduled::new(ReminderKind::OneTime, reminder.id),
+        reminder.expire,
+    )
+    .await
+}
+
+#[instrument]
+pub async fn schedule_recurring(
+    state: Arc<State>,
+    reminder: &recurring_reminders::Model,
+    after: DateTime<Utc>,
+) -> SResult<()> {
+    let owner_timezone = owner_timezone(state.clone(), reminder.owner).await?;
+    match reminder
+        .clone()
+        .into_recurring_reminder()
+        .next_fire(after, owner_timezone)
+    {
+        Some(next) => {
+            schedule(
+                state,
+                Scheduled::new(ReminderKind::Recurring, reminder.id),
+                next,
+            )
+            .await
+        }
+        None => unschedule(state, ReminderKind::Recurring, reminder.id).await,
+    }
+}
+
+// Makes sure everything in the database is in the due set. Safe to run on every startup,
+// reminders that are already scheduled are left alone so nothing is fired twice.
+#[instrument]
+pub async fn bootstrap(state: Arc<State>) -> SResult<()> {
+    let now = Utc::now();
+    let grace = now - Duration::seconds(MISSED_GRACE_SECS);
+
+    for onetime in onetime_reminders::Entity::find()
+        .all(&state.database)
+        .await?
+    {
+        if onetime.expire < grace {
+            continue;
+        }
+        schedule_if_absent(
+            state.clone(),
+            Scheduled::new(ReminderKind::OneTime, onetime.id),
+            onetime.expire,
+        )
+        .await?;
+    }
+
+    let mut timezones: HashMap<u64, Timezone> = HashMap::new();
+    for recurring in recurring_reminders::Entity::find()
+        .all(&state.database)
+        .await?
+    {
+        let owner_timezone = if let Some(tz) = timezones.get(&recurring.owner) {
+            *tz
+        } else {
+            let tz = owner_timezone(state.clone(), recurring.owner).await?;
+            timezones.insert(recurring.owner, tz);
+            tz
+        };
+        if let Some(next) = recurring
+            .clone()
+            .into_recurring_reminder()
+            .next_fire(now, owner_timezone)
+        {
+            schedule_if_absent(
+                state.clone(),
+                Scheduled::new(ReminderKind::Recurring, recurring.id),
+                next,
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+// SET NX, so only the first worker to get here delivers the event
+#[instrument]
+async fn claim_delivery(state: Arc<State>, event_key: &str) -> SResult<bool> {
+    let claimed: Option<String> = redis::cmd("SET")
+        .arg(claim_key(event_key))
+        .arg(Utc::now().timestamp())
+        .arg("NX")
+        .arg("EX")
+        .arg(CLAIM_TTL)
+        .query_async(&mut state.redis_owned())
+        .await?;
+    Ok(claimed.is_some())
+}
+
+#[instrument]
+async fn event_for(
+    state: Arc<State>,
+    scheduled: Scheduled,
+    fire_at: DateTime<Utc>,
+) -> SResult<Option<ReminderEvent>> {
+    let event = match scheduled.kind {
+        ReminderKind::OneTime => onetime_reminders::Entity::find_by_id(scheduled.reminder)
+            .one(&state.database)
+            .await?
+            .map(|onetime| ReminderEvent {
+                kind: ReminderKind::OneTime,
+                reminder: onetime.id,
+                owner: onetime.owner,
+                name: onetime.name,
+                fire_at,
+            }),
+        ReminderKind::Recurring => {
+            let recurring = match recurring_reminders::Entity::find_by_id(scheduled.reminder)
+                .one(&state.database)
+                .await?
+            {
+                Some(r) => r,
+                None => return Ok(None),
+            };
+            // queue up the next one before we deliver this one, a failed delivery
+            // shouldn't stop the reminder from recurring. snoozes and retries already did.
+            if scheduled.snoozed_from.is_none() && scheduled.retry.is_none() {
+                schedule_recurring(state.clone(), &recurring, fire_at).await?;
+            }
+            Some(ReminderEvent {
+                kind: ReminderKind::Recurring,
+                reminder: recurring.id,
+                owner: recurring.owner,
+                name: recurring.name,
+                fire_at,
+            })
+        }
+    };
+    Ok(event)
+}
+
+// puts a failed delivery back in the due set, or gives up on it
+#[instrument]
+async fn reschedule_failed(
+    state: Arc<State>,
+    scheduled: Scheduled,
+    fire_at: DateTime<Utc>,
+) -> SResult<()> {
+    if let Some((retry, at)) = scheduled.retry(fire_at, Utc::now()) {
+        schedule(state, retry, at).await
+    } else {
+        tracing::warn!("giving up on delivering {scheduled}");
+        Ok(())
+    }
+}
+
+#[instrument]
+async fn fire(
+    state: Arc<State>,
+    sink: Arc<dyn ReminderSink>,
+    scheduled: Scheduled,
+    due: DateTime<Utc>,
+) -> SResult<()> {
+    // snoozed events and retries keep the identity of the event they came from
+    let fire_at = match scheduled
+        .retry
+        .map(|(fire_at, _)| fire_at)
+        .or(scheduled.snoozed_from)
+    {
+        Some(timestamp) => match from_timestamp(timestamp) {
+            Some(fire_at) => fire_at,
+            None => {
+                tracing::warn!("dropping {scheduled}: {timestamp} is out of range");
+                return Ok(());
+            }
+        },
+        None => due,
+    };
+    let event = match event_for(state.clone(), scheduled, fire_at).await? {
+        Some(e) => e,
+        None => return Ok(()), // deleted since it was scheduled
+    };
+    let key = event.key();
+
+    if scheduled.snoozed_from.is_some() {
+        // the user might have acknowledged it in the meantime
+        let snoozed = match find_event(state.clone(), &event).await? {
+            Some(e) => e,
+            None => return Ok(()),
+        };
+        if !matches!(snoozed.state, DeliveryState::Snoozed { .. }) {
+            return Ok(());
+        }
+        if let Err(why) = sink.deliver(&event).await {
+            reschedule_failed(state, scheduled, fire_at).await?;
+            return Err(ServerError::ISErr(Cow::from(why.to_string())));
+        }
+        return set_event_state(state, snoozed, DeliveryState::Delivered).await;
+    }
+
+    if !claim_delivery(state.clone(), &key).await? {
+        return Ok(());
+    }
+    if let Err(why) = sink.deliver(&event).await {
+        // give it back so the retry can claim it
+        let _: () = state.redis_owned().del(claim_key(&key)).await?;
+        reschedule_failed(state, scheduled, fire_at).await?;
+        return Err(ServerError::ISErr(Cow::from(why.to_string())));
+    }
+    record_delivery(state, &event).await?;
+    Ok(())
+}
+
+#[instrument]
+pub async fn fire_due(state: Arc<State>, sink: Arc<dyn ReminderSink>) -> SResult<()> {
+    let due: Vec<(String, i64)> = state
+        .redis_owned()
+        .zrangebyscore_limit_withscores(DUE_SET, "-inf", Utc::now().timestamp(), 0, BATCH)
+        .await?;
+
+    for (member, score) in due {
+        // ZREM is atomic, whoever removes it gets to fire it
+        let removed: i64 = state.redis_owned().zrem(DUE_SET, &member).await?;
+        if removed == 0 {
+            continue;
+        }
+
+        let scheduled = match Scheduled::from_str(&member) {
+            Ok(s) => s,
+            Err(why) => {
+                tracing::warn!("dropping {member}: {why}");
+                continue;
+            }
+        };
+
+        let due = match from_timestamp(score) {
+            Some(due) => due,
+            None => {
+                tracing::warn!("dropping {member}: {score} is out of range");
+                continue;
+            }
+        };
+
+        if let Err(why) = fire(state.clone(), sink.clone(), scheduled, due).await {
+            tracing::error!("failed to fire {member}: {why}");
+        }
+    }
+
+    Ok(())
+}
+
+pub fn spawn(state: Arc<State>, sink: Arc<dyn ReminderSink>) -> JoinHandle<()> {
+    tokio::task::spawn(async move {
+        if let Err(why) = bootstrap(state.clone()).await {
+            tracing::error!("failed to bootstrap reminder scheduler: {why}");
+        }
+        let mut interval = tokio::time::interval(TICK);
+        loop {
+            interval.tick().await;
+            if let Err(why) = fire_due(state.clone(), sink.clone()).await {
+                tracing::error!("reminder scheduler tick failed: {why}");
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kindkapibari_core::delivery::{InMemorySink, Notification};
+
+    fn event() -> ReminderEvent {
+        ReminderEvent {
+            kind: ReminderKind::OneTime,
+            reminder: 1,
+            owner: 2,
+            name: "drink water".to_string(),
+            fire_at: from_timestamp(1_650_000_000).unwrap(),
+        }
+    }
+
+    #[test]
+    fn scheduled_round_trips() {
+        let fresh = Scheduled::new(ReminderKind::Recurring, 7);
+        let snoozed = Scheduled::snoozed(&event());
+        let retried = Scheduled {
+            retry: Some((1_650_000_000, 3)),
+            ..fresh
+        };
+        let snoozed_retried = Scheduled {
+            retry: Some((1_650_000_000, 1)),
+            ..snoozed
+        };
+        for scheduled in [fresh, snoozed, retried, snoozed_retried] {
+            assert_eq!(
+                Scheduled::from_str(&scheduled.to_string()).unwrap(),
+                scheduled
+            );
+        }
+        assert_eq!(snoozed.to_string(), "onetime:1:1650000000");
+        assert_eq!(retried.to_string(), "recurring:7:retry:1650000000:3");
+
+        for bad in ["onetime", "onetime:x", "onetime:1:retry:5", "onetime:1:5:6"] {
+            assert!(Scheduled::from_str(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_backs_off() {
+        let event = event();
+        let (sink, notifications) = InMemorySink::new();
+        sink.deliver(&event).await.unwrap();
+        assert!(matches!(
+            notifications.recv_async().await,
+            Ok(Notification::Reminder(delivered)) if delivered == event
+        ));
+
+        // nobody listening anymore, every delivery fails from here on
+        drop(notifications);
+        let now = from_timestamp(1_650_000_100).unwrap();
+        let mut scheduled = Scheduled::new(event.kind, event.reminder);
+        let mut delays = vec![];
+        while sink.deliver(&event).await.is_err() {
+            match scheduled.retry(event.fire_at, now) {
+                Some((retry, at)) => {
+                    // retries are the same event, not one at the retry time
+                    let (fire_at, _) = retry.retry.unwrap();
+                    assert_eq!(fire_at, event.fire_at.timestamp());
+                    delays.push((at - now).num_seconds());
+                    scheduled = retry;
+                }
+                None => break,
+            }
+        }
+        assert_eq!(delays, vec![30, 60, 120, 240, 480, 960, 1920, 3600]);
+    }
+}
@@ -0,0 +1,326 @@
+use crate::{
+    access::oauth_thirdparty::{
+        exchange_code, get_oauth_client, no_redirects, start_attempt, AuthProviderDataCommon,
+        OAuthAttempt, ProviderEndpoints,
+    },
+    State,
+};
+use kindkapibari_core::impl_redis;
+use kindkapibari_schema::{
+    error::ServerError,
+    redis::{insert_into_cache, read_from_cache},
+    SResult,
+};
+use oauth2::{
+    basic::BasicClient,
+    url::{Host, Url},
+    TokenResponse,
+};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::net::lookup_host;
+use tracing::instrument;
+
+// Mastodon and friends. Every instance is its own OAuth server, so there's nothing to configure,
+// we register ourselves on each one the first time someone logs in from it.
+
+// what these logins show up as, no configured provider can use it
+pub const FEDIVERSE_PROVIDER: &str = "fediverse";
+pub const FEDIVERSE_APP_PREFIX: &str = "fedi";
+const FEDIVERSE_SCOPES: &str = "read:accounts";
+// instances are whoever, don't wait on them forever
+const INSTANCE_TIMEOUT: Duration = Duration::from_secs(10);
+// Instances don't expire our client, but anyone can make up instances for us to remember, so we
+// forget them after a while and register again.
+const FEDIVERSE_APP_SECONDS: usize = 60 * 60 * 24 * 7;
+
+// our client on one instance
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FediverseApp {
+    pub client_id: String,
+    pub client_secret: String,
+    // registered with this, a different host_url means registering again
+    pub redirect_uri: String,
+}
+
+// Anything that isn't a plain domain gets turned away, IPs and `localhost` included, so nobody
+// gets us to poke at our own network (`instance_client` checks where the domain goes). Handles
+// (`@someone@mastodon.social`) and URLs work too.
+pub fn normalize_instance(instance: &str) -> SResult<String> {
+    let instance = instance.trim();
+    let instance = instance.strip_prefix("https://").unwrap_or(instance);
+    let instance = instance.trim_end_matches('/');
+    let host = instance
+        .rsplit('@')
+        .next()
+        .unwrap_or(instance)
+        .to_lowercase();
+
+    let url = Url::parse(&format!("https://{host}/"))
+        .map_err(|why| ServerError::BadArgumentError(Cow::from("instance"), Box::new(why)))?;
+    match url.host() {
+        Some(Host::Domain(domain))
+            if domain == host && domain.contains('.') && url.port().is_none() =>
+        {
+            Ok(host)
+        }
+        _ => Err(ServerError::BadRequest(Cow::from(
+            "instance must be a domain, like mastodon.social",
+        ))),
+    }
+}
+
+fn bad_instance(why: reqwest::Error) -> ServerError {
+    ServerError::BadArgumentError(Cow::from("instance"), Box::new(why))
+}
+
+// anything that's out on the internet, not on our network or nobody's
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // "this network", carrier-grade NAT and reserved
+                || first == 0
+                || (first == 100 && (64..128).contains(&second))
+                || first >= 240)
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            // IPv4 written as IPv6 (`::ffff:127.0.0.1`) gets the same checks
+            if let Some(ip) = ip.to_ipv4() {
+                return !ip.is_unspecified() && is_public(IpAddr::V4(ip));
+            }
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // unique local, link local and documentation
+                || segments[0] & 0xfe00 == 0xfc00
+                || segments[0] & 0xffc0 == 0xfe80
+                || (segments[0] == 0x2001 && segments[1] == 0x0db8))
+        }
+    }
+}
+
+// A name can point anywhere, so we look it up ourselves, turn it away if any of it is on a private
+// network and connect to what we checked, not whatever it says the next time it's asked. No
+// redirects either, or the instance could send us somewhere else.
+#[instrument]
+async fn instance_client(instance: &str) -> SResult<Client> {
+    let addresses = lookup_host((instance, 443))
+        .await
+        .map_err(|why| ServerError::BadArgumentError(Cow::from("instance"), Box::new(why)))?
+        .collect::<Vec<SocketAddr>>();
+    let address = match addresses.first() {
+        Some(address) if addresses.iter().all(|address| is_public(address.ip())) => *address,
+        _ => {
+            return Err(ServerError::BadRequest(Cow::from(
+                "instance must be on the public internet",
+            )))
+        }
+    };
+    no_redirects()
+        .resolve(instance, address)
+        .timeout(INSTANCE_TIMEOUT)
+        .user_agent("kindkapibari")
+        .build()
+        .map_err(|why| ServerError::InternalServer(Box::new(why)))
+}
+
+fn endpoints(instance: &str) -> ProviderEndpoints {
+    ProviderEndpoints {
+        authorize_url: format!("https://{instance}/oauth/authorize"),
+        token_url: format!("https://{instance}/oauth/token"),
+        userinfo_url: format!("https://{instance}/api/v1/accounts/verify_credentials"),
+    }
+}
+
+fn fediverse_client(instance: &str, app: FediverseApp) -> SResult<BasicClient> {
+    get_oauth_client(
+        &endpoints(instance),
+        app.redirect_uri,
+        app.client_id,
+        app.client_secret,
+    )
+}
+
+#[instrument(skip(http))]
+async fn fediverse_app(state: Arc<State>, http: &Client, instance: &str) -> SResult<FediverseApp> {
+    let (host_url, redirect_uri) = {
+        let config = state.config.read().await;
+        (
+            config.host_url.clone(),
+            format!("{}/redirect", config.host_url),
+        )
+    };
+
+    let key = format!("{FEDIVERSE_APP_PREFIX}:{instance}");
+    match read_from_cache::<FediverseApp>(state.clone(), &key).await {
+        Ok(app) if app.redirect_uri == redirect_uri => return Ok(app),
+        Ok(_) | Err(ServerError::NotFound(..)) => {}
+        Err(why) => return Err(why),
+    }
+
+    #[derive(Deserialize)]
+    struct RegisteredApp {
+        client_id: String,
+        client_secret: String,
+    }
+
+    // two logins racing here just register twice, both work and the last one is kept
+    let registered = http
+        .post(format!("https://{instance}/api/v1/apps"))
+        .form(&[
+            ("client_name", "KindKapiBari"),
+            ("redirect_uris", redirect_uri.as_str()),
+            ("scopes", FEDIVERSE_SCOPES),
+            ("website", host_url.as_str()),
+        ])
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(bad_instance)?
+        .json::<RegisteredApp>()
+        .await
+        .map_err(bad_instance)?;
+
+    let app = FediverseApp {
+        client_id: registered.client_id,
+        client_secret: registered.client_secret,
+        redirect_uri,
+    };
+    insert_into_cache(state, key, &app, Some(FEDIVERSE_APP_SECONDS)).await?;
+    Ok(app)
+}
+
+#[instrument]
+pub async fn fediverse_login(state: Arc<State>, instance: &str) -> SResult<OAuthAttempt> {
+    let instance = normalize_instance(instance)?;
+    let http = instance_client(&instance).await?;
+    let app = fediverse_app(state, &http, &instance).await?;
+    let client = fediverse_client(&instance, app)?;
+    Ok(start_attempt(
+        &client,
+        [FEDIVERSE_SCOPES.to_string()],
+        FEDIVERSE_PROVIDER,
+        Some(instance),
+    ))
+}
+
+// Account IDs are only unique on their own instance, so the ID is `{id}@{instance}`. There's no
+// email, instances don't share it.
+#[instrument(skip(attempt, code))]
+pub async fn fediverse_user_data(
+    state: Arc<State>,
+    attempt: &OAuthAttempt,
+    instance: &str,
+    code: String,
+) -> SResult<AuthProviderDataCommon> {
+    #[derive(Deserialize)]
+    struct Account {
+        id: String,
+        username: String,
+        avatar: String,
+    }
+
+    let http = instance_client(instance).await?;
+    let app = fediverse_app(state, &http, instance).await?;
+    let client = fediverse_client(instance, app)?;
+    let token = exchange_code(&client, &http, attempt, code).await?;
+
+    let account = http
+        .get(endpoints(instance).userinfo_url)
+        .bearer_auth(token.access_token().secret())
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(bad_instance)?
+        .json::<Account>()
+        .await
+        .map_err(bad_instance)?;
+
+    Ok(AuthProviderDataCommon {
+        provider: FEDIVERSE_PROVIDER.to_string(),
+        id: format!("{}@{instance}", account.id),
+        username: account.username,
+        profile_picture: account.avatar,
+        email: None,
+    })
+}
+
+impl_redis!(FediverseApp);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public(ip: &str) -> bool {
+        is_public(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn private_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(!public(ip), "{ip}");
+        }
+    }
+
+    #[test]
+    fn internet_addresses_are_public() {
+        for ip in [
+            "1.1.1.1",
+            "151.101.1.140",
+            "2606:4700::1111",
+            "::ffff:1.1.1.1",
+        ] {
+            assert!(public(ip), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn names_for_our_own_network_are_refused() {
+        assert!(instance_client("localhost").await.is_err());
+    }
+
+    #[test]
+    fn instances_are_plain_domains() {
+        assert_eq!(
+            normalize_instance("@kapi@Mastodon.Social").unwrap(),
+            "mastodon.social"
+        );
+        assert_eq!(
+            normalize_instance("https://mastodon.social/").unwrap(),
+            "mastodon.social"
+        );
+        for instance in ["127.0.0.1", "localhost", "[::1]", "mastodon.social:8080"] {
+            assert!(normalize_instance(instance).is_err(), "{instance}");
+        }
+    }
+}
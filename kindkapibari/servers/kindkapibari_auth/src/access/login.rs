@@ -1,17 +1,28 @@
-use crate::{access::oauth_thirdparty::AuthorizationProviders, State};
-use kindkapibari_core::secret::{
-    create_new_token_with_refresh, decode_access_token,
-    decode_access_token_without_time_verification, decode_refresh_token, JWTPair, RefreshClaims,
-    TokenClaims, TokenType,
+use crate::{
+    access::{
+        fediverse::FEDIVERSE_PROVIDER,
+        oauth_thirdparty::AuthProviderDataCommon,
+        oidc::{id_token_claims, user_data_by_id, FIRST_PARTY_CLIENT_ID},
+    },
+    State,
+};
+use kindkapibari_core::{
+    secret::{
+        create_id_token, create_new_token_with_refresh, decode_access_token,
+        decode_access_token_without_time_verification, decode_refresh_token, JWTPair,
+        RefreshClaims, TokenClaims, TokenType,
+    },
+    session::SessionClient,
 };
 use kindkapibari_schema::{
     error::ServerError,
-    schema::users::{connections, refresh_tokens, user},
+    redis::deny_access_token,
+    refresh::{rotate_refresh_token, Rotation},
+    schema::users::{connections, identities, refresh_tokens, user},
     SResult,
 };
 use sea_orm::{
-    ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, IntoActiveModel, ModelTrait,
-    QueryFilter,
+    ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, QueryFilter, TransactionTrait,
 };
 use std::{borrow::Cow, sync::Arc};
 use tracing::instrument;
@@ -38,11 +49,28 @@ pub async fn user_by_id(state: Arc<State>, id: u64) -> SResult<user::Model> {
     }
 }
 
+// starts a new session (refresh token family)
 #[instrument]
-pub async fn generate_login_token(state: Arc<State>, user: u64) -> SResult<JWTPair> {
+pub async fn generate_login_token(
+    state: Arc<State>,
+    user: u64,
+    client: SessionClient,
+) -> SResult<JWTPair> {
+    issue_login_token(state, user, client, None).await
+}
+
+// `previous` is the refresh token being rotated, if any. It's only used up in the transaction that
+// stores the new one, so a refresh that fails part way through leaves the old one working instead
+// of making the retry look like a replay.
+async fn issue_login_token(
+    state: Arc<State>,
+    user: u64,
+    client: SessionClient,
+    previous: Option<&refresh_tokens::Model>,
+) -> SResult<JWTPair> {
     let user = user_by_id(state.clone(), user).await?;
-    let token_id = state.id_generator.login_token_ids.generate_id();
-    let refresh_id = state.id_generator.refresh_token_ids.generate_id();
+    let token_id = state.id_generator.login_token_ids.generate_id()?;
+    let refresh_id = state.id_generator.refresh_token_ids.generate_id()?;
     let config = state.config.read().await;
     let access_claim = TokenClaims::new()
         .set_user(user.id)
@@ -53,33 +81,79 @@ pub async fn generate_login_token(state: Arc<State>, user: u64) -> SResult<JWTPa
 
     let mut refresh_claim: RefreshClaims = access_claim.clone().into().set_id(refresh_id);
 
-    let grant_pair = create_new_token_with_refresh(
+    let user_data = user_data_by_id(state.clone(), user.id).await?;
+    let id_claim = id_token_claims(
+        &config.host_url,
+        FIRST_PARTY_CLIENT_ID,
         &access_claim,
-        &refresh_claim,
-        config.signing_keys.login_key.as_bytes(),
-    )?;
+        &user,
+        &user_data,
+    );
+
+    let keyring = state.keyring.read().await;
+    let mut grant_pair = create_new_token_with_refresh(&access_claim, &refresh_claim, &keyring)
+        .map_err(|why| ServerError::InternalServer(Box::new(why)))?;
+    grant_pair.id_token = Some(
+        create_id_token(&id_claim, &keyring)
+            .map_err(|why| ServerError::InternalServer(Box::new(why)))?,
+    );
+
+    let issued = refresh_claim.iat as u64;
+    // a client that stops telling us things (background refreshes) keeps what we knew before
+    let (family, session_start, user_agent, ip) = match previous {
+        Some(previous) => (
+            previous.family,
+            previous.session_start,
+            client.user_agent.or_else(|| previous.user_agent.clone()),
+            client.ip.or_else(|| previous.ip.clone()),
+        ),
+        None => (refresh_id, issued, client.user_agent, client.ip),
+    };
 
     let refresh_active = refresh_tokens::ActiveModel {
         id: ActiveValue::Set(refresh_id),
         owner: ActiveValue::Set(user.id),
         related: ActiveValue::Set(token_id),
+        family: ActiveValue::Set(family),
+        authorization: ActiveValue::Set(None),
         expire: ActiveValue::Set(refresh_claim.exp as u64),
-        created: ActiveValue::Set(refresh_claim.iat as u64),
+        created: ActiveValue::Set(issued),
         revoked: ActiveValue::Set(false),
+        session_start: ActiveValue::Set(session_start),
+        last_used: ActiveValue::Set(issued),
+        user_agent: ActiveValue::Set(user_agent),
+        ip: ActiveValue::Set(ip),
         stored_secret: ActiveValue::Set(refresh_claim),
     };
 
-    refresh_active.insert(&state.database).await?;
+    let txn = state.database.begin().await?;
+    if let Some(previous) = previous {
+        if let Rotation::Replayed(family) = rotate_refresh_token(&txn, previous).await? {
+            txn.commit().await?;
+            for token in &family.access_tokens {
+                deny_access_token(state.clone(), *token).await?;
+            }
+            tracing::warn!(
+                user = previous.owner,
+                family = previous.family,
+                token = previous.id,
+                revoked = family.revoked,
+                "refresh token reused, revoked its family"
+            );
+            return Err(ServerError::Unauthorized);
+        }
+    }
+    refresh_active.insert(&txn).await?;
+    txn.commit().await?;
 
     Ok(grant_pair)
 }
 
 #[instrument]
 pub async fn verify_user_login_token(state: Arc<State>, token: String) -> SResult<user::Model> {
-    let config = state.config.read().await;
+    let keyring = state.keyring.read().await;
 
-    let token = decode_access_token(token, config.signing_keys.login_key.as_bytes())
-        .map_err(|_| ServerError::Unauthorized)?;
+    let token = decode_access_token(token, &*keyring).map_err(|_| ServerError::Unauthorized)?;
     if token.token_type != TokenType::Login {
         return Err(ServerError::Forbidden);
     }
@@ -91,36 +165,31 @@ pub async fn refresh_user_login_token(
     state: Arc<State>,
     access: String,
     refresh: String,
+    client: SessionClient,
 ) -> SResult<JWTPair> {
-    let config = state.config.read().await;
-
-    let expired_access = decode_access_token_without_time_verification(
-        access,
-        config.signing_keys.login_key.as_bytes(),
-    )
-    .map_err(|_| ServerError::Unauthorized)?;
-    if expired_access.token_type != TokenType::Login {
-        return Err(ServerError::Forbidden);
-    }
-
-    let refresh = decode_refresh_token(refresh, config.signing_keys.login_key.as_bytes())
-        .map_err(|_| ServerError::Unauthorized)?;
-    if refresh.token_type != TokenType::Login
-        && refresh.reference_token != expired_access.jti
-        && refresh.user_id != expired_access.user_id
+    // don't hold on to the keyring while issuing, that takes it again
+    let (expired_access, refresh) = {
+        let keyring = state.keyring.read().await;
+        let expired_access = decode_access_token_without_time_verification(access, &*keyring)
+            .map_err(|_| ServerError::Unauthorized)?;
+        let refresh =
+            decode_refresh_token(refresh, &*keyring).map_err(|_| ServerError::Unauthorized)?;
+        (expired_access, refresh)
+    };
+    if expired_access.token_type != TokenType::Login
+        || refresh.token_type != TokenType::Login
+        || refresh.reference_token != expired_access.jti
+        || refresh.user_id != expired_access.user_id
     {
         return Err(ServerError::Forbidden);
     }
 
-    let mut db_refreeh_tkn = refresh_tokens::Entity::find_by_id(refresh.jti)
+    let stored = refresh_tokens::Entity::find_by_id(refresh.jti)
         .one(&state.database)
         .await?
-        .ok_or(ServerError::Unauthorized)?
-        .into_active_model();
-    db_refreeh_tkn.revoked = ActiveValue::Set(true);
-    db_refreeh_tkn.update(&state.database).await?;
+        .ok_or(ServerError::Unauthorized)?;
 
-    generate_login_token(state.clone(), refresh.reference_token)
+    issue_login_token(state, stored.owner, client, Some(&stored)).await
 }
 
 // #[instrument]
@@ -130,43 +199,33 @@ pub async fn refresh_user_login_token(
 //     base64::encode(blake3::hash(&[rng_gen.as_slice(), salt.as_slice()].concat()).as_bytes())
 // }
 
-#[allow(clippy::cast_sign_loss)]
 #[instrument]
 pub async fn detect_user_already_exists_auth_provider(
     state: Arc<State>,
-    maybeuser: AuthorizationProviders,
+    maybeuser: &AuthProviderDataCommon,
 ) -> SResult<Option<u64>> {
-    // check if the account exists in connections
-    let prepared = connections::Entity::find();
+    // fediverse accounts live on connections, and never come with an email
+    if maybeuser.provider == FEDIVERSE_PROVIDER {
+        let exists = connections::Entity::find()
+            .filter(connections::Column::FediverseId.eq(Some(maybeuser.id.clone())))
+            .one(&state.database)
+            .await?;
+        return Ok(exists.map(|connection| connection.user_id));
+    }
 
-    let exists = match &maybeuser {
-        AuthorizationProviders::Twitter(twt) => {
-            prepared
-                .filter(connections::Column::TwitterId.eq(Some(twt.twitter_id)))
-                .one(&state.database)
-                .await?
-        }
-        AuthorizationProviders::Github(ghb) => {
-            prepared
-                .filter(connections::Column::GithubId.eq(Some(ghb.github_id as u64)))
-                .one(&state.database)
-                .await?
-        }
-    };
+    // check if the account is linked already
+    let exists = identities::Entity::find_by_id((maybeuser.provider.clone(), maybeuser.id.clone()))
+        .one(&state.database)
+        .await?;
 
-    if let Some(user) = exists {
-        return Ok(Some(user.user_id));
+    if let Some(identity) = exists {
+        return Ok(Some(identity.user_id));
     }
 
     // email account
-    let email_chk = match maybeuser {
-        AuthorizationProviders::Twitter(twt) => twt.email,
-        AuthorizationProviders::Github(ghb) => ghb.email,
-    };
-
-    if let Some(email) = email_chk {
+    if let Some(email) = &maybeuser.email {
         return match user::Entity::find()
-            .filter(user::Column::Email.eq(email))
+            .filter(user::Column::Email.eq(email.as_str()))
             .one(&state.database)
             .await?
         {
@@ -1,2 +1,5 @@
+pub mod fediverse;
 pub mod login;
+pub mod oauth;
 pub mod oauth_thirdparty;
+pub mod oidc;
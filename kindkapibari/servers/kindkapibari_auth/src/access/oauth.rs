@@ -0,0 +1,492 @@
+use crate::{
+    access::{login::user_by_id, oidc::oauth_id_token},
+    State,
+};
+use axum::{
+    http::{header::WWW_AUTHENTICATE, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{TimeZone, Utc};
+use kindkapibari_core::{
+    oauth::{OpenIdRequest, TokenResponse},
+    scopes::{KKBScope, KKBScopes},
+    secret::{
+        create_new_token, create_new_token_with_refresh,
+        decode_access_token_without_time_verification, decode_refresh_token, verify_client_secret,
+        RefreshClaims, TokenClaims, TokenType, ACCESS_TOKEN_SECONDS,
+    },
+    session::SessionClient,
+    snowflake::SnowflakeError,
+};
+use kindkapibari_schema::{
+    error::ServerError,
+    oauth::revoke_authorizations,
+    redis::{deny_access_token, record_client_token, redeem_authorization_code},
+    refresh::{rotate_refresh_token, Rotation},
+    schema::{
+        applications,
+        users::{oauth_authorizations, refresh_tokens},
+    },
+    SResult,
+};
+use oauth2::{PkceCodeChallenge, PkceCodeVerifier};
+use sea_orm::{ActiveModelTrait, ActiveValue, DbErr, EntityTrait, TransactionTrait};
+use serde_json::json;
+use std::{borrow::Cow, sync::Arc};
+use thiserror::Error;
+use tracing::instrument;
+
+// RFC 6749 5.2, apps expect token endpoint errors as JSON with one of these codes
+#[derive(Debug, Error)]
+pub enum OAuthError {
+    #[error("{0}")]
+    InvalidRequest(Cow<'static, str>),
+    #[error("client authentication failed")]
+    InvalidClient,
+    #[error("the grant is invalid, expired or revoked")]
+    InvalidGrant,
+    #[error("this client can't use this grant type")]
+    UnauthorizedClient,
+    #[error("unsupported grant type")]
+    UnsupportedGrantType,
+    #[error("{0}")]
+    InvalidScope(Cow<'static, str>),
+    #[error(transparent)]
+    Server(#[from] ServerError),
+}
+
+impl OAuthError {
+    fn code(&self) -> &'static str {
+        match self {
+            OAuthError::InvalidRequest(_) => "invalid_request",
+            OAuthError::InvalidClient => "invalid_client",
+            OAuthError::InvalidGrant => "invalid_grant",
+            OAuthError::UnauthorizedClient => "unauthorized_client",
+            OAuthError::UnsupportedGrantType => "unsupported_grant_type",
+            OAuthError::InvalidScope(_) => "invalid_scope",
+            OAuthError::Server(_) => "server_error",
+        }
+    }
+}
+
+impl From<DbErr> for OAuthError {
+    fn from(why: DbErr) -> Self {
+        OAuthError::Server(why.into())
+    }
+}
+
+impl From<SnowflakeError> for OAuthError {
+    fn from(why: SnowflakeError) -> Self {
+        OAuthError::Server(why.into())
+    }
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        if let OAuthError::Server(why) = self {
+            return why.into_response();
+        }
+
+        let body = Json(json!({
+            "error": self.code(),
+            "error_description": self.to_string(),
+        }));
+        match self {
+            OAuthError::InvalidClient => (
+                StatusCode::UNAUTHORIZED,
+                [(WWW_AUTHENTICATE, "Basic realm=\"kindkapibari\"")],
+                body,
+            )
+                .into_response(),
+            _ => (StatusCode::BAD_REQUEST, body).into_response(),
+        }
+    }
+}
+
+// who's asking, from HTTP Basic or the client_id/client_secret form fields
+pub struct ClientCredentials {
+    pub id: String,
+    pub secret: Option<String>,
+}
+
+#[instrument(skip(credentials))]
+pub async fn authenticate_client(
+    state: Arc<State>,
+    credentials: ClientCredentials,
+) -> Result<applications::Model, OAuthError> {
+    let id = credentials
+        .id
+        .parse::<u64>()
+        .map_err(|_| OAuthError::InvalidClient)?;
+    let application = applications::Entity::find_by_id(id)
+        .one(&state.database)
+        .await?
+        .ok_or(OAuthError::InvalidClient)?;
+
+    // public clients can't keep a secret, PKCE is all they get
+    if !application.confidential {
+        return Ok(application);
+    }
+    let (secret, hash) = match (credentials.secret, application.secret_hash.clone()) {
+        (Some(secret), Some(hash)) => (secret, hash),
+        _ => return Err(OAuthError::InvalidClient),
+    };
+    // argon2 is slow on purpose, keep it off the async threads
+    let matches = tokio::task::spawn_blocking(move || verify_client_secret(&secret, &hash))
+        .await
+        .map_err(|why| ServerError::InternalServer(Box::new(why)))?;
+    if matches {
+        Ok(application)
+    } else {
+        Err(OAuthError::InvalidClient)
+    }
+}
+
+// RFC 7636 4.1, verifiers are 43 to 128 characters
+fn pkce_matches(verifier: &str, challenge: &str) -> bool {
+    (43..=128).contains(&verifier.len())
+        && PkceCodeChallenge::from_code_verifier_sha256(&PkceCodeVerifier::new(
+            verifier.to_string(),
+        ))
+        .as_str()
+            == challenge
+}
+
+// an optional `scope` can only ask for less than what's allowed
+fn narrow_scopes(requested: Option<&str>, allowed: &KKBScopes) -> Result<KKBScopes, OAuthError> {
+    // `openid` is fine to send again, there's just no new id_token
+    let requested = match requested {
+        Some(requested) => {
+            KKBScopes::parse_with_openid(requested)
+                .map_err(|why| OAuthError::InvalidScope(Cow::from(why.to_string())))?
+                .0
+        }
+        None => KKBScopes::default(),
+    };
+    if requested.is_empty() {
+        return Ok(allowed.clone());
+    }
+    if let Some(scope) = requested.iter().find(|scope| !allowed.grants(scope)) {
+        return Err(OAuthError::InvalidScope(Cow::from(format!(
+            "{} wasn't granted",
+            scope.scope_name()
+        ))));
+    }
+    Ok(requested)
+}
+
+#[instrument(skip(code, code_verifier))]
+pub async fn exchange_authorization_code(
+    state: Arc<State>,
+    application: applications::Model,
+    code: &str,
+    redirect_uri: &str,
+    code_verifier: &str,
+    client: SessionClient,
+) -> Result<TokenResponse, OAuthError> {
+    let grant = redeem_authorization_code(state.clone(), code)
+        .await?
+        .ok_or(OAuthError::InvalidGrant)?;
+    if grant.application != application.id
+        || redirect_uri != grant.redirect_uri
+        || !pkce_matches(code_verifier, &grant.code_challenge)
+    {
+        return Err(OAuthError::InvalidGrant);
+    }
+
+    let now = Utc::now();
+    let authorization = oauth_authorizations::ActiveModel {
+        id: ActiveValue::Set(state.id_generator.authorization_ids.generate_id()?),
+        owner: ActiveValue::Set(grant.user),
+        application: ActiveValue::Set(application.id),
+        scopes: ActiveValue::Set(grant.scopes),
+        expire: ActiveValue::Set(now),
+        created: ActiveValue::Set(now),
+        last_used: ActiveValue::Set(now),
+        access_token: ActiveValue::Set(0),
+        revoked: ActiveValue::Set(false),
+    }
+    .insert(&state.database)
+    .await?;
+
+    let scopes = authorization.scopes.to_vec();
+    issue_oauth_token(
+        state,
+        &authorization,
+        scopes,
+        client,
+        None,
+        grant.openid.as_ref(),
+    )
+    .await
+}
+
+#[instrument(skip(refresh))]
+pub async fn refresh_oauth_token(
+    state: Arc<State>,
+    application: applications::Model,
+    refresh: &str,
+    scope: Option<&str>,
+    client: SessionClient,
+) -> Result<TokenResponse, OAuthError> {
+    let claims = {
+        let keyring = state.keyring.read().await;
+        decode_refresh_token(refresh, &*keyring).map_err(|_| OAuthError::InvalidGrant)?
+    };
+    if claims.token_type != TokenType::OAuth {
+        return Err(OAuthError::InvalidGrant);
+    }
+
+    let stored = refresh_tokens::Entity::find_by_id(claims.jti)
+        .one(&state.database)
+        .await?
+        .ok_or(OAuthError::InvalidGrant)?;
+    let authorization = match stored.authorization {
+        Some(id) => {
+            oauth_authorizations::Entity::find_by_id(id)
+                .one(&state.database)
+                .await?
+        }
+        None => None,
+    }
+    .ok_or(OAuthError::InvalidGrant)?;
+    if authorization.application != application.id || authorization.revoked {
+        return Err(OAuthError::InvalidGrant);
+    }
+    // before rotating, so asking for too much doesn't burn the token
+    let scopes = narrow_scopes(scope, &authorization.scopes)?;
+
+    issue_oauth_token(
+        state,
+        &authorization,
+        scopes.to_vec(),
+        client,
+        Some(&stored),
+        None,
+    )
+    .await
+}
+
+// The app acting as whoever made it. No refresh token (RFC 6749 4.4.3) and no authorization,
+// nobody granted anything. The token is only remembered so rotating the secret or deleting the
+// app can take it back.
+#[instrument]
+pub async fn client_credentials_token(
+    state: Arc<State>,
+    application: applications::Model,
+    scope: Option<&str>,
+) -> Result<TokenResponse, OAuthError> {
+    if !application.confidential {
+        return Err(OAuthError::UnauthorizedClient);
+    }
+    let mut scopes = narrow_scopes(scope, &application.scopes)?;
+    scopes.retain(|scope| *scope != KKBScope::OfflineRead);
+
+    let creator = user_by_id(state.clone(), application.creator).await?;
+    let access_claim = TokenClaims::new()
+        .set_user(creator.id)
+        .set_role(creator.roles)
+        .set_id(state.id_generator.login_token_ids.generate_id()?)
+        .set_token_type(TokenType::OAuth)
+        .set_machine_id(state.config.read().await.machine_id)
+        .set_scopes(scopes.to_vec())
+        .set_client(Some(application.id));
+
+    let access = {
+        let keyring = state.keyring.read().await;
+        create_new_token(&access_claim, &keyring)
+            .map_err(|why| ServerError::InternalServer(Box::new(why)))?
+    };
+    record_client_token(state, application.id, access_claim.jti).await?;
+
+    Ok(TokenResponse {
+        access_token: access,
+        token_type: "Bearer".to_string(),
+        expires_in: ACCESS_TOKEN_SECONDS,
+        refresh_token: None,
+        scope: scopes.to_scope_string(),
+        id_token: None,
+    })
+}
+
+// `previous` is the refresh token being rotated, if any. Like login sessions it's only used up in
+// the transaction that stores what replaces it. `openid` only comes with a code.
+#[allow(clippy::cast_possible_wrap)]
+async fn issue_oauth_token(
+    state: Arc<State>,
+    authorization: &oauth_authorizations::Model,
+    scopes: Vec<KKBScope>,
+    client: SessionClient,
+    previous: Option<&refresh_tokens::Model>,
+    openid: Option<&OpenIdRequest>,
+) -> Result<TokenResponse, OAuthError> {
+    let user = user_by_id(state.clone(), authorization.owner).await?;
+    let token_id = state.id_generator.login_token_ids.generate_id()?;
+    let access_claim = TokenClaims::new()
+        .set_user(user.id)
+        .set_role(user.roles)
+        .set_id(token_id)
+        .set_token_type(TokenType::OAuth)
+        .set_machine_id(state.config.read().await.machine_id)
+        .set_scopes(scopes.clone())
+        .set_client(Some(authorization.application));
+
+    // only apps that asked to keep access while the user is away get to refresh
+    let refresh_claim = if scopes.contains(&KKBScope::OfflineRead) {
+        Some(
+            RefreshClaims::from(access_claim.clone())
+                .set_id(state.id_generator.refresh_token_ids.generate_id()?),
+        )
+    } else {
+        None
+    };
+
+    let (access, refresh) = {
+        let keyring = state.keyring.read().await;
+        match &refresh_claim {
+            Some(refresh_claim) => {
+                let pair = create_new_token_with_refresh(&access_claim, refresh_claim, &keyring)
+                    .map_err(|why| ServerError::InternalServer(Box::new(why)))?;
+                (pair.access, Some(pair.refresh))
+            }
+            None => (
+                create_new_token(&access_claim, &keyring)
+                    .map_err(|why| ServerError::InternalServer(Box::new(why)))?,
+                None,
+            ),
+        }
+    };
+
+    let id_token = match openid {
+        Some(openid) => Some(
+            oauth_id_token(
+                state.clone(),
+                openid,
+                authorization.application,
+                &access_claim,
+                &user,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+
+    let txn = state.database.begin().await?;
+    if let Some(previous) = previous {
+        // same as login sessions, a replayed refresh token takes the whole authorization with it
+        if let Rotation::Replayed(_) = rotate_refresh_token(&txn, previous).await? {
+            txn.commit().await?;
+            revoke_authorization(state.clone(), authorization.id).await?;
+            tracing::warn!(
+                user = authorization.owner,
+                application = authorization.application,
+                authorization = authorization.id,
+                token = previous.id,
+                "oauth refresh token reused, revoked its authorization"
+            );
+            return Err(OAuthError::InvalidGrant);
+        }
+    }
+
+    let issued = access_claim.iat as u64;
+    if let Some(refresh_claim) = refresh_claim {
+        let (session_start, user_agent, ip) = match previous {
+            Some(previous) => (
+                previous.session_start,
+                client.user_agent.or_else(|| previous.user_agent.clone()),
+                client.ip.or_else(|| previous.ip.clone()),
+            ),
+            None => (issued, client.user_agent, client.ip),
+        };
+        refresh_tokens::ActiveModel {
+            id: ActiveValue::Set(refresh_claim.jti),
+            owner: ActiveValue::Set(user.id),
+            related: ActiveValue::Set(token_id),
+            family: ActiveValue::Set(authorization.id),
+            authorization: ActiveValue::Set(Some(authorization.id)),
+            expire: ActiveValue::Set(refresh_claim.exp as u64),
+            created: ActiveValue::Set(issued),
+            revoked: ActiveValue::Set(false),
+            session_start: ActiveValue::Set(session_start),
+            last_used: ActiveValue::Set(issued),
+            user_agent: ActiveValue::Set(user_agent),
+            ip: ActiveValue::Set(ip),
+            stored_secret: ActiveValue::Set(refresh_claim),
+        }
+        .insert(&txn)
+        .await?;
+    }
+
+    // lives as long as whatever we just handed out does
+    let expire = refresh_claim.map_or(access_claim.exp, |refresh_claim| refresh_claim.exp);
+    let mut active: oauth_authorizations::ActiveModel = authorization.clone().into();
+    active.access_token = ActiveValue::Set(token_id);
+    active.last_used = ActiveValue::Set(Utc::now());
+    active.expire = ActiveValue::Set(Utc.timestamp(expire as i64, 0));
+    active.update(&txn).await?;
+    txn.commit().await?;
+
+    Ok(TokenResponse {
+        access_token: access,
+        token_type: "Bearer".to_string(),
+        expires_in: ACCESS_TOKEN_SECONDS,
+        refresh_token: refresh,
+        scope: KKBScopes::from(scopes).to_scope_string(),
+        id_token,
+    })
+}
+
+// RFC 7009, anything that's bad or belongs to another app is ignored and they still get a 200
+#[instrument(skip(token))]
+pub async fn revoke_oauth_token(
+    state: Arc<State>,
+    application: applications::Model,
+    token: &str,
+) -> Result<(), OAuthError> {
+    // the two never decode as each other, so there's no need for token_type_hint
+    let (refresh, access) = {
+        let keyring = state.keyring.read().await;
+        (
+            decode_refresh_token(token, &*keyring).ok(),
+            decode_access_token_without_time_verification(token, &*keyring).ok(),
+        )
+    };
+
+    if let Some(refresh) = refresh.filter(|claims| claims.token_type == TokenType::OAuth) {
+        let authorization = match refresh_tokens::Entity::find_by_id(refresh.jti)
+            .one(&state.database)
+            .await?
+            .and_then(|stored| stored.authorization)
+        {
+            Some(id) => {
+                oauth_authorizations::Entity::find_by_id(id)
+                    .one(&state.database)
+                    .await?
+            }
+            None => None,
+        };
+        if let Some(authorization) =
+            authorization.filter(|authorization| authorization.application == application.id)
+        {
+            revoke_authorization(state, authorization.id).await?;
+        }
+    } else if let Some(access) = access.filter(|claims| claims.client == Some(application.id)) {
+        deny_access_token(state, access.jti).await?;
+    }
+    Ok(())
+}
+
+// the authorization, its refresh tokens and any of its access tokens that haven't expired yet
+#[instrument]
+pub async fn revoke_authorization(state: Arc<State>, id: u64) -> SResult<()> {
+    let revoked = revoke_authorizations(
+        &state.database,
+        oauth_authorizations::Entity::find_by_id(id),
+    )
+    .await?;
+    for token in revoked.access_tokens {
+        deny_access_token(state.clone(), token).await?;
+    }
+    Ok(())
+}
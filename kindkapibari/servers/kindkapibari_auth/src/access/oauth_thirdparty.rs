@@ -1,127 +1,41 @@
+use crate::{
+    access::fediverse::{fediverse_user_data, FEDIVERSE_PROVIDER},
+    config::{ClaimMapping, OAuth},
+    State,
+};
+use axum::http::header::{ACCEPT, USER_AGENT};
 use kindkapibari_core::{impl_redis, impl_sea_orm};
 use kindkapibari_schema::{error::ServerError, SResult};
 use oauth2::{
     basic::{BasicClient, BasicTokenResponse},
-    AuthUrl, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge, RedirectUrl, Scope,
-    TokenResponse, TokenUrl,
+    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, HttpRequest, HttpResponse,
+    PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse, TokenUrl,
 };
-use paste::paste;
-use reqwest::Client;
+use reqwest::{redirect::Policy, Client, ClientBuilder};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::{borrow::Cow, fmt::Debug, sync::Arc};
 use tracing::instrument;
 
-pub type AResult<T> = Result<T, Box<dyn std::error::Error>>;
-
-trait OAuthProviderBasicInfo {
-    fn id_as_str(&self) -> String;
-    fn username(&self) -> &str;
-    fn handle(&self) -> &str;
-    fn profile_picture_url(&self) -> &str;
-    fn email(&self) -> &Option<String>;
-}
-
-#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
-pub struct Twitter {
-    pub twitter_id: u64,
-    pub username: String,
-    pub handle: String,
-    pub profile_picture: String,
-    pub email: Option<String>, // Always `None` for now due to twitter API v2
-}
-
-impl OAuthProviderBasicInfo for Twitter {
-    fn id_as_str(&self) -> String {
-        self.twitter_id.to_string()
-    }
-
-    fn username(&self) -> &str {
-        &self.username
-    }
-
-    fn handle(&self) -> &str {
-        &self.handle
-    }
-
-    fn profile_picture_url(&self) -> &str {
-        self.profile_picture.as_ref()
-    }
-
-    fn email(&self) -> &Option<String> {
-        &None
-    }
-}
-
-#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
-pub struct Github {
-    pub github_id: i64,
-    pub username: String,
-    pub profile_picture: String,
-    pub email: Option<String>,
-}
-
-impl OAuthProviderBasicInfo for Github {
-    fn id_as_str(&self) -> String {
-        self.github_id.to_string()
-    }
-
-    fn username(&self) -> &str {
-        &self.username
-    }
-
-    fn handle(&self) -> &str {
-        self.username()
-    }
-
-    fn profile_picture_url(&self) -> &str {
-        self.profile_picture.as_ref()
-    }
-
-    fn email(&self) -> &Option<String> {
-        &self.email
-    }
-}
-
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-pub enum AuthorizationProviders {
-    Twitter(Twitter),
-    Github(Github),
-}
-
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AuthProviderDataCommon {
-    pub id: u64,
+    // the name the provider is configured under, and the user's ID on it
+    pub provider: String,
+    pub id: String,
     pub username: String,
     pub profile_picture: String,
+    // only ever one the provider vouched for, see `OAuth::trust_email`
     pub email: Option<String>,
 }
 
-impl From<AuthorizationProviders> for AuthProviderDataCommon {
-    #[allow(clippy::cast_sign_loss)]
-    fn from(authp: AuthorizationProviders) -> Self {
-        match authp {
-            AuthorizationProviders::Twitter(twt) => Self {
-                id: twt.twitter_id,
-                username: twt.username,
-                profile_picture: twt.profile_picture,
-                email: twt.email,
-            },
-            AuthorizationProviders::Github(ghb) => Self {
-                id: ghb.github_id as u64,
-                username: ghb.username,
-                profile_picture: ghb.profile_picture,
-                email: ghb.email,
-            },
-        }
-    }
-}
-
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct OAuthAttempt {
     auth_url: String,
     csrf_token: String,
     pkce_verifier: String,
     authorizer: String,
+    // the fediverse instance, those aren't configured providers
+    instance: Option<String>,
 }
 
 impl OAuthAttempt {
@@ -141,155 +55,593 @@ impl OAuthAttempt {
     pub fn authorizer(&self) -> &str {
         &self.authorizer
     }
+    #[must_use]
+    pub fn instance(&self) -> Option<&str> {
+        self.instance.as_deref()
+    }
+}
+
+// where to send the user, where to get tokens and where to ask who they are
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProviderEndpoints {
+    pub authorize_url: String,
+    pub token_url: String,
+    pub userinfo_url: String,
+}
+
+// the bits of an OpenID Provider Metadata document we care about
+#[derive(Deserialize)]
+struct Discovery {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
 }
 
 pub fn get_oauth_client(
-    authorize_url: String,
-    token_url: String,
+    endpoints: &ProviderEndpoints,
     redirect_url: String,
     client_id: String,
     client_secret: String,
-) -> Result<BasicClient, Box<dyn std::error::Error>> {
+) -> SResult<BasicClient> {
+    let bad_url = |why| ServerError::InternalServer(Box::new(why));
     Ok(BasicClient::new(
         ClientId::new(client_id),
         Some(ClientSecret::new(client_secret)),
-        AuthUrl::new(authorize_url)?,
-        Some(TokenUrl::new(token_url)?),
+        AuthUrl::new(endpoints.authorize_url.clone()).map_err(bad_url)?,
+        Some(TokenUrl::new(endpoints.token_url.clone()).map_err(bad_url)?),
     )
-    .set_redirect_uri(RedirectUrl::new(redirect_url)?))
+    .set_redirect_uri(RedirectUrl::new(redirect_url).map_err(bad_url)?))
 }
 
-macro_rules! oauth_providers {
-    ($( [$provider:ident { $($scope:expr),* }] ),*) => {
-        $(
-            paste! {
-                #[instrument]
-                pub async fn [<oauth_login_ $provider>](state: Arc<crate::State>, url: &str) -> SResult<OAuthAttempt> {
-                    let config = state.config.read().await.clone();
-                    let client = get_oauth_client(
-                        config.oauth.$provider.authorize_url,
-                        config.oauth.$provider.token_url,
-                        format!("{url}/redirect"),
-                        config.oauth.$provider.client_id,
-                        config.oauth.$provider.secret,
-                    ).map_err(|x| ServerError::InternalServer(x))?;
-                    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256_len(96);
-
-                    let (auth_url, csrf) = client
-                        .authorize_url(CsrfToken::new_random)
-                        $(
-                        .add_scope(Scope::new($scope.to_string()))
-                        )*
-                        .set_pkce_challenge(pkce_challenge)
-                        .url();
-
-                    return Ok(
-                        OAuthAttempt {
-                            auth_url: auth_url.to_string(),
-                            csrf_token: csrf.secret().to_string(),
-                            pkce_verifier: pkce_verifier.secret().to_string(),
-                            authorizer: stringify!($provider).to_string(),
-                        }
-                    );
-                }
-            }
-        )*
-    };
+#[instrument]
+async fn provider_config(state: Arc<State>, provider: &str) -> SResult<OAuth> {
+    state
+        .config
+        .read()
+        .await
+        .oauth
+        .providers
+        .get(provider)
+        .cloned()
+        // taken by the fediverse login
+        .filter(|_| provider != FEDIVERSE_PROVIDER)
+        .ok_or_else(|| {
+            ServerError::NotFound(Cow::from("login provider"), Cow::from(provider.to_string()))
+        })
 }
 
-oauth_providers!([twitter {"users.read", "tweet.read"}], [github {"read:user", "user:email"}]);
+// Discovery documents are cached per provider, anything set in the config is used as is.
+#[instrument(skip(config))]
+pub async fn provider_endpoints(
+    state: Arc<State>,
+    provider: &str,
+    config: &OAuth,
+) -> SResult<Arc<ProviderEndpoints>> {
+    if let (Some(authorize_url), Some(token_url), Some(userinfo_url)) = (
+        &config.authorize_url,
+        &config.token_url,
+        &config.userinfo_url,
+    ) {
+        return Ok(Arc::new(ProviderEndpoints {
+            authorize_url: authorize_url.clone(),
+            token_url: token_url.clone(),
+            userinfo_url: userinfo_url.clone(),
+        }));
+    }
+    if let Some(endpoints) = state.caches.providers_cache.get(&provider.to_string()) {
+        return Ok(endpoints);
+    }
 
-#[instrument]
-pub async fn get_user_data(
+    let endpoints = Arc::new(discover_endpoints(provider, config).await?);
+    state
+        .caches
+        .providers_cache
+        .insert(provider.to_string(), endpoints.clone())
+        .await;
+    Ok(endpoints)
+}
+
+#[instrument(skip(config))]
+async fn discover_endpoints(provider: &str, config: &OAuth) -> SResult<ProviderEndpoints> {
+    let discovery_url = config.discovery_url.as_ref().ok_or_else(|| {
+        ServerError::ISErr(Cow::from(format!(
+            "login provider {provider} has neither a discovery_url nor all of its endpoints"
+        )))
+    })?;
+    let discovered = Client::new()
+        .get(discovery_url)
+        .header(ACCEPT, "application/json")
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|why| ServerError::InternalServer(Box::new(why)))?
+        .json::<Discovery>()
+        .await
+        .map_err(|why| ServerError::InternalServer(Box::new(why)))?;
+
+    let userinfo_url = config
+        .userinfo_url
+        .clone()
+        .or(discovered.userinfo_endpoint)
+        .ok_or_else(|| {
+            ServerError::ISErr(Cow::from(format!(
+                "login provider {provider} has no userinfo endpoint"
+            )))
+        })?;
+    Ok(ProviderEndpoints {
+        authorize_url: config
+            .authorize_url
+            .clone()
+            .unwrap_or(discovered.authorization_endpoint),
+        token_url: config
+            .token_url
+            .clone()
+            .unwrap_or(discovered.token_endpoint),
+        userinfo_url,
+    })
+}
+
+#[instrument(skip(config))]
+async fn provider_client(
+    state: Arc<State>,
+    provider: &str,
+    config: &OAuth,
+) -> SResult<(BasicClient, Arc<ProviderEndpoints>)> {
+    let endpoints = provider_endpoints(state.clone(), provider, config).await?;
+    let host_url = state.config.read().await.host_url.clone();
+    let client = get_oauth_client(
+        &endpoints,
+        format!("{host_url}/redirect"),
+        config.client_id.clone(),
+        config.secret.clone(),
+    )?;
+    Ok((client, endpoints))
+}
+
+pub(crate) fn start_attempt(
+    client: &BasicClient,
+    scopes: impl IntoIterator<Item = String>,
     authorizer: &str,
-    token: BasicTokenResponse,
-) -> SResult<AuthorizationProviders> {
-    match authorizer {
-        "twitter" => Ok(AuthorizationProviders::Twitter(
-            get_twitter_info(token)
-                .await
-                .map_err(|x| ServerError::InternalServer(x))?,
-        )),
-        "github" => Ok(AuthorizationProviders::Github(
-            get_github_info(token)
-                .await
-                .map_err(|x| ServerError::InternalServer(x))?,
-        )),
-        _ => Err(ServerError::BadRequest(Cow::from("bad authorizer"))),
+    instance: Option<String>,
+) -> OAuthAttempt {
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256_len(96);
+
+    let (auth_url, csrf) = client
+        .authorize_url(CsrfToken::new_random)
+        .add_scopes(scopes.into_iter().map(Scope::new))
+        .set_pkce_challenge(pkce_challenge)
+        .url();
+
+    OAuthAttempt {
+        auth_url: auth_url.to_string(),
+        csrf_token: csrf.secret().clone(),
+        pkce_verifier: pkce_verifier.secret().clone(),
+        authorizer: authorizer.to_string(),
+        instance,
     }
 }
 
-#[instrument]
-async fn get_twitter_info(token: BasicTokenResponse) -> AResult<Twitter> {
-    #[derive(Serialize, Deserialize)]
-    struct TwitterUserData {
-        profile_image_url: String,
-        id: String,
-        username: String,
-        name: String,
-    }
+// a token endpoint has no business sending us anywhere else
+pub(crate) fn no_redirects() -> ClientBuilder {
+    Client::builder().redirect(Policy::none())
+}
 
-    #[derive(Serialize, Deserialize)]
-    struct TwitterUser {
-        data: TwitterUserData,
-    }
+// `http` is whoever's allowed to talk to the provider, the fediverse pins where instances are
+#[instrument(skip(client, http, attempt, code))]
+pub(crate) async fn exchange_code(
+    client: &BasicClient,
+    http: &Client,
+    attempt: &OAuthAttempt,
+    code: String,
+) -> SResult<BasicTokenResponse> {
+    client
+        .exchange_code(AuthorizationCode::new(code))
+        .set_pkce_verifier(PkceCodeVerifier::new(attempt.pkce_verifier().to_string()))
+        .request_async(|request| send(http, request))
+        .await
+        .map_err(|why| ServerError::InternalServer(Box::new(why)))
+}
 
-    let user = Client::new()
-        .get("https://api.twitter.com/2/users/me?user.fields=id,name,username,profile_image_url")
-        .bearer_auth(token.access_token().secret())
+async fn send(http: &Client, request: HttpRequest) -> Result<HttpResponse, reqwest::Error> {
+    let response = http
+        .request(request.method, request.url)
+        .headers(request.headers)
+        .body(request.body)
         .send()
-        .await?
-        .json::<TwitterUser>()
         .await?;
-
-    Ok(Twitter {
-        twitter_id: user.data.id.parse()?,
-        username: user.data.name,
-        handle: user.data.username,
-        profile_picture: user.data.profile_image_url,
-        email: None,
+    Ok(HttpResponse {
+        status_code: response.status(),
+        headers: response.headers().clone(),
+        body: response.bytes().await?.to_vec(),
     })
 }
 
 #[instrument]
-async fn get_github_info(token: BasicTokenResponse) -> AResult<Github> {
-    #[derive(Default, Serialize, Deserialize)]
-    #[serde(default)]
-    struct GithubUser {
-        id: i64,
-        login: String,
-        avatar_url: String,
-        email: Option<String>,
+pub async fn oauth_login(state: Arc<State>, provider: &str) -> SResult<OAuthAttempt> {
+    let config = provider_config(state.clone(), provider).await?;
+    let (client, _) = provider_client(state, provider, &config).await?;
+    Ok(start_attempt(&client, config.scopes, provider, None))
+}
+
+// The code is swapped for a token straight with the provider over TLS, so asking its userinfo
+// endpoint who the token belongs to is as good as checking an id_token (OIDC Core 3.1.3.7).
+#[instrument(skip(attempt, code))]
+pub async fn get_user_data(
+    state: Arc<State>,
+    attempt: &OAuthAttempt,
+    code: String,
+) -> SResult<AuthProviderDataCommon> {
+    if let Some(instance) = attempt.instance() {
+        return fediverse_user_data(state, attempt, instance, code).await;
     }
 
-    let user = Client::new()
-        .get("https://api.github.com/user")
-        .bearer_auth(token.access_token().secret())
-        .header("Accept", "application/vnd.github.v3+json")
+    let provider = attempt.authorizer();
+    let config = provider_config(state.clone(), provider).await?;
+    let (client, endpoints) = provider_client(state, provider, &config).await?;
+    let http = no_redirects()
+        .build()
+        .map_err(|why| ServerError::InternalServer(Box::new(why)))?;
+    let token = exchange_code(&client, &http, attempt, code).await?;
+    let userinfo = userinfo(&endpoints.userinfo_url, token.access_token().secret()).await?;
+
+    map_claims(provider, &config, &userinfo)
+}
+
+#[instrument(skip(access_token))]
+async fn userinfo(userinfo_url: &str, access_token: &str) -> SResult<Value> {
+    Client::new()
+        .get(userinfo_url)
+        .bearer_auth(access_token)
+        .header(ACCEPT, "application/json")
+        // github turns away anything without one
+        .header(USER_AGENT, "kindkapibari")
         .send()
-        .await?
-        .json::<GithubUser>()
-        .await?;
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|why| ServerError::InternalServer(Box::new(why)))?
+        .json::<Value>()
+        .await
+        .map_err(|why| ServerError::InternalServer(Box::new(why)))
+}
+
+fn claim<'a>(userinfo: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(userinfo, |value, key| value.get(key))
+}
+
+// IDs are numbers on some providers and strings on others
+fn claim_string(userinfo: &Value, path: &str) -> Option<String> {
+    match claim(userinfo, path)? {
+        Value::String(string) => Some(string.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
 
-    Ok(Github {
-        github_id: user.id,
-        username: user.login,
-        profile_picture: user.avatar_url,
-        email: user.email,
+fn email_verified(userinfo: &Value, claims: &ClaimMapping) -> bool {
+    match claim(userinfo, &claims.email_verified) {
+        Some(Value::Bool(verified)) => *verified,
+        // some providers send it as a string
+        Some(Value::String(verified)) => verified == "true",
+        _ => false,
+    }
+}
+
+fn map_claims(provider: &str, config: &OAuth, userinfo: &Value) -> SResult<AuthProviderDataCommon> {
+    let claims = &config.claims;
+    let id = claim_string(userinfo, &claims.id).ok_or_else(|| {
+        ServerError::ISErr(Cow::from(format!(
+            "login provider {provider} didn't send a {}",
+            claims.id
+        )))
+    })?;
+    let email = if config.trust_email || email_verified(userinfo, claims) {
+        claim_string(userinfo, &claims.email)
+    } else {
+        None
+    };
+
+    Ok(AuthProviderDataCommon {
+        provider: provider.to_string(),
+        username: claim_string(userinfo, &claims.username).unwrap_or_else(|| id.clone()),
+        id,
+        profile_picture: claim_string(userinfo, &claims.picture).unwrap_or_default(),
+        email,
     })
 }
 
-impl_sea_orm!(
-    Twitter,
-    Github,
-    AuthorizationProviders,
-    AuthProviderDataCommon,
-    OAuthAttempt
-);
-impl_redis!(
-    Twitter,
-    Github,
-    AuthorizationProviders,
-    AuthProviderDataCommon,
-    OAuthAttempt
-);
+impl_sea_orm!(AuthProviderDataCommon, OAuthAttempt);
+impl_redis!(AuthProviderDataCommon => 1, OAuthAttempt => 1);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        headers::{authorization::Bearer, Authorization},
+        http::{header::LOCATION, StatusCode},
+        routing::{get, post},
+        Form, Json, Router, TypedHeader,
+    };
+    use serde_json::json;
+    use std::{collections::HashMap, net::TcpListener};
+
+    const CODE: &str = "mock-code";
+    const ACCESS_TOKEN: &str = "mock-access-token";
+
+    fn userinfo_claims() -> Value {
+        json!({
+            "sub": "248289761001",
+            "preferred_username": "kapi",
+            "picture": "https://provider.test/kapi.png",
+            "email": "kapi@provider.test",
+            "email_verified": true,
+        })
+    }
+
+    async fn token(Form(form): Form<HashMap<String, String>>) -> Result<Json<Value>, StatusCode> {
+        if form.get("grant_type").map(String::as_str) != Some("authorization_code")
+            || form.get("code").map(String::as_str) != Some(CODE)
+            || !form.contains_key("code_verifier")
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        Ok(Json(json!({
+            "access_token": ACCESS_TOKEN,
+            "token_type": "Bearer",
+            "expires_in": 3600,
+        })))
+    }
+
+    async fn userinfo_endpoint(
+        TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    ) -> Result<Json<Value>, StatusCode> {
+        if bearer.token() != ACCESS_TOKEN {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        Ok(Json(userinfo_claims()))
+    }
+
+    // an OpenID provider on localhost, `/bare` is one that doesn't say where its userinfo is
+    fn mock_provider() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let discovery = json!({
+            "issuer": base,
+            "authorization_endpoint": format!("{base}/authorize"),
+            "token_endpoint": format!("{base}/token"),
+            "userinfo_endpoint": format!("{base}/userinfo"),
+        });
+        let no_userinfo = json!({
+            "issuer": base,
+            "authorization_endpoint": format!("{base}/authorize"),
+            "token_endpoint": format!("{base}/token"),
+        });
+        let routes = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(move || async move { Json(discovery) }),
+            )
+            .route(
+                "/bare/.well-known/openid-configuration",
+                get(move || async move { Json(no_userinfo) }),
+            )
+            .route("/token", post(token))
+            .route(
+                "/moved/token",
+                post(|| async { (StatusCode::TEMPORARY_REDIRECT, [(LOCATION, "/token")]) }),
+            )
+            .route("/userinfo", get(userinfo_endpoint));
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(routes.into_make_service()),
+        );
+        base
+    }
+
+    fn config(discovery_url: Option<String>) -> OAuth {
+        OAuth {
+            client_id: "kindkapibari".to_string(),
+            secret: "hunter2".to_string(),
+            discovery_url,
+            authorize_url: None,
+            token_url: None,
+            userinfo_url: None,
+            scopes: vec!["openid".to_string()],
+            claims: ClaimMapping::default(),
+            trust_email: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn discovery_fills_in_the_endpoints() {
+        let base = mock_provider();
+        let config = config(Some(format!("{base}/.well-known/openid-configuration")));
+
+        assert_eq!(
+            discover_endpoints("mock", &config).await.unwrap(),
+            ProviderEndpoints {
+                authorize_url: format!("{base}/authorize"),
+                token_url: format!("{base}/token"),
+                userinfo_url: format!("{base}/userinfo"),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn configured_endpoints_win_over_discovery() {
+        let base = mock_provider();
+        let mut config = config(Some(format!("{base}/.well-known/openid-configuration")));
+        config.token_url = Some("https://elsewhere.test/token".to_string());
+
+        let endpoints = discover_endpoints("mock", &config).await.unwrap();
+        assert_eq!(endpoints.token_url, "https://elsewhere.test/token");
+        assert_eq!(endpoints.authorize_url, format!("{base}/authorize"));
+    }
+
+    #[tokio::test]
+    async fn discovery_needs_a_userinfo_endpoint() {
+        let base = mock_provider();
+        let mut config = config(Some(format!(
+            "{base}/bare/.well-known/openid-configuration"
+        )));
+        assert!(discover_endpoints("mock", &config).await.is_err());
+
+        config.userinfo_url = Some(format!("{base}/userinfo"));
+        assert_eq!(
+            discover_endpoints("mock", &config)
+                .await
+                .unwrap()
+                .userinfo_url,
+            format!("{base}/userinfo")
+        );
+    }
+
+    #[tokio::test]
+    async fn no_discovery_url_and_no_endpoints_is_refused() {
+        assert!(discover_endpoints("mock", &config(None)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn logs_in_through_the_provider() {
+        let base = mock_provider();
+        let config = config(Some(format!("{base}/.well-known/openid-configuration")));
+        let endpoints = discover_endpoints("mock", &config).await.unwrap();
+        let client = get_oauth_client(
+            &endpoints,
+            "https://kindkapibari.test/redirect".to_string(),
+            config.client_id.clone(),
+            config.secret.clone(),
+        )
+        .unwrap();
+        let attempt = start_attempt(&client, config.scopes.clone(), "mock", None);
+        assert!(attempt
+            .auth_url()
+            .starts_with(&format!("{base}/authorize?")));
+
+        let http = no_redirects().build().unwrap();
+        assert!(
+            exchange_code(&client, &http, &attempt, "wrong-code".to_string())
+                .await
+                .is_err()
+        );
+        let token = exchange_code(&client, &http, &attempt, CODE.to_string())
+            .await
+            .unwrap();
+        let claims = userinfo(&endpoints.userinfo_url, token.access_token().secret())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            map_claims("mock", &config, &claims).unwrap(),
+            AuthProviderDataCommon {
+                provider: "mock".to_string(),
+                id: "248289761001".to_string(),
+                username: "kapi".to_string(),
+                profile_picture: "https://provider.test/kapi.png".to_string(),
+                email: Some("kapi@provider.test".to_string()),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn token_endpoints_cant_redirect() {
+        let base = mock_provider();
+        let endpoints = ProviderEndpoints {
+            authorize_url: format!("{base}/authorize"),
+            token_url: format!("{base}/moved/token"),
+            userinfo_url: format!("{base}/userinfo"),
+        };
+        let client = get_oauth_client(
+            &endpoints,
+            "https://kindkapibari.test/redirect".to_string(),
+            "kindkapibari".to_string(),
+            "hunter2".to_string(),
+        )
+        .unwrap();
+        let attempt = start_attempt(&client, [], "mock", None);
+        let http = no_redirects().build().unwrap();
+
+        assert!(exchange_code(&client, &http, &attempt, CODE.to_string())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn userinfo_needs_the_access_token() {
+        let base = mock_provider();
+        assert!(userinfo(&format!("{base}/userinfo"), "stolen")
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn unverified_emails_are_dropped() {
+        let mut claims = userinfo_claims();
+        claims["email_verified"] = json!(false);
+        assert_eq!(
+            map_claims("mock", &config(None), &claims).unwrap().email,
+            None
+        );
+
+        claims.as_object_mut().unwrap().remove("email_verified");
+        assert_eq!(
+            map_claims("mock", &config(None), &claims).unwrap().email,
+            None
+        );
+    }
+
+    #[test]
+    fn email_verified_can_be_a_string() {
+        let mut claims = userinfo_claims();
+        claims["email_verified"] = json!("true");
+        assert!(email_verified(&claims, &ClaimMapping::default()));
+        claims["email_verified"] = json!("false");
+        assert!(!email_verified(&claims, &ClaimMapping::default()));
+    }
+
+    #[test]
+    fn trusted_providers_keep_unverified_emails() {
+        let mut claims = userinfo_claims();
+        claims.as_object_mut().unwrap().remove("email_verified");
+        let mut config = config(None);
+        config.trust_email = true;
+
+        assert_eq!(
+            map_claims("mock", &config, &claims).unwrap().email,
+            Some("kapi@provider.test".to_string())
+        );
+    }
+
+    #[test]
+    fn claims_follow_dotted_paths() {
+        // numeric IDs nested a level down, like some plain OAuth2 APIs
+        let claims = json!({ "data": { "id": 4_200_000, "login": "kapi" } });
+        let mut config = config(None);
+        config.claims.id = "data.id".to_string();
+        config.claims.username = "data.login".to_string();
+
+        let user = map_claims("mock", &config, &claims).unwrap();
+        assert_eq!(user.id, "4200000");
+        assert_eq!(user.username, "kapi");
+        assert_eq!(user.profile_picture, "");
+        assert_eq!(claim(&claims, "data.missing"), None);
+        assert_eq!(claim(&claims, "data.id.deeper"), None);
+    }
+
+    #[test]
+    fn a_missing_id_is_refused() {
+        let mut claims = userinfo_claims();
+        claims.as_object_mut().unwrap().remove("sub");
+        assert!(map_claims("mock", &config(None), &claims).is_err());
+    }
+
+    #[test]
+    fn username_falls_back_to_the_id() {
+        let claims = json!({ "sub": "248289761001" });
+        assert_eq!(
+            map_claims("mock", &config(None), &claims).unwrap().username,
+            "248289761001"
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use crate::State;
+use kindkapibari_core::{
+    oauth::OpenIdRequest,
+    secret::{create_id_token, IdTokenClaims, TokenClaims},
+    user_data::UserData,
+};
+use kindkapibari_schema::{
+    error::ServerError,
+    schema::users::{user, userdata},
+    SResult,
+};
+use sea_orm::EntityTrait;
+use std::{borrow::Cow, sync::Arc};
+use tracing::instrument;
+
+// the audience for id_tokens handed to our own frontends
+pub const FIRST_PARTY_CLIENT_ID: &str = "kindkapibari";
+
+#[must_use]
+pub fn id_token_claims(
+    issuer: &str,
+    audience: &str,
+    access: &TokenClaims,
+    user: &user::Model,
+    user_data: &UserData,
+) -> IdTokenClaims {
+    IdTokenClaims::new(issuer, audience, access)
+        .set_username(user.username.as_str())
+        .set_picture(user.profile_picture.clone())
+        .set_locale(user_data.locale.to_string())
+}
+
+// For third party apps that asked for `openid`, so `aud` is their client_id. Without `profile` all
+// they learn is who it is.
+#[instrument(skip(access, user))]
+pub async fn oauth_id_token(
+    state: Arc<State>,
+    openid: &OpenIdRequest,
+    application: u64,
+    access: &TokenClaims,
+    user: &user::Model,
+) -> SResult<String> {
+    let issuer = state.config.read().await.host_url.clone();
+    let audience = application.to_string();
+    let claims = if openid.profile {
+        let user_data = user_data_by_id(state.clone(), user.id).await?;
+        id_token_claims(&issuer, &audience, access, user, &user_data)
+    } else {
+        IdTokenClaims::new(issuer, audience, access)
+    }
+    .set_nonce(openid.nonce.clone());
+
+    let keyring = state.keyring.read().await;
+    create_id_token(&claims, &keyring).map_err(|why| ServerError::InternalServer(Box::new(why)))
+}
+
+#[instrument]
+pub async fn user_data_by_id(state: Arc<State>, id: u64) -> SResult<UserData> {
+    userdata::Entity::find_by_id(id)
+        .one(&state.database)
+        .await?
+        .map(userdata::Model::into_userdata)
+        .ok_or(ServerError::NotFound(
+            Cow::from("user data"),
+            Cow::from("id"),
+        ))
+}
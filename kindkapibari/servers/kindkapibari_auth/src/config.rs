@@ -1,6 +1,8 @@
 use color_eyre::eyre::Result;
+use kindkapibari_core::secret::{PrivateKey, PublicKey};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     fs::File,
     io::{Read, Write},
 };
@@ -14,7 +16,8 @@ pub struct Config {
     pub database: Database,
     pub host_url: String,
     pub other_urls: OtherServers,
-    pub signing_keys: SigningKeys,
+    // taken out when the keyring is built, so the private key isn't also sitting in `State`
+    pub signing_keys: Option<SigningKeys>,
     pub oauth: OAuthProviders,
 }
 
@@ -47,34 +50,96 @@ pub struct Database {
 pub struct OAuthProviders {
     pub default_time_seconds: usize,
     pub redirect_url: String,
-    pub twitter: OAuth,
-    pub github: OAuth,
+    // keyed by the name used in `/login_with/:provider`, so adding one is just config
+    #[serde(default)]
+    pub providers: HashMap<String, OAuth>,
 }
 
+// Any OpenID Connect provider only needs `discovery_url` and its client. Plain OAuth2 ones (github,
+// twitter) leave it out and set the endpoints and claims by hand, e.g. for github:
+//
+//   [oauth.providers.github]
+//   authorize_url = "https://github.com/login/oauth/authorize"
+//   token_url = "https://github.com/login/oauth/access_token"
+//   userinfo_url = "https://api.github.com/user"
+//   scopes = ["read:user", "user:email"]
+//   claims = { id = "id", username = "login", picture = "avatar_url" }
+//   trust_email = true
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct OAuth {
     pub client_id: String,
     pub secret: String,
-    pub authorize_url: String,
-    pub token_url: String,
+    // `{issuer}/.well-known/openid-configuration`, the endpoints below win over what it says
+    pub discovery_url: Option<String>,
+    pub authorize_url: Option<String>,
+    pub token_url: Option<String>,
+    pub userinfo_url: Option<String>,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub claims: ClaimMapping,
+    // Emails are how existing accounts get found, so by default one only counts if the provider
+    // says it's verified. Only for providers that never hand out unverified ones.
+    #[serde(default)]
+    pub trust_email: bool,
+}
+
+// where each field is in the userinfo response, `.` goes into nested objects (`data.id`)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClaimMapping {
+    pub id: String,
+    pub username: String,
+    pub picture: String,
+    pub email: String,
+    pub email_verified: String,
+}
+
+// the standard OIDC claims
+impl Default for ClaimMapping {
+    fn default() -> Self {
+        Self {
+            id: "sub".to_string(),
+            username: "preferred_username".to_string(),
+            picture: "picture".to_string(),
+            email: "email".to_string(),
+            email_verified: "email_verified".to_string(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SigningKeys {
-    pub oauth_key: String,
-    pub oauth_thirdparty_key: String,
-    pub login_key: String,
+    pub current: PrivateKey,
+    // keys we've rotated away from, kept until every token they signed has expired
+    #[serde(default)]
+    pub previous: Vec<PublicKey>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct OtherServers {
     pub api: String,
+    // the frontend page apps send users to, it asks the API what to show
+    #[serde(default = "default_consent_page")]
+    pub consent_page: String,
 }
 
 const fn default_port() -> u16 {
     3160
 }
 
+fn default_oidc_scopes() -> Vec<String> {
+    vec![
+        "openid".to_string(),
+        "profile".to_string(),
+        "email".to_string(),
+    ]
+}
+
+fn default_consent_page() -> String {
+    "https://kindkapibari.land/oauth/authorize".to_string()
+}
+
 const fn default_max_threads() -> u32 {
     4
 }
@@ -1,14 +1,20 @@
 use crate::{
     access::{
+        fediverse::fediverse_login,
         login::{refresh_user_login_token, verify_user_login_token},
-        oauth_thirdparty::{oauth_login_github, oauth_login_twitter, OAuthAttempt},
+        oauth_thirdparty::{oauth_login, OAuthAttempt},
     },
     State,
 };
-use axum::{extract::Query, routing::post, Extension, Json};
+use axum::{
+    extract::{Path, Query},
+    routing::{get, post},
+    Extension, Json,
+};
 use kindkapibari_core::{
     route,
     secret::{JWTPair, SentSecret},
+    session::SessionClient,
 };
 use kindkapibari_schema::{
     error::ServerError,
@@ -16,50 +22,94 @@ use kindkapibari_schema::{
     schema::users::user::Model,
     SResult,
 };
+use serde::Deserialize;
 use std::{borrow::Cow, sync::Arc};
 use tracing::instrument;
+use utoipa::Component;
 
 #[instrument]
 #[utoipa::path(
-    post,
-    path = "/login_with_twitter",
+    get,
+    path = "/login_providers",
     responses(
-    (status = 200, description = "Twitter Url Sucessfully Generated", body = String),
-    (status = 500, description = "Failed")
+    (status = 200, description = "Names of the configured login providers", body = [String])
 ))]
-pub async fn login_with_twitter(Extension(app): Extension<Arc<State>>) -> SResult<String> {
-    let oauth = oauth_login_twitter(app.clone(), app.config.read().await.host_url.as_str()).await?;
+pub async fn login_providers(Extension(app): Extension<Arc<State>>) -> Json<Vec<String>> {
+    let mut providers = app
+        .config
+        .read()
+        .await
+        .oauth
+        .providers
+        .keys()
+        .cloned()
+        .collect::<Vec<String>>();
+    providers.sort();
+    Json(providers)
+}
+
+#[instrument]
+#[utoipa::path(
+    post,
+    path = "/login_with/{provider}",
+    responses(
+    (status = 200, description = "Provider Url Sucessfully Generated", body = String),
+    (status = 404, description = "No Such Provider"),
+    (status = 500, description = "Failed")),
+    params(
+    ("provider" = String, path, description = "Login provider, one of /login_providers")
+    )
+)]
+pub async fn login_with(
+    Extension(app): Extension<Arc<State>>,
+    Path(provider): Path<String>,
+) -> SResult<String> {
+    let oauth = oauth_login(app.clone(), &provider).await?;
     let redirect = oauth.auth_url().to_string();
-    let pkce = oauth.pkce_verifier().to_string();
+    // the provider hands the CSRF token back as `state`, that's how we find this again
+    let csrf = oauth.csrf_token().to_string();
 
-    if check_if_exists_cache::<&str, OAuthAttempt>(app.clone(), oauth.pkce_verifier()).await {
+    if check_if_exists_cache::<&str, OAuthAttempt>(app.clone(), oauth.csrf_token()).await {
         return Err(ServerError::ISErr(Cow::Borrowed(
             "ID already exists, please try again!",
         )));
     }
-    insert_into_cache(app, pkce, oauth, Some(1000)).await?;
+    insert_into_cache(app, csrf, &oauth, Some(1000)).await?;
     Ok(redirect)
 }
 
+#[derive(Clone, Debug, Deserialize, Component)]
+pub struct FediverseLogin {
+    // a domain like `mastodon.social`, a handle on it works too
+    pub instance: String,
+}
+
 #[instrument]
 #[utoipa::path(
     post,
-    path = "/login_with_github",
+    path = "/login_with_fediverse",
     responses(
-    (status = 200, description = "Github Url Sucessfully Generated", body = String),
-    (status = 500, description = "Failed")
-))]
-pub async fn login_with_github(Extension(app): Extension<Arc<State>>) -> SResult<String> {
-    let oauth = oauth_login_github(app.clone(), app.config.read().await.host_url.as_str()).await?;
+    (status = 200, description = "Instance Url Sucessfully Generated", body = String),
+    (status = 400, description = "Bad Instance/Instance Unreachable"),
+    (status = 500, description = "Failed")),
+    params(
+    ("instance" = String, query, description = "The user's instance, e.g. mastodon.social")
+    )
+)]
+pub async fn login_with_fediverse(
+    Extension(app): Extension<Arc<State>>,
+    Query(login): Query<FediverseLogin>,
+) -> SResult<String> {
+    let oauth = fediverse_login(app.clone(), &login.instance).await?;
     let redirect = oauth.auth_url().to_string();
-    let pkce = oauth.pkce_verifier().to_string();
+    let csrf = oauth.csrf_token().to_string();
 
-    if check_if_exists_cache::<&str, OAuthAttempt>(app.clone(), oauth.pkce_verifier()).await {
+    if check_if_exists_cache::<&str, OAuthAttempt>(app.clone(), oauth.csrf_token()).await {
         return Err(ServerError::ISErr(Cow::Borrowed(
             "ID already exists, please try again!",
         )));
     }
-    insert_into_cache(app, pkce, oauth, Some(1000)).await?;
+    insert_into_cache(app, csrf, &oauth, Some(1000)).await?;
     Ok(redirect)
 }
 
@@ -104,15 +154,17 @@ pub async fn refresh_token(
     Extension(app): Extension<Arc<State>>,
     access: Query<String>,
     refresh: Query<String>,
+    client: SessionClient,
 ) -> SResult<Json<JWTPair>> {
-    refresh_user_login_token(app, access.0, refresh.0)
+    refresh_user_login_token(app, access.0, refresh.0, client)
         .await
         .map(|x| Json(x))
 }
 
 route! {
-    "/login_with_twitter" => post(login_with_twitter),
-    "/login_with_github" => post(login_with_github),
+    "/login_providers" => get(login_providers),
+    "/login_with/:provider" => post(login_with),
+    "/login_with_fediverse" => post(login_with_fediverse),
     "/verify_login_token" => post(verify_login_token),
     "/refresh_token" => post(refresh_token)
 }
@@ -1,9 +1,13 @@
 use kindkapibari_core::route;
 
 pub mod login;
+pub mod oauth;
 pub mod signup;
+pub mod well_known;
 
-route!{ 
+route! {
     "/login" => login,
-    "/signup" => signup
-}
\ No newline at end of file
+    "/oauth" => oauth,
+    "/signup" => signup,
+    "/.well-known" => well_known
+}
@@ -0,0 +1,143 @@
+use crate::{
+    access::oauth::{
+        authenticate_client, client_credentials_token, exchange_authorization_code,
+        refresh_oauth_token, revoke_oauth_token, ClientCredentials, OAuthError,
+    },
+    State,
+};
+use axum::{
+    headers::{authorization::Basic, Authorization},
+    http::{
+        header::{CACHE_CONTROL, PRAGMA},
+        HeaderName,
+    },
+    routing::post,
+    Extension, Form, Json, TypedHeader,
+};
+use kindkapibari_core::{oauth::TokenResponse, route, session::SessionClient};
+use serde::Deserialize;
+use std::{borrow::Cow, sync::Arc};
+use tracing::instrument;
+use utoipa::Component;
+
+// RFC 6749 5.1, nothing in between gets to keep these
+type NoStore<T> = ([(HeaderName, &'static str); 2], Json<T>);
+
+// no Debug on these, they carry secrets
+#[derive(Clone, Deserialize, Component)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Component)]
+pub struct RevokeRequest {
+    pub token: String,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+// HTTP Basic wins over the form fields if both are there
+fn client_credentials(
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+) -> Result<ClientCredentials, OAuthError> {
+    match basic {
+        Some(TypedHeader(Authorization(basic))) => Ok(ClientCredentials {
+            id: basic.username().to_string(),
+            secret: Some(basic.password().to_string()),
+        }),
+        None => Ok(ClientCredentials {
+            id: client_id.ok_or(OAuthError::InvalidClient)?,
+            secret: client_secret,
+        }),
+    }
+}
+
+fn missing(field: &'static str) -> OAuthError {
+    OAuthError::InvalidRequest(Cow::from(format!("missing {field}")))
+}
+
+#[instrument(skip(basic, request))]
+#[utoipa::path(
+    post,
+    path = "/oauth/token",
+    request_body(content = TokenRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+    (status = 200, description = "Tokens for the grant", body = TokenResponse),
+    (status = 400, description = "Bad Request/Grant/Scope"),
+    (status = 401, description = "Client Authentication Failed"),
+    (status = 500, description = "Failed")
+))]
+pub async fn token(
+    Extension(app): Extension<Arc<State>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    client: SessionClient,
+    Form(request): Form<TokenRequest>,
+) -> Result<NoStore<TokenResponse>, OAuthError> {
+    let credentials = client_credentials(basic, request.client_id, request.client_secret)?;
+    let application = authenticate_client(app.clone(), credentials).await?;
+
+    let response = match request.grant_type.as_str() {
+        "authorization_code" => {
+            let code = request.code.ok_or_else(|| missing("code"))?;
+            // RFC 6749 4.1.3, we always send one back to the app so it always has to say which
+            let redirect_uri = request
+                .redirect_uri
+                .ok_or_else(|| missing("redirect_uri"))?;
+            let verifier = request
+                .code_verifier
+                .ok_or_else(|| missing("code_verifier"))?;
+            exchange_authorization_code(app, application, &code, &redirect_uri, &verifier, client)
+                .await?
+        }
+        "refresh_token" => {
+            let refresh = request
+                .refresh_token
+                .ok_or_else(|| missing("refresh_token"))?;
+            refresh_oauth_token(app, application, &refresh, request.scope.as_deref(), client)
+                .await?
+        }
+        "client_credentials" => {
+            client_credentials_token(app, application, request.scope.as_deref()).await?
+        }
+        _ => return Err(OAuthError::UnsupportedGrantType),
+    };
+
+    Ok((
+        [(CACHE_CONTROL, "no-store"), (PRAGMA, "no-cache")],
+        Json(response),
+    ))
+}
+
+#[instrument(skip(basic, request))]
+#[utoipa::path(
+    post,
+    path = "/oauth/revoke",
+    request_body(content = RevokeRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+    (status = 200, description = "Revoked, or was never valid to begin with"),
+    (status = 401, description = "Client Authentication Failed"),
+    (status = 500, description = "Failed")
+))]
+pub async fn revoke(
+    Extension(app): Extension<Arc<State>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    Form(request): Form<RevokeRequest>,
+) -> Result<(), OAuthError> {
+    let credentials = client_credentials(basic, request.client_id, request.client_secret)?;
+    let application = authenticate_client(app.clone(), credentials).await?;
+    revoke_oauth_token(app, application, &request.token).await
+}
+
+route! {
+    "/token" => post(token),
+    "/revoke" => post(revoke)
+}
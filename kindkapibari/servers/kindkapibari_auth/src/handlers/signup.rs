@@ -1,10 +1,11 @@
 use crate::{
     access::{
+        fediverse::FEDIVERSE_PROVIDER,
         login::{
             detect_user_already_exists_auth_provider, generate_login_token, user_by_email,
             user_by_id, user_by_username,
         },
-        oauth_thirdparty::{get_oauth_client, get_user_data, AuthProviderDataCommon, OAuthAttempt},
+        oauth_thirdparty::{get_user_data, AuthProviderDataCommon, OAuthAttempt},
     },
     State,
 };
@@ -14,14 +15,15 @@ use axum::{
     Extension, Json,
 };
 use chrono::Utc;
-use kindkapibari_core::{roles::Role, route, secret::JWTPair, user_data::UserSignupRequest};
+use kindkapibari_core::{
+    roles::Role, route, secret::JWTPair, session::SessionClient, user_data::UserSignupRequest,
+};
 use kindkapibari_schema::{
     error::ServerError,
     redis::{check_if_exists_cache, delet_dis, insert_into_cache, read_from_cache},
-    schema::users::{user, userdata},
+    schema::users::{connections, identities, user, userdata},
     SResult,
 };
-use oauth2::{reqwest::async_http_client, AuthorizationCode, PkceCodeVerifier};
 use sea_orm::{ActiveValue, EntityTrait};
 use serde::{Deserialize, Serialize};
 use std::{borrow::Cow, sync::Arc};
@@ -53,50 +55,21 @@ pub struct StateAndCode {
 pub async fn redirect(
     Extension(app): Extension<Arc<State>>,
     state_and_code: Query<StateAndCode>,
+    client: SessionClient,
 ) -> SResult<Json<RedirectedUser>> {
     let oauth_attempt = read_from_cache::<OAuthAttempt>(app.clone(), &state_and_code.state).await?;
-    let config = app.config.read().await.clone();
-    if state_and_code.state != oauth_attempt.pkce_verifier() {
+    if state_and_code.state != oauth_attempt.csrf_token() {
         return Err(ServerError::BadRequest(Cow::Borrowed("Bad State")));
     }
 
-    let client_rebuild = match oauth_attempt.authorizer() {
-        "twitter" => get_oauth_client(
-            config.oauth.twitter.authorize_url,
-            config.oauth.twitter.token_url,
-            format!("{}/redirect", config.host_url),
-            config.oauth.twitter.client_id,
-            config.oauth.twitter.secret,
-        )
-        .map_err(|why| ServerError::InternalServer(why))?,
-        "github" => get_oauth_client(
-            config.oauth.github.authorize_url,
-            config.oauth.github.token_url,
-            format!("{}/redirect", config.host_url),
-            config.oauth.github.client_id,
-            config.oauth.github.secret,
-        )
-        .map_err(|why| ServerError::InternalServer(why))?,
-        _ => return Err(ServerError::BadRequest(Cow::Borrowed("Bad Authorizer"))),
-    };
-
-    let token_result = client_rebuild
-        .exchange_code(AuthorizationCode::new(state_and_code.code.clone()))
-        .set_pkce_verifier(PkceCodeVerifier::new(
-            oauth_attempt.pkce_verifier().to_string(),
-        ))
-        .request_async(async_http_client)
-        .await
-        .map_err(|why| ServerError::InternalServer(why.into()))?;
-
-    let user_info = get_user_data(oauth_attempt.authorizer(), token_result).await?;
+    let user_info_common =
+        get_user_data(app.clone(), &oauth_attempt, state_and_code.code.clone()).await?;
     let maybe_existing_user =
-        detect_user_already_exists_auth_provider(app.clone(), user_info.clone()).await?;
-    let user_info_common: AuthProviderDataCommon = user_info.into();
+        detect_user_already_exists_auth_provider(app.clone(), &user_info_common).await?;
     Ok(Json(match maybe_existing_user {
-        Some(existing) => {
-            RedirectedUser::AlreadyExists(generate_login_token(app.clone(), existing).await?)
-        }
+        Some(existing) => RedirectedUser::AlreadyExists(
+            generate_login_token(app.clone(), existing, client).await?,
+        ),
         None => {
             // in this case we create a "slip" that the user can trade for not making this request again
             let user_info_num = format!(
@@ -193,6 +166,7 @@ pub struct PostSignupSent {
 pub async fn signup(
     Extension(state): Extension<Arc<State>>,
     request: Query<String>,
+    client: SessionClient,
     data: Json<UserSignupRequest>,
 ) -> SResult<Json<PostSignupSent>> {
     if !(data.username.len() > 30
@@ -206,24 +180,25 @@ pub async fn signup(
     let oauth_data = read_from_cache::<AuthProviderDataCommon>(state.clone(), &request.0).await?;
     let user_data = data.0;
 
-    let user_id = state.id_generator.user_ids.generate_id();
+    let user_id = state.id_generator.user_ids.generate_id()?;
 
     if user_by_id(state.clone(), user_id).await.is_ok() {
         return Err(ServerError::ISErr(Cow::from("please retry")));
     }
 
-    let oauth_email = match oauth_data.email.as_ref() {
-        Some(e) => e.clone(),
-        None => "".to_string(),
+    // The fediverse never tells us an email and nobody checked the one typed in, so those accounts
+    // go without. Holding on to it would lock whoever really owns it out of signing up.
+    let oauth_email = match oauth_data.email.clone() {
+        Some(email) => {
+            if user_data.email != email || user_by_email(state.clone(), &email).await?.is_some() {
+                return Err(ServerError::BadRequest(Cow::from("email")));
+            }
+            Some(email)
+        }
+        None if oauth_data.provider == FEDIVERSE_PROVIDER => None,
+        None => return Err(ServerError::BadRequest(Cow::from("email"))),
     };
 
-    if oauth_data.email.is_none()
-        || user_data.email != oauth_email
-        || user_by_email(state.clone(), &oauth_email).await?.is_some()
-    {
-        return Err(ServerError::BadRequest(Cow::from("email")));
-    }
-
     if user_by_username(state.clone(), &user_data.username)
         .await?
         .is_some()
@@ -234,7 +209,7 @@ pub async fn signup(
     let user_active_model = user::ActiveModel {
         id: ActiveValue::Set(user_id),
         username: ActiveValue::Set(user_data.username),
-        email: ActiveValue::Set(oauth_data.email.unwrap()),
+        email: ActiveValue::Set(oauth_email),
         profile_picture: ActiveValue::Set(Some(oauth_data.profile_picture)),
         creation_date: ActiveValue::Set(Utc::now()),
         roles: ActiveValue::Set(Role::NormalUser),
@@ -246,6 +221,8 @@ pub async fn signup(
         pronouns: ActiveValue::Set(user_data.other_data.pronouns),
         birthday: ActiveValue::Set(user_data.other_data.birthday),
         locale: ActiveValue::Set(user_data.other_data.locale),
+        timezone: ActiveValue::Set(user_data.other_data.timezone),
+        any_pronouns: ActiveValue::Set(user_data.other_data.any_pronouns.into()),
     };
 
     user::Entity::insert(user_active_model)
@@ -254,8 +231,28 @@ pub async fn signup(
     userdata::Entity::insert(user_data_active_model)
         .exec(&state.database)
         .await?;
+    if oauth_data.provider == FEDIVERSE_PROVIDER {
+        connections::Entity::insert(connections::ActiveModel {
+            user_id: ActiveValue::Set(user_id),
+            github_id: ActiveValue::Set(None),
+            twitter_id: ActiveValue::Set(None),
+            reddit_id: ActiveValue::Set(None),
+            fediverse_id: ActiveValue::Set(Some(oauth_data.id)),
+        })
+        .exec(&state.database)
+        .await?;
+    } else {
+        identities::Entity::insert(identities::ActiveModel {
+            provider: ActiveValue::Set(oauth_data.provider),
+            subject: ActiveValue::Set(oauth_data.id),
+            user_id: ActiveValue::Set(user_id),
+            linked: ActiveValue::Set(Utc::now()),
+        })
+        .exec(&state.database)
+        .await?;
+    }
 
-    let login_generated = generate_login_token(state.clone(), user_id).await?;
+    let login_generated = generate_login_token(state.clone(), user_id, client).await?;
 
     Ok(Json(PostSignupSent {
         id: user_id,
@@ -0,0 +1,117 @@
+use crate::State;
+use axum::{routing::get, Extension, Json};
+use kindkapibari_core::{oauth::CODE_CHALLENGE_METHOD, route, scopes::KKBScope, secret::Jwks};
+use kindkapibari_schema::{error::ServerError, SResult};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::instrument;
+use utoipa::Component;
+
+const CLAIMS_SUPPORTED: [&str; 9] = [
+    "iss",
+    "sub",
+    "aud",
+    "exp",
+    "iat",
+    "nonce",
+    "preferred_username",
+    "picture",
+    "locale",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct OpenIdConfiguration {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub revocation_endpoint: String,
+    pub jwks_uri: String,
+    pub response_types_supported: Vec<String>,
+    pub grant_types_supported: Vec<String>,
+    pub code_challenge_methods_supported: Vec<String>,
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    pub subject_types_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    pub scopes_supported: Vec<String>,
+    pub claims_supported: Vec<String>,
+}
+
+#[instrument]
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    responses(
+    (status = 200, description = "Public keys tokens are signed with", body = Jwks),
+    (status = 500, description = "Failed")
+))]
+pub async fn jwks(Extension(app): Extension<Arc<State>>) -> SResult<Json<Jwks>> {
+    let jwks = app
+        .keyring
+        .read()
+        .await
+        .verifying()
+        .jwks()
+        .map_err(|why| ServerError::InternalServer(Box::new(why)))?;
+    Ok(Json(jwks))
+}
+
+#[instrument]
+#[utoipa::path(
+    get,
+    path = "/.well-known/openid-configuration",
+    responses(
+    (status = 200, description = "OpenID Connect discovery document", body = OpenIdConfiguration),
+))]
+pub async fn openid_configuration(
+    Extension(app): Extension<Arc<State>>,
+) -> Json<OpenIdConfiguration> {
+    let (issuer, consent_page) = {
+        let config = app.config.read().await;
+        (
+            config.host_url.clone(),
+            config.other_urls.consent_page.clone(),
+        )
+    };
+    let algorithms = app
+        .keyring
+        .read()
+        .await
+        .verifying()
+        .algorithms()
+        .into_iter()
+        .map(|algorithm| algorithm.name().to_string())
+        .collect();
+
+    let mut scopes_supported = vec!["openid".to_string(), "profile".to_string()];
+    scopes_supported.extend(KKBScope::ALL.iter().map(KKBScope::scope_name));
+
+    Json(OpenIdConfiguration {
+        // third party apps go through our consent page, our own logins still start under /login
+        authorization_endpoint: consent_page,
+        token_endpoint: format!("{issuer}/oauth/token"),
+        revocation_endpoint: format!("{issuer}/oauth/revoke"),
+        jwks_uri: format!("{issuer}/.well-known/jwks.json"),
+        response_types_supported: vec!["code".to_string()],
+        grant_types_supported: vec![
+            "authorization_code".to_string(),
+            "refresh_token".to_string(),
+            "client_credentials".to_string(),
+        ],
+        code_challenge_methods_supported: vec![CODE_CHALLENGE_METHOD.to_string()],
+        token_endpoint_auth_methods_supported: vec![
+            "client_secret_basic".to_string(),
+            "client_secret_post".to_string(),
+            "none".to_string(),
+        ],
+        subject_types_supported: vec!["public".to_string()],
+        id_token_signing_alg_values_supported: algorithms,
+        scopes_supported,
+        claims_supported: CLAIMS_SUPPORTED.iter().map(ToString::to_string).collect(),
+        issuer,
+    })
+}
+
+route! {
+    "/jwks.json" => get(jwks),
+    "/openid-configuration" => get(openid_configuration)
+}
@@ -3,17 +3,28 @@
 #![warn(clippy::perf)]
 #![allow(clippy::missing_errors_doc)]
 
-use crate::{config::Config, handlers::signup::PostSignupSent};
+use crate::{
+    access::oauth_thirdparty::ProviderEndpoints,
+    config::Config,
+    handlers::{
+        login::FediverseLogin,
+        oauth::{RevokeRequest, TokenRequest},
+        signup::PostSignupSent,
+        well_known::OpenIdConfiguration,
+    },
+};
 use kindkapibari_core::{
     gender::Gender,
     make_caches,
-    pronouns::{PronounProfile, Pronouns},
+    oauth::TokenResponse,
+    pronouns::{PronounForms, PronounProfile, Pronouns},
     roles::Role,
-    secret::JWTPair,
+    secret::{IdTokenClaims, JWTPair, Jwk, Jwks, SigningKeyring},
     snowflake::SnowflakeIdGenerator,
-    user_data::{Locale, UserData, UserSignupRequest},
+    user_data::{Locale, Timezone, UserData, UserSignupRequest},
 };
 use kindkapibari_schema::{redis::RedisState, schema::users::user::Model};
+use moka::future::Cache;
 use redis::{
     aio::{ConnectionLike, ConnectionManager},
     Client,
@@ -25,6 +36,8 @@ use std::{
     io::Write,
     net::SocketAddr,
     ops::{Deref, DerefMut},
+    sync::Arc,
+    time::Duration,
 };
 use tokio::sync::RwLock;
 use utoipa::OpenApi;
@@ -65,6 +78,7 @@ pub struct State {
     pub config: RwLock<Config>,
     pub caches: Caches,
     pub id_generator: IdGenerators,
+    pub keyring: RwLock<SigningKeyring>,
 }
 
 impl RedisState for State {
@@ -79,10 +93,28 @@ pub struct IdGenerators {
     pub redirect_ids: SnowflakeIdGenerator,
     pub login_token_ids: SnowflakeIdGenerator,
     pub refresh_token_ids: SnowflakeIdGenerator,
+    pub authorization_ids: SnowflakeIdGenerator,
 }
 
 make_caches! {
-    users: u64: user::Model
+    users: u64: user::Model,
+    providers: String: Arc<ProviderEndpoints>
+}
+
+const CACHE_CAPACITY: u64 = 10_000;
+// how long a provider's discovery document is trusted before we fetch it again
+const DISCOVERY_TTL: Duration = Duration::from_secs(60 * 60);
+
+impl Caches {
+    fn new() -> Self {
+        Self {
+            users_cache: Cache::new(CACHE_CAPACITY),
+            providers_cache: Cache::builder()
+                .max_capacity(CACHE_CAPACITY)
+                .time_to_live(DISCOVERY_TTL)
+                .build(),
+        }
+    }
 }
 
 #[tokio::main]
@@ -90,22 +122,37 @@ async fn main() {
     #[derive(OpenApi)]
     #[openapi(
         handlers (
-            handlers::login::login_with_twitter,
-            handlers::login::login_with_github,
+            handlers::login::login_providers,
+            handlers::login::login_with,
+            handlers::login::login_with_fediverse,
             handlers::login::verify_login_token,
+            handlers::oauth::token,
+            handlers::oauth::revoke,
             handlers::signup::burn_signup_token,
-            handlers::signup::signup
+            handlers::signup::signup,
+            handlers::well_known::jwks,
+            handlers::well_known::openid_configuration
         ),
         components(
             Model,
             JWTPair,
+            IdTokenClaims,
+            Jwk,
+            Jwks,
+            OpenIdConfiguration,
+            TokenRequest,
+            RevokeRequest,
+            TokenResponse,
+            FediverseLogin,
             UserSignupRequest,
             PostSignupSent,
             UserData,
             Pronouns,
             PronounProfile,
+            PronounForms,
             Gender,
             Locale,
+            Timezone,
             Role,
         ),
         tags (
@@ -128,7 +175,14 @@ async fn main() {
 
     // FIXME: instantiate State
 
-    let config = Config::load().expect("Failed to read config");
+    let mut config = Config::load().expect("Failed to read config");
+    let signing_keys = config
+        .signing_keys
+        .take()
+        .expect("No signing keys in config");
+    let keyring = SigningKeyring::new(signing_keys.current, &signing_keys.previous)
+        .expect("Failed to load signing keys");
+    let caches = Caches::new();
     let database: DatabaseConnection = Database::connect(&config.database.postgres_url)
         .await
         .expect("Failed to connect to PostgreSQL");
@@ -141,7 +195,7 @@ async fn main() {
     let routes = handlers::routes();
 
     axum::Server::bind(&addr)
-        .serve(routes.into_make_service())
+        .serve(routes.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }